@@ -0,0 +1,472 @@
+//! Verified random access to BLAKE3 hashed files with Bao outboards.
+//!
+//! A Bao outboard contains the 8 byte little-endian content length followed by
+//! the parent nodes of the BLAKE3 tree in pre-order. Each parent node consists
+//! of the chaining values of its left and right subtree.
+use crate::buffered_range_client::unexpected_end;
+use crate::error::{HttpError, Result};
+use crate::range_client::{AsyncHttpRangeClient, SyncHttpRangeClient};
+use crate::{AsyncBufferedHttpRangeClient, SyncBufferedHttpRangeClient};
+use blake3::hazmat::{
+    left_subtree_len, merge_subtrees_non_root, merge_subtrees_root, ChainingValue, HasherExt, Mode,
+};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+const CHUNK_LEN: u64 = 1024;
+const HEADER_LEN: u64 = 8;
+const PARENT_LEN: u64 = 64;
+/// Maximal gap between parent nodes fetched with a single request
+const MAX_PARENT_GAP: usize = 1024;
+
+fn chunk_count(len: u64) -> u64 {
+    len.div_ceil(CHUNK_LEN).max(1)
+}
+
+/// Subtree of the BLAKE3 tree
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    /// Content offset
+    start: u64,
+    /// Content length
+    len: u64,
+    /// Outboard offset of the parent node
+    offset: u64,
+    root: bool,
+}
+
+impl Node {
+    fn root(len: u64) -> Self {
+        Node {
+            start: 0,
+            len,
+            offset: HEADER_LEN,
+            root: true,
+        }
+    }
+
+    fn is_leaf(&self) -> bool {
+        self.len <= CHUNK_LEN
+    }
+
+    fn children(&self) -> (Node, Node) {
+        let left_len = left_subtree_len(self.len);
+        let left = Node {
+            start: self.start,
+            len: left_len,
+            offset: self.offset + PARENT_LEN,
+            root: false,
+        };
+        let right = Node {
+            start: self.start + left_len,
+            len: self.len - left_len,
+            offset: left.offset + (chunk_count(left_len) - 1) * PARENT_LEN,
+            root: false,
+        };
+        (left, right)
+    }
+
+    fn overlaps(&self, begin: u64, end: u64) -> bool {
+        self.start < end && begin < self.start + self.len
+    }
+
+    /// Outboard offsets of the parent nodes needed to verify `begin..end`
+    fn parents(&self, begin: u64, end: u64, offsets: &mut Vec<u64>) {
+        if self.is_leaf() {
+            return;
+        }
+        offsets.push(self.offset);
+        let (left, right) = self.children();
+        for child in [left, right] {
+            if child.overlaps(begin, end) {
+                child.parents(begin, end, offsets);
+            }
+        }
+    }
+}
+
+fn tampered(what: &str, node: &Node) -> HttpError {
+    HttpError::IntegrityError(format!(
+        "BLAKE3 {what} mismatch at offset {} (length {})",
+        node.start, node.len
+    ))
+}
+
+/// Chunks and parent nodes required for verifying a content range
+struct Proof {
+    begin: u64,
+    end: u64,
+    /// Parent node offsets in the outboard
+    parents: Vec<u64>,
+    /// Chunk aligned content range
+    chunk_begin: u64,
+    chunk_end: u64,
+}
+
+impl Proof {
+    fn new(len: u64, begin: u64, end: u64) -> Self {
+        let mut parents = Vec::new();
+        Node::root(len).parents(begin, end, &mut parents);
+        Proof {
+            begin,
+            end,
+            parents,
+            chunk_begin: begin - begin % CHUNK_LEN,
+            chunk_end: end.div_ceil(CHUNK_LEN).saturating_mul(CHUNK_LEN).min(len),
+        }
+    }
+
+    /// `(offset, length)` ranges of the parent nodes
+    fn parent_ranges(&self) -> Vec<(usize, usize)> {
+        self.parents
+            .iter()
+            .map(|&offset| (offset as usize, PARENT_LEN as usize))
+            .collect()
+    }
+
+    /// Verify `chunks` of the chunk aligned range against the root hash.
+    fn verify(
+        &self,
+        len: u64,
+        root: &blake3::Hash,
+        parents: &[Bytes],
+        chunks: &[u8],
+    ) -> Result<()> {
+        let mut nodes = HashMap::new();
+        for (&offset, parent) in self.parents.iter().zip(parents) {
+            if parent.len() < PARENT_LEN as usize {
+                return Err(unexpected_end(PARENT_LEN as usize));
+            }
+            nodes.insert(offset, parent);
+        }
+        let verifier = Verifier {
+            proof: self,
+            nodes,
+            chunks,
+        };
+        verifier.check(Node::root(len), root.as_bytes())
+    }
+}
+
+struct Verifier<'a> {
+    proof: &'a Proof,
+    nodes: HashMap<u64, &'a Bytes>,
+    chunks: &'a [u8],
+}
+
+impl Verifier<'_> {
+    fn check(&self, node: Node, expected: &ChainingValue) -> Result<()> {
+        if node.is_leaf() {
+            let from = (node.start - self.proof.chunk_begin) as usize;
+            let chunk = self
+                .chunks
+                .get(from..from + node.len as usize)
+                .ok_or_else(|| unexpected_end(node.len as usize))?;
+            let cv = if node.root {
+                *blake3::hash(chunk).as_bytes()
+            } else {
+                blake3::Hasher::new()
+                    .set_input_offset(node.start)
+                    .update(chunk)
+                    .finalize_non_root()
+            };
+            return if cv == *expected {
+                Ok(())
+            } else {
+                Err(tampered("chunk", &node))
+            };
+        }
+        let parent = self.nodes[&node.offset];
+        let left: ChainingValue = parent[..32].try_into().expect("32 byte slice");
+        let right: ChainingValue = parent[32..64].try_into().expect("32 byte slice");
+        let cv = if node.root {
+            *merge_subtrees_root(&left, &right, Mode::Hash).as_bytes()
+        } else {
+            merge_subtrees_non_root(&left, &right, Mode::Hash)
+        };
+        if cv != *expected {
+            return Err(tampered("parent node", &node));
+        }
+        let (left_node, right_node) = node.children();
+        if left_node.overlaps(self.proof.begin, self.proof.end) {
+            self.check(left_node, &left)?;
+        }
+        if right_node.overlaps(self.proof.begin, self.proof.end) {
+            self.check(right_node, &right)?;
+        }
+        Ok(())
+    }
+}
+
+/// Content range `begin..end` of a request, clamped to the content length
+fn request_range(len: u64, begin: usize, length: usize) -> Result<(u64, u64)> {
+    let begin = begin as u64;
+    if begin >= len && length > 0 {
+        return Err(HttpError::HttpStatus(416));
+    }
+    Ok((begin, (begin + length as u64).min(len)))
+}
+
+fn parse_length(header: &[u8]) -> Result<u64> {
+    let header: [u8; 8] = header
+        .try_into()
+        .map_err(|_| unexpected_end(HEADER_LEN as usize))?;
+    Ok(u64::from_le_bytes(header))
+}
+
+/// Range reader verifying all data against a BLAKE3 root hash
+///
+/// Data is read from the file, the tree nodes required for verification from
+/// its Bao outboard. Ranges are extended to 1 KiB chunk boundaries.
+pub struct AsyncVerifiedHttpRangeClient<T: AsyncHttpRangeClient> {
+    data: AsyncBufferedHttpRangeClient<T>,
+    outboard: AsyncBufferedHttpRangeClient<T>,
+    root: blake3::Hash,
+    length: Option<u64>,
+}
+
+impl<T: AsyncHttpRangeClient> AsyncVerifiedHttpRangeClient<T> {
+    pub fn new(
+        data: AsyncBufferedHttpRangeClient<T>,
+        outboard: AsyncBufferedHttpRangeClient<T>,
+        root: blake3::Hash,
+    ) -> Self {
+        AsyncVerifiedHttpRangeClient {
+            data,
+            outboard,
+            root,
+            length: None,
+        }
+    }
+
+    /// Content length from the outboard header.
+    ///
+    /// The length is authenticated by verifying the final chunk.
+    pub async fn content_length(&mut self) -> Result<u64> {
+        if let Some(length) = self.length {
+            return Ok(length);
+        }
+        let length = parse_length(self.outboard.get_range(0, HEADER_LEN as usize).await?)?;
+        let last = (chunk_count(length) - 1) * CHUNK_LEN;
+        self.verify(length, last, length.max(last + 1)).await?;
+        self.length = Some(length);
+        Ok(length)
+    }
+
+    /// Get verified bytes of the given range.
+    pub async fn get_range(&mut self, begin: usize, length: usize) -> Result<&[u8]> {
+        let len = self.content_length().await?;
+        let (begin, end) = request_range(len, begin, length)?;
+        if begin == end {
+            return Ok(&[]);
+        }
+        let proof = self.verify(len, begin, end).await?;
+        let chunks = self
+            .data
+            .get_range(
+                proof.chunk_begin as usize,
+                (end - proof.chunk_begin) as usize,
+            )
+            .await?;
+        Ok(&chunks[(begin - proof.chunk_begin) as usize..])
+    }
+
+    /// Fetch and verify the chunks covering `begin..end`
+    async fn verify(&mut self, len: u64, begin: u64, end: u64) -> Result<Proof> {
+        let proof = Proof::new(len, begin, end);
+        let parents = self
+            .outboard
+            .get_ranges_coalesced(&proof.parent_ranges(), MAX_PARENT_GAP)
+            .await?;
+        let chunks = if proof.chunk_end > proof.chunk_begin {
+            self.data
+                .get_range(
+                    proof.chunk_begin as usize,
+                    (proof.chunk_end - proof.chunk_begin) as usize,
+                )
+                .await?
+        } else {
+            &[]
+        };
+        proof.verify(len, &self.root, &parents, chunks)?;
+        Ok(proof)
+    }
+}
+
+/// Range reader verifying all data against a BLAKE3 root hash
+///
+/// Data is read from the file, the tree nodes required for verification from
+/// its Bao outboard. Ranges are extended to 1 KiB chunk boundaries.
+pub struct SyncVerifiedHttpRangeClient<T: SyncHttpRangeClient> {
+    data: SyncBufferedHttpRangeClient<T>,
+    outboard: SyncBufferedHttpRangeClient<T>,
+    root: blake3::Hash,
+    length: Option<u64>,
+}
+
+impl<T: SyncHttpRangeClient> SyncVerifiedHttpRangeClient<T> {
+    pub fn new(
+        data: SyncBufferedHttpRangeClient<T>,
+        outboard: SyncBufferedHttpRangeClient<T>,
+        root: blake3::Hash,
+    ) -> Self {
+        SyncVerifiedHttpRangeClient {
+            data,
+            outboard,
+            root,
+            length: None,
+        }
+    }
+
+    /// Content length from the outboard header.
+    ///
+    /// The length is authenticated by verifying the final chunk.
+    pub fn content_length(&mut self) -> Result<u64> {
+        if let Some(length) = self.length {
+            return Ok(length);
+        }
+        let length = parse_length(self.outboard.get_range(0, HEADER_LEN as usize)?)?;
+        let last = (chunk_count(length) - 1) * CHUNK_LEN;
+        self.verify(length, last, length.max(last + 1))?;
+        self.length = Some(length);
+        Ok(length)
+    }
+
+    /// Get verified bytes of the given range.
+    pub fn get_range(&mut self, begin: usize, length: usize) -> Result<&[u8]> {
+        let len = self.content_length()?;
+        let (begin, end) = request_range(len, begin, length)?;
+        if begin == end {
+            return Ok(&[]);
+        }
+        let proof = self.verify(len, begin, end)?;
+        let chunks = self.data.get_range(
+            proof.chunk_begin as usize,
+            (end - proof.chunk_begin) as usize,
+        )?;
+        Ok(&chunks[(begin - proof.chunk_begin) as usize..])
+    }
+
+    /// Fetch and verify the chunks covering `begin..end`
+    fn verify(&mut self, len: u64, begin: u64, end: u64) -> Result<Proof> {
+        let proof = Proof::new(len, begin, end);
+        let parents = self
+            .outboard
+            .get_ranges_coalesced(&proof.parent_ranges(), MAX_PARENT_GAP)?;
+        let chunks = if proof.chunk_end > proof.chunk_begin {
+            self.data.get_range(
+                proof.chunk_begin as usize,
+                (proof.chunk_end - proof.chunk_begin) as usize,
+            )?
+        } else {
+            &[]
+        };
+        proof.verify(len, &self.root, &parents, chunks)?;
+        Ok(proof)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_client::MockHttpRangeClient;
+
+    /// Append the parent nodes of `data` in pre-order and return its chaining value.
+    fn encode(data: &[u8], start: u64, outboard: &mut Vec<u8>) -> ChainingValue {
+        if data.len() as u64 <= CHUNK_LEN {
+            return blake3::Hasher::new()
+                .set_input_offset(start)
+                .update(data)
+                .finalize_non_root();
+        }
+        let pos = outboard.len();
+        outboard.extend([0; PARENT_LEN as usize]);
+        let left_len = left_subtree_len(data.len() as u64);
+        let left = encode(&data[..left_len as usize], start, outboard);
+        let right = encode(&data[left_len as usize..], start + left_len, outboard);
+        outboard[pos..pos + 32].copy_from_slice(&left);
+        outboard[pos + 32..pos + 64].copy_from_slice(&right);
+        merge_subtrees_non_root(&left, &right, Mode::Hash)
+    }
+
+    fn outboard(data: &[u8]) -> Vec<u8> {
+        let mut outboard = (data.len() as u64).to_le_bytes().to_vec();
+        encode(data, 0, &mut outboard);
+        outboard
+    }
+
+    fn test_data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn sync_verified_ranges() -> Result<()> {
+        let data = test_data(10_000);
+        let root = blake3::hash(&data);
+        let mut client = SyncVerifiedHttpRangeClient::new(
+            SyncBufferedHttpRangeClient::with(MockHttpRangeClient::new(data.clone()), "data"),
+            SyncBufferedHttpRangeClient::with(MockHttpRangeClient::new(outboard(&data)), "bao"),
+            root,
+        );
+        assert_eq!(client.content_length()?, 10_000);
+        assert_eq!(client.get_range(3000, 2500)?, &data[3000..5500]);
+        assert_eq!(client.get_range(9990, 100)?, &data[9990..]);
+        assert!(client.get_range(10_000, 1).is_err());
+
+        // Small file with a single chunk
+        let data = test_data(100);
+        let mut client = SyncVerifiedHttpRangeClient::new(
+            SyncBufferedHttpRangeClient::with(MockHttpRangeClient::new(data.clone()), "data"),
+            SyncBufferedHttpRangeClient::with(MockHttpRangeClient::new(outboard(&data)), "bao"),
+            blake3::hash(&data),
+        );
+        assert_eq!(client.get_range(10, 5)?, &data[10..15]);
+        Ok(())
+    }
+
+    #[test]
+    fn sync_tampered_data() -> Result<()> {
+        let data = test_data(10_000);
+        let root = blake3::hash(&data);
+        let mut tampered = data.clone();
+        tampered[4321] ^= 1;
+        let mut client = SyncVerifiedHttpRangeClient::new(
+            SyncBufferedHttpRangeClient::with(MockHttpRangeClient::new(tampered), "data"),
+            SyncBufferedHttpRangeClient::with(MockHttpRangeClient::new(outboard(&data)), "bao"),
+            root,
+        );
+        client.data.min_req_size(1);
+        assert_eq!(client.get_range(0, 100)?, &data[..100]);
+        assert!(matches!(
+            client.get_range(4000, 100),
+            Err(HttpError::IntegrityError(_))
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_verified_ranges() -> Result<()> {
+        let data = test_data(5000);
+        let mut bao = outboard(&data);
+        let mut client = AsyncVerifiedHttpRangeClient::new(
+            AsyncBufferedHttpRangeClient::with(MockHttpRangeClient::new(data.clone()), "data"),
+            AsyncBufferedHttpRangeClient::with(MockHttpRangeClient::new(bao.clone()), "bao"),
+            blake3::hash(&data),
+        );
+        assert_eq!(client.get_range(1000, 3000).await?, &data[1000..4000]);
+
+        // Tampered outboard node
+        bao[HEADER_LEN as usize + 3] ^= 1;
+        let mut client = AsyncVerifiedHttpRangeClient::new(
+            AsyncBufferedHttpRangeClient::with(MockHttpRangeClient::new(data.clone()), "data"),
+            AsyncBufferedHttpRangeClient::with(MockHttpRangeClient::new(bao), "bao"),
+            blake3::hash(&data),
+        );
+        assert!(matches!(
+            client.content_length().await,
+            Err(HttpError::IntegrityError(_))
+        ));
+        Ok(())
+    }
+}
@@ -0,0 +1,365 @@
+//! Block index of BGZF files, as used for genomics data.
+use crate::buffered_range_client::unexpected_end;
+use crate::compressed::{decode_error, Frame};
+use crate::error::{HttpError, Result};
+use std::convert::TryInto;
+use std::io::Read;
+
+/// Length of a BGZF block header
+const HEADER_LEN: usize = 18;
+/// Length of the `ISIZE` field at the end of a block
+const ISIZE_LEN: usize = 4;
+
+fn invalid(reason: &str) -> HttpError {
+    HttpError::HttpError(format!("invalid BGZF file: {reason}"))
+}
+
+fn u16_le(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes(data[pos..pos + 2].try_into().expect("2 byte slice"))
+}
+
+fn u32_le(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().expect("4 byte slice"))
+}
+
+fn u64_le(data: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(data[pos..pos + 8].try_into().expect("8 byte slice"))
+}
+
+/// Compressed size of the block starting with `header`
+fn block_size(header: &[u8], offset: u64) -> Result<u64> {
+    if header.len() < HEADER_LEN {
+        return Err(unexpected_end(HEADER_LEN));
+    }
+    // gzip magic, deflate, FEXTRA flag and `BC` extra subfield of length 2
+    let valid = header[..3] == [0x1f, 0x8b, 8]
+        && header[3] & 4 != 0
+        && header[12..14] == *b"BC"
+        && u16_le(header, 14) == 2;
+    if !valid {
+        return Err(HttpError::HttpError(format!(
+            "no BGZF block at offset {offset}"
+        )));
+    }
+    Ok(u64::from(u16_le(header, 16)) + 1)
+}
+
+/// `(compressed offset, uncompressed offset)` block starts of a `.gzi` index,
+/// including the first block
+fn parse_gzi(gzi: &[u8]) -> Result<Vec<(u64, u64)>> {
+    if gzi.len() < 8 {
+        return Err(unexpected_end(8));
+    }
+    let count = u64_le(gzi, 0) as usize;
+    let entries = &gzi[8..];
+    let gzi_len = count
+        .checked_mul(16)
+        .and_then(|len| len.checked_add(8))
+        .ok_or_else(|| invalid("too many index entries"))?;
+    if gzi.len() < gzi_len {
+        return Err(unexpected_end(gzi_len));
+    }
+    let mut blocks = vec![(0, 0)];
+    blocks.extend(
+        entries
+            .chunks_exact(16)
+            .take(count)
+            .map(|entry| (u64_le(entry, 0), u64_le(entry, 8))),
+    );
+    Ok(blocks)
+}
+
+/// Offset of the `ISIZE` field of the last block, checking the order of indexed blocks
+fn last_isize_offset(blocks: &[(u64, u64)], compressed_len: u64) -> Result<u64> {
+    let ordered = blocks
+        .windows(2)
+        .all(|pair| pair[0].0 < pair[1].0 && pair[0].1 <= pair[1].1);
+    let last_block = blocks.last().map_or(0, |&(offset, _)| offset);
+    if !ordered || last_block >= compressed_len {
+        return Err(invalid("index entries out of order"));
+    }
+    compressed_len
+        .checked_sub(ISIZE_LEN as u64)
+        .ok_or_else(|| invalid("file too short"))
+}
+
+/// Frames of indexed blocks. The last block ends at `compressed_len` and
+/// decompresses to `last_isize` bytes.
+fn indexed_frames(blocks: &[(u64, u64)], compressed_len: u64, last_isize: u32) -> Vec<Frame> {
+    blocks
+        .iter()
+        .enumerate()
+        .map(|(i, &(compressed_offset, uncompressed_offset))| {
+            let (next_compressed, next_uncompressed) = blocks
+                .get(i + 1)
+                .copied()
+                .unwrap_or((compressed_len, uncompressed_offset + u64::from(last_isize)));
+            Frame {
+                compressed_offset,
+                compressed_len: next_compressed - compressed_offset,
+                uncompressed_offset,
+                uncompressed_len: next_uncompressed - uncompressed_offset,
+            }
+        })
+        .collect()
+}
+
+fn unknown_length() -> HttpError {
+    HttpError::HttpError("content length unknown".to_string())
+}
+
+/// Decompress a single BGZF block, checking its CRC32. Stops after `max_len + 1` bytes.
+pub(crate) fn decode_block(data: &[u8], max_len: u64) -> Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    flate2::read::GzDecoder::new(data)
+        .take(max_len.saturating_add(1))
+        .read_to_end(&mut decoded)
+        .map_err(decode_error)?;
+    Ok(decoded)
+}
+
+pub(crate) mod sync {
+    use super::*;
+    use crate::range_client::SyncHttpRangeClient;
+    use crate::SyncBufferedHttpRangeClient;
+
+    fn content_length<T: SyncHttpRangeClient>(
+        client: &mut SyncBufferedHttpRangeClient<T>,
+    ) -> Result<u64> {
+        match client.content_length() {
+            Some(length) => Ok(length),
+            None => client.get_content_length()?.ok_or_else(unknown_length),
+        }
+    }
+
+    /// Read block headers with one request per block.
+    pub(crate) fn scan_blocks<T: SyncHttpRangeClient>(
+        client: &mut SyncBufferedHttpRangeClient<T>,
+    ) -> Result<Vec<Frame>> {
+        let total = content_length(client)?;
+        let mut frames = Vec::new();
+        let mut header = client.get_range(0, HEADER_LEN)?.to_vec();
+        let (mut compressed_offset, mut uncompressed_offset) = (0, 0);
+        while compressed_offset < total {
+            let compressed_len = block_size(&header, compressed_offset)?;
+            let next = compressed_offset + compressed_len;
+            // ISIZE of this block followed by the header of the next block
+            let tail_len = if next < total {
+                ISIZE_LEN + HEADER_LEN
+            } else {
+                ISIZE_LEN
+            };
+            let tail_begin = next
+                .checked_sub(ISIZE_LEN as u64)
+                .ok_or_else(|| invalid("block too short"))?;
+            let tail = client.get_range(tail_begin as usize, tail_len)?;
+            if tail.len() < tail_len {
+                return Err(unexpected_end(tail_len));
+            }
+            let uncompressed_len = u64::from(u32_le(tail, 0));
+            header = tail[ISIZE_LEN..].to_vec();
+            frames.push(Frame {
+                compressed_offset,
+                compressed_len,
+                uncompressed_offset,
+                uncompressed_len,
+            });
+            compressed_offset = next;
+            uncompressed_offset += uncompressed_len;
+        }
+        Ok(frames)
+    }
+
+    /// Frames of a `.gzi` index. Reads the uncompressed size of the last block.
+    pub(crate) fn indexed_blocks<T: SyncHttpRangeClient>(
+        client: &mut SyncBufferedHttpRangeClient<T>,
+        gzi: &[u8],
+    ) -> Result<Vec<Frame>> {
+        let blocks = parse_gzi(gzi)?;
+        let total = content_length(client)?;
+        let isize_begin = last_isize_offset(&blocks, total)?;
+        let isize = client.get_range(isize_begin as usize, ISIZE_LEN)?;
+        if isize.len() < ISIZE_LEN {
+            return Err(unexpected_end(ISIZE_LEN));
+        }
+        Ok(indexed_frames(&blocks, total, u32_le(isize, 0)))
+    }
+}
+
+pub(crate) mod nonblocking {
+    use super::*;
+    use crate::range_client::AsyncHttpRangeClient;
+    use crate::AsyncBufferedHttpRangeClient;
+
+    async fn content_length<T: AsyncHttpRangeClient>(
+        client: &mut AsyncBufferedHttpRangeClient<T>,
+    ) -> Result<u64> {
+        match client.content_length() {
+            Some(length) => Ok(length),
+            None => client
+                .get_content_length()
+                .await?
+                .ok_or_else(unknown_length),
+        }
+    }
+
+    /// Read block headers with one request per block.
+    pub(crate) async fn scan_blocks<T: AsyncHttpRangeClient>(
+        client: &mut AsyncBufferedHttpRangeClient<T>,
+    ) -> Result<Vec<Frame>> {
+        let total = content_length(client).await?;
+        let mut frames = Vec::new();
+        let mut header = client.get_range(0, HEADER_LEN).await?.to_vec();
+        let (mut compressed_offset, mut uncompressed_offset) = (0, 0);
+        while compressed_offset < total {
+            let compressed_len = block_size(&header, compressed_offset)?;
+            let next = compressed_offset + compressed_len;
+            // ISIZE of this block followed by the header of the next block
+            let tail_len = if next < total {
+                ISIZE_LEN + HEADER_LEN
+            } else {
+                ISIZE_LEN
+            };
+            let tail_begin = next
+                .checked_sub(ISIZE_LEN as u64)
+                .ok_or_else(|| invalid("block too short"))?;
+            let tail = client.get_range(tail_begin as usize, tail_len).await?;
+            if tail.len() < tail_len {
+                return Err(unexpected_end(tail_len));
+            }
+            let uncompressed_len = u64::from(u32_le(tail, 0));
+            header = tail[ISIZE_LEN..].to_vec();
+            frames.push(Frame {
+                compressed_offset,
+                compressed_len,
+                uncompressed_offset,
+                uncompressed_len,
+            });
+            compressed_offset = next;
+            uncompressed_offset += uncompressed_len;
+        }
+        Ok(frames)
+    }
+
+    /// Frames of a `.gzi` index. Reads the uncompressed size of the last block.
+    pub(crate) async fn indexed_blocks<T: AsyncHttpRangeClient>(
+        client: &mut AsyncBufferedHttpRangeClient<T>,
+        gzi: &[u8],
+    ) -> Result<Vec<Frame>> {
+        let blocks = parse_gzi(gzi)?;
+        let total = content_length(client).await?;
+        let isize_begin = last_isize_offset(&blocks, total)?;
+        let isize = client.get_range(isize_begin as usize, ISIZE_LEN).await?;
+        if isize.len() < ISIZE_LEN {
+            return Err(unexpected_end(ISIZE_LEN));
+        }
+        Ok(indexed_frames(&blocks, total, u32_le(isize, 0)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_client::MockHttpRangeClient;
+    use crate::{
+        AsyncBufferedHttpRangeClient, AsyncCompressedReader, SyncBufferedHttpRangeClient,
+        SyncCompressedReader,
+    };
+    use flate2::{Compression, GzBuilder};
+    use std::io::{Seek, SeekFrom, Write};
+
+    /// BGZF block of `data`
+    fn block(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzBuilder::new()
+            .extra(vec![b'B', b'C', 2, 0, 0, 0])
+            .write(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        let mut block = encoder.finish().unwrap();
+        let bsize = (block.len() - 1) as u16;
+        block[16..18].copy_from_slice(&bsize.to_le_bytes());
+        block
+    }
+
+    /// BGZF file with blocks of 10_000 bytes, an empty EOF block and its `.gzi` index
+    fn bgzf(data: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let mut file = Vec::new();
+        let mut entries = Vec::new();
+        for (i, chunk) in data.chunks(10_000).chain([&[][..]]).enumerate() {
+            if i > 0 {
+                entries.push((file.len() as u64, (i * 10_000).min(data.len()) as u64));
+            }
+            file.extend(block(chunk));
+        }
+        let mut gzi = (entries.len() as u64).to_le_bytes().to_vec();
+        for (compressed, uncompressed) in entries {
+            gzi.extend(compressed.to_le_bytes());
+            gzi.extend(uncompressed.to_le_bytes());
+        }
+        (file, gzi)
+    }
+
+    fn test_data() -> Vec<u8> {
+        (0..45_000u32).flat_map(|i| (i / 7).to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn sync_bgzf() -> std::io::Result<()> {
+        let data = test_data();
+        let (file, _) = bgzf(&data);
+        let backend = MockHttpRangeClient::new(file);
+        let client = SyncBufferedHttpRangeClient::with(backend, "mock");
+        let mut reader = SyncCompressedReader::bgzf(client).unwrap();
+        assert_eq!(reader.frames().len(), 19);
+        assert_eq!(reader.uncompressed_len(), data.len() as u64);
+        let mut buf = vec![0; 15_000];
+        reader.seek(SeekFrom::Start(95_000))?;
+        reader.read_exact(&mut buf)?;
+        assert_eq!(buf, data[95_000..110_000]);
+
+        // Virtual offset of byte 5 in the third block
+        let virtual_offset = reader.frames()[2].compressed_offset << 16 | 5;
+        assert_eq!(reader.seek_virtual(virtual_offset).unwrap(), 20_005);
+        let mut rest = Vec::new();
+        reader.seek(SeekFrom::End(-10))?;
+        reader.read_to_end(&mut rest)?;
+        assert_eq!(rest, data[data.len() - 10..]);
+        Ok(())
+    }
+
+    #[test]
+    fn malformed() {
+        let mut gzi = u64::MAX.to_le_bytes().to_vec();
+        gzi.extend([0; 16]);
+        assert!(parse_gzi(&gzi).is_err());
+        assert!(last_isize_offset(&[(0, 0), (500, 100), (400, 200)], 1000).is_err());
+        assert!(last_isize_offset(&[(0, 0), (500, 100)], 500).is_err());
+        assert!(last_isize_offset(&[(0, 0)], 2).is_err());
+
+        // Block size field too small
+        let mut file = block(b"data");
+        file[16..18].copy_from_slice(&[1, 0]);
+        let backend = MockHttpRangeClient::new(file);
+        let client = SyncBufferedHttpRangeClient::with(backend, "mock");
+        assert!(SyncCompressedReader::bgzf(client).is_err());
+
+        // Decompression stops after the expected length
+        assert_eq!(decode_block(&block(&[0; 100_000]), 100).unwrap().len(), 101);
+    }
+
+    #[tokio::test]
+    async fn async_bgzf_with_index() -> Result<()> {
+        let data = test_data();
+        let (file, gzi) = bgzf(&data);
+        let backend = MockHttpRangeClient::new(file);
+        let mut client = AsyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        client.min_req_size(1);
+        let mut reader = AsyncCompressedReader::bgzf_with_index(client, &gzi).await?;
+        assert_eq!(reader.uncompressed_len(), data.len() as u64);
+        let requests = backend.requests().len();
+        assert_eq!(reader.get_range(79_990, 20).await?, data[79_990..80_010]);
+        // Two blocks
+        assert_eq!(backend.requests().len(), requests + 2);
+        assert_eq!(reader.get_range(179_995, 10).await?, data[179_995..]);
+        Ok(())
+    }
+}
@@ -1,13 +1,23 @@
-use crate::error::Result;
-use bytes::{BufMut, BytesMut};
+#[cfg(feature = "checksum")]
+use crate::checksum::{BlockHashes, Checksum, ObjectVerifier};
+use crate::credentials::{AsyncCredentialProvider, CredentialProvider, Credentials};
+use crate::error::{HttpError, Result};
+use crate::range_client::set_header;
+use crate::retry::RetryPolicy;
+use byteorder::ByteOrder;
+use bytes::{BufMut, Bytes, BytesMut};
 use read_logger::{Level, ReadStatsLogger};
 use std::cmp::{max, min};
+use std::convert::TryFrom;
 use std::str::{self, FromStr};
+use std::time::Duration;
 
 /// Buffer for Range request reader (https://developer.mozilla.org/en-US/docs/Web/HTTP/Range_requests)
 struct HttpRangeBuffer {
     buf: BytesMut,
     min_req_size: usize,
+    /// Maximal size of a request merging multiple ranges
+    max_req_size: usize,
     /// Current position for Read+Seek implementation
     offset: usize,
     /// Lower index of buffer relative to input stream
@@ -21,6 +31,7 @@ impl HttpRangeBuffer {
         HttpRangeBuffer {
             buf: BytesMut::new(),
             min_req_size: 1024,
+            max_req_size: 1024 * 1024,
             offset: 0,
             head: 0,
             read_stats: ReadStatsLogger::new(Level::Trace, "read"),
@@ -69,17 +80,230 @@ impl HttpRangeBuffer {
         let end = (begin + length).saturating_sub(1);
         format!("bytes={begin}-{end}")
     }
+
+    /// Replace buffer content with `bytes` starting at offset `begin`
+    fn replace(&mut self, begin: usize, bytes: Bytes) {
+        self.buf.clear();
+        self.head = begin;
+        self.buf.put(bytes);
+        self.offset = self.tail();
+    }
+
+    /// Return bytes from buffer, if the range is completely buffered
+    fn get_buffered(&self, begin: usize, length: usize) -> Option<Bytes> {
+        if begin >= self.head && begin + length <= self.tail() {
+            let lower = begin - self.head;
+            Some(Bytes::copy_from_slice(&self.buf[lower..lower + length]))
+        } else {
+            None
+        }
+    }
+}
+
+/// Request covering one or more ranges of a batch read
+#[derive(Debug, PartialEq)]
+struct CoalescedRange {
+    begin: usize,
+    length: usize,
+    /// Indices of the requested ranges covered by this request
+    members: Vec<usize>,
+}
+
+/// Sort ranges and merge ranges with gaps up to `max_gap` bytes into requests
+/// not larger than `max_req_size`. Empty ranges and ranges in `skip` are ignored.
+fn coalesce_ranges(
+    ranges: &[(usize, usize)],
+    skip: &[bool],
+    max_gap: usize,
+    max_req_size: usize,
+) -> Vec<CoalescedRange> {
+    let mut order: Vec<usize> = (0..ranges.len())
+        .filter(|&i| ranges[i].1 > 0 && !skip[i])
+        .collect();
+    order.sort_by_key(|&i| ranges[i].0);
+    let mut requests: Vec<CoalescedRange> = Vec::new();
+    for i in order {
+        let (begin, length) = ranges[i];
+        if let Some(req) = requests.last_mut() {
+            let req_end = req.begin + req.length;
+            let end = max(req_end, begin + length);
+            if begin <= req_end + max_gap && end - req.begin <= max_req_size {
+                req.length = end - req.begin;
+                req.members.push(i);
+                continue;
+            }
+        }
+        requests.push(CoalescedRange {
+            begin,
+            length,
+            members: vec![i],
+        });
+    }
+    requests
+}
+
+/// Distribute the response bytes of coalesced requests to the requested ranges
+fn split_coalesced(
+    ranges: &[(usize, usize)],
+    requests: &[CoalescedRange],
+    responses: Vec<Bytes>,
+    results: &mut [Bytes],
+) {
+    for (req, bytes) in requests.iter().zip(responses) {
+        for &i in &req.members {
+            let (begin, length) = ranges[i];
+            let lower = min(begin - req.begin, bytes.len());
+            let upper = min(lower + length, bytes.len());
+            results[i] = bytes.slice(lower..upper);
+        }
+    }
+}
+
+pub(crate) fn unexpected_end(length: usize) -> HttpError {
+    HttpError::HttpError(format!("unexpected end of data reading {length} bytes"))
+}
+
+/// Value of a `Content-Length` header
+pub(crate) fn parse_content_length(value: Option<&str>) -> Result<Option<u64>> {
+    value
+        .map(|value| {
+            u64::from_str(value)
+                .map_err(|_| HttpError::HttpError("Invalid content-length received".to_string()))
+        })
+        .transpose()
+}
+
+/// Copy a slice of `N` bytes into an array
+fn to_array<const N: usize>(bytes: &[u8]) -> Result<[u8; N]> {
+    <[u8; N]>::try_from(bytes).map_err(|_| unexpected_end(N))
+}
+
+/// Retry status of a request
+#[derive(Default)]
+struct RetryState {
+    /// Refresh credentials for next request
+    refresh: bool,
+    refreshed: bool,
+    /// Number of retries after transient errors
+    attempts: u32,
+    /// Delay before the next retry
+    delay: Duration,
+}
+
+/// Request headers not sent to a pinned URL of another origin
+const CREDENTIAL_HEADERS: [&str; 3] = ["authorization", "proxy-authorization", "cookie"];
+
+/// Scheme and host of `url`, including the port
+fn origin(url: &str) -> Option<String> {
+    let (scheme, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    Some(format!("{scheme}://{host}").to_ascii_lowercase())
+}
+
+/// URL and headers for a request, including `credentials` from a provider
+///
+/// Credential headers are removed for a pinned URL with an origin other than `url`.
+fn request_target(
+    url: &str,
+    pinned_url: Option<&str>,
+    headers: &[(String, String)],
+    credentials: Option<Credentials>,
+) -> (String, Vec<(String, String)>) {
+    let mut headers = headers.to_vec();
+    let mut credential_headers = Vec::new();
+    if let Some(credentials) = credentials {
+        for (name, value) in &credentials.headers {
+            set_header(&mut headers, name, value);
+            credential_headers.push(name.to_ascii_lowercase());
+        }
+        if let Some(url) = credentials.url {
+            return (url, headers);
+        }
+    }
+    let Some(pinned_url) = pinned_url else {
+        return (url.to_string(), headers);
+    };
+    if origin(pinned_url) != origin(url) {
+        headers.retain(|(name, _)| {
+            let name = name.to_ascii_lowercase();
+            !CREDENTIAL_HEADERS.contains(&name.as_str()) && !credential_headers.contains(&name)
+        });
+    }
+    (pinned_url.to_string(), headers)
+}
+
+/// Check whether a failed request should be retried with refreshed credentials,
+/// with the original URL instead of an expired pinned URL or according to the retry policy.
+fn retry_request(
+    error: &HttpError,
+    retry: &mut RetryState,
+    has_credential_provider: bool,
+    pinned_url: &mut Option<String>,
+    policy: &RetryPolicy,
+) -> bool {
+    retry.refresh = false;
+    retry.delay = Duration::ZERO;
+    match error {
+        HttpError::HttpStatus(401 | 403) if has_credential_provider && !retry.refreshed => {
+            retry.refresh = true;
+            retry.refreshed = true;
+            true
+        }
+        HttpError::HttpStatus(403 | 404) if pinned_url.is_some() => {
+            // Pinned URL expired
+            *pinned_url = None;
+            true
+        }
+        _ => match policy.retry_delay(error, retry.attempts) {
+            Some(delay) => {
+                retry.attempts += 1;
+                retry.delay = delay;
+                true
+            }
+            None => false,
+        },
+    }
 }
 
 pub(crate) mod nonblocking {
     use super::*;
-    use crate::range_client::AsyncHttpRangeClient;
+    use crate::range_client::{basic_auth, AsyncHttpRangeClient, RangeResponse};
+    use futures_util::future::try_join_all;
+    use futures_util::stream::{self, Stream};
+    use std::sync::Arc;
+
+    /// Wait without depending on a specific async runtime
+    #[cfg(not(target_arch = "wasm32"))]
+    async fn sleep(duration: Duration) {
+        if !duration.is_zero() {
+            futures_timer::Delay::new(duration).await;
+        }
+    }
+
+    /// Retry delays are not applied on Wasm targets
+    #[cfg(target_arch = "wasm32")]
+    async fn sleep(_duration: Duration) {}
 
     /// HTTP client adapter for HTTP Range requests with a buffer optimized for sequential reading
     pub struct AsyncBufferedHttpRangeClient<T: AsyncHttpRangeClient> {
         http_client: T,
         url: String,
         buffer: HttpRangeBuffer,
+        length_info: Option<Option<u64>>,
+        pin_redirects: bool,
+        /// Final URL of a redirected request
+        pinned_url: Option<String>,
+        /// Additional request headers
+        headers: Vec<(String, String)>,
+        credential_provider: Option<Arc<dyn AsyncCredentialProvider>>,
+        retry_policy: RetryPolicy,
+        #[cfg(feature = "checksum")]
+        block_hashes: Option<Arc<BlockHashes>>,
+        #[cfg(feature = "checksum")]
+        object_verifier: Option<ObjectVerifier>,
     }
 
     impl<T: AsyncHttpRangeClient> AsyncBufferedHttpRangeClient<T> {
@@ -88,6 +312,16 @@ pub(crate) mod nonblocking {
                 http_client,
                 url: url.to_string(),
                 buffer: HttpRangeBuffer::new(),
+                length_info: None,
+                pin_redirects: false,
+                pinned_url: None,
+                headers: Vec::new(),
+                credential_provider: None,
+                retry_policy: RetryPolicy::default(),
+                #[cfg(feature = "checksum")]
+                block_hashes: None,
+                #[cfg(feature = "checksum")]
+                object_verifier: None,
             }
         }
 
@@ -102,6 +336,27 @@ pub(crate) mod nonblocking {
             self
         }
 
+        /// Set a request header sent with every request.
+        pub fn set_header(&mut self, name: &str, value: &str) {
+            set_header(&mut self.headers, name, value);
+        }
+
+        /// Set a request header sent with every request.
+        pub fn with_header(&mut self, name: &str, value: &str) -> &mut Self {
+            self.set_header(name, value);
+            self
+        }
+
+        /// Use HTTP Bearer authentication.
+        pub fn bearer_auth(&mut self, token: &str) -> &mut Self {
+            self.with_header("Authorization", &format!("Bearer {token}"))
+        }
+
+        /// Use HTTP Basic authentication.
+        pub fn basic_auth(&mut self, username: &str, password: Option<&str>) -> &mut Self {
+            self.with_header("Authorization", &basic_auth(username, password))
+        }
+
         /// Get `length` bytes with offset `begin`.
         pub async fn get_range(&mut self, begin: usize, length: usize) -> Result<&[u8]> {
             let slice_len = if let Some((range_begin, range_length)) =
@@ -111,9 +366,9 @@ pub(crate) mod nonblocking {
                     .http_stats
                     .log(range_begin, range_length, length);
                 let range = self.buffer.range(range_begin, range_length);
-                let bytes = self.http_client.get_range(&self.url, &range).await?;
-                let eff_len = bytes.len();
-                self.buffer.buf.put(bytes);
+                let response = self.fetch_range(&range).await?;
+                let eff_len = response.data.len();
+                self.buffer.buf.put(response.data);
                 min(range_begin - begin + eff_len, length)
             } else {
                 length
@@ -129,21 +384,564 @@ pub(crate) mod nonblocking {
             self.get_range(self.buffer.offset, length).await
         }
 
+        /// Read `N` bytes at the current position.
+        async fn read_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+            to_array(self.get_bytes(N).await?)
+        }
+
+        /// Read an unsigned 8 bit integer.
+        pub async fn get_u8(&mut self) -> Result<u8> {
+            Ok(self.read_array::<1>().await?[0])
+        }
+
+        /// Read a signed 8 bit integer.
+        pub async fn get_i8(&mut self) -> Result<i8> {
+            Ok(self.read_array::<1>().await?[0] as i8)
+        }
+
+        /// Read an unsigned 16 bit integer in byte order `B`.
+        pub async fn get_u16<B: ByteOrder>(&mut self) -> Result<u16> {
+            Ok(B::read_u16(&self.read_array::<2>().await?))
+        }
+
+        /// Read a signed 16 bit integer in byte order `B`.
+        pub async fn get_i16<B: ByteOrder>(&mut self) -> Result<i16> {
+            Ok(B::read_i16(&self.read_array::<2>().await?))
+        }
+
+        /// Read an unsigned 32 bit integer in byte order `B`.
+        pub async fn get_u32<B: ByteOrder>(&mut self) -> Result<u32> {
+            Ok(B::read_u32(&self.read_array::<4>().await?))
+        }
+
+        /// Read a signed 32 bit integer in byte order `B`.
+        pub async fn get_i32<B: ByteOrder>(&mut self) -> Result<i32> {
+            Ok(B::read_i32(&self.read_array::<4>().await?))
+        }
+
+        /// Read an unsigned 64 bit integer in byte order `B`.
+        pub async fn get_u64<B: ByteOrder>(&mut self) -> Result<u64> {
+            Ok(B::read_u64(&self.read_array::<8>().await?))
+        }
+
+        /// Read a signed 64 bit integer in byte order `B`.
+        pub async fn get_i64<B: ByteOrder>(&mut self) -> Result<i64> {
+            Ok(B::read_i64(&self.read_array::<8>().await?))
+        }
+
+        /// Read a IEEE754 single-precision floating point number in byte order `B`.
+        pub async fn get_f32<B: ByteOrder>(&mut self) -> Result<f32> {
+            Ok(B::read_f32(&self.read_array::<4>().await?))
+        }
+
+        /// Read a IEEE754 double-precision floating point number in byte order `B`.
+        pub async fn get_f64<B: ByteOrder>(&mut self) -> Result<f64> {
+            Ok(B::read_f64(&self.read_array::<8>().await?))
+        }
+
+        /// Read a plain old data value, e.g. a `#[repr(C)]` struct, in native byte order.
+        #[cfg(feature = "bytemuck")]
+        pub async fn get_pod<P: bytemuck::Pod>(&mut self) -> Result<P> {
+            let size = std::mem::size_of::<P>();
+            let bytes = self.get_bytes(size).await?;
+            if bytes.len() < size {
+                return Err(unexpected_end(size));
+            }
+            Ok(bytemuck::pod_read_unaligned(bytes))
+        }
+
+        /// Return the buffered bytes at the current position, requesting `min_req_size`
+        /// bytes if the buffer is exhausted. An empty slice indicates the end of the data.
+        pub async fn fill_buf(&mut self) -> Result<&[u8]> {
+            let offset = self.buffer.offset;
+            if self
+                .content_length()
+                .is_some_and(|length| offset as u64 >= length)
+            {
+                return Ok(&[]);
+            }
+            if offset >= self.buffer.tail() || offset < self.buffer.head {
+                let result = self
+                    .get_range(offset, self.buffer.min_req_size)
+                    .await
+                    .map(|_| ());
+                self.buffer.offset = offset;
+                match result {
+                    Err(HttpError::HttpStatus(416)) => return Ok(&[]),
+                    result => result?,
+                }
+            }
+            let lower = self.buffer.offset - self.buffer.head;
+            Ok(&self.buffer.buf[lower..])
+        }
+
+        /// Mark `amt` bytes returned by [fill_buf](Self::fill_buf) as read.
+        pub fn consume(&mut self, amt: usize) {
+            self.buffer.offset += amt;
+        }
+
+        /// Read bytes into `buf` until the delimiter `byte` or the end of the data is reached.
+        ///
+        /// Returns the number of bytes read, including the delimiter.
+        pub async fn read_until(&mut self, byte: u8, buf: &mut Vec<u8>) -> Result<usize> {
+            let mut read = 0;
+            loop {
+                let (done, used) = {
+                    let available = self.fill_buf().await?;
+                    match available.iter().position(|b| *b == byte) {
+                        Some(i) => {
+                            buf.extend_from_slice(&available[..=i]);
+                            (true, i + 1)
+                        }
+                        None => {
+                            buf.extend_from_slice(available);
+                            (available.is_empty(), available.len())
+                        }
+                    }
+                };
+                self.consume(used);
+                read += used;
+                if done {
+                    return Ok(read);
+                }
+            }
+        }
+
+        /// Read a line including the line break and append it to `buf`.
+        ///
+        /// Returns the number of bytes read, which is 0 at the end of the data.
+        pub async fn read_line(&mut self, buf: &mut String) -> Result<usize> {
+            let mut bytes = Vec::new();
+            let read = self.read_until(b'\n', &mut bytes).await?;
+            let line = String::from_utf8(bytes)
+                .map_err(|_| HttpError::HttpError("line is not valid UTF-8".to_string()))?;
+            buf.push_str(&line);
+            Ok(read)
+        }
+
+        /// Stream of lines without line breaks, starting at the current position.
+        ///
+        /// Like [std::io::BufRead::lines], `\n` and `\r\n` line endings are removed.
+        /// The stream ends at the end of the data or after the first error.
+        pub fn lines(&mut self) -> impl Stream<Item = Result<String>> + '_ {
+            stream::unfold(Some(self), |client| async move {
+                let client = client?;
+                let mut line = String::new();
+                match client.read_line(&mut line).await {
+                    Ok(0) => None,
+                    Ok(_) => {
+                        if line.ends_with('\n') {
+                            line.pop();
+                            if line.ends_with('\r') {
+                                line.pop();
+                            }
+                        }
+                        Some((Ok(line), Some(client)))
+                    }
+                    Err(e) => Some((Err(e), None)),
+                }
+            })
+        }
+
+        /// Set maximal request size for merged ranges.
+        pub fn set_max_req_size(&mut self, size: usize) {
+            self.buffer.max_req_size = size;
+        }
+
+        /// Set maximal request size for merged ranges.
+        pub fn max_req_size(&mut self, size: usize) -> &mut Self {
+            self.set_max_req_size(size);
+            self
+        }
+
+        /// Get multiple `(begin, length)` ranges with concurrent requests.
+        ///
+        /// Ranges with gaps up to `max_gap` bytes are merged into a single request.
+        /// Results are returned in the order of `ranges`.
+        pub async fn get_ranges_coalesced(
+            &mut self,
+            ranges: &[(usize, usize)],
+            max_gap: usize,
+        ) -> Result<Vec<Bytes>> {
+            let buffered: Vec<Option<Bytes>> = ranges
+                .iter()
+                .map(|&(begin, length)| self.buffer.get_buffered(begin, length))
+                .collect();
+            let skip: Vec<bool> = buffered.iter().map(Option::is_some).collect();
+            let mut results: Vec<Bytes> = buffered
+                .into_iter()
+                .map(Option::unwrap_or_default)
+                .collect();
+            let requests = coalesce_ranges(ranges, &skip, max_gap, self.buffer.max_req_size);
+            let mut http_ranges = Vec::with_capacity(requests.len());
+            for req in &requests {
+                self.buffer
+                    .http_stats
+                    .log(req.begin, req.length, req.length);
+                http_ranges.push(self.buffer.range(req.begin, req.length));
+            }
+            let responses = self.fetch_ranges(&http_ranges, &[]).await?;
+            let responses = responses
+                .into_iter()
+                .map(|response| response.data)
+                .collect();
+            split_coalesced(ranges, &requests, responses, &mut results);
+            Ok(results)
+        }
+
+        /// Get the last `length` bytes with a suffix range request (`bytes=-length`).
+        ///
+        /// The total length of the file is taken from the `Content-Range` response header.
+        pub async fn get_suffix(&mut self, length: usize) -> Result<&[u8]> {
+            // `bytes=-0` is unsatisfiable
+            if length == 0 {
+                return Ok(&[]);
+            }
+            #[cfg(feature = "checksum")]
+            if self.block_hashes.is_some() && self.length_info.is_none() {
+                self.get_content_length().await?;
+            }
+            if let Some(Some(total)) = self.length_info {
+                let begin = (total as usize).saturating_sub(length);
+                return self.get_range(begin, total as usize - begin).await;
+            }
+            let range = format!("bytes=-{length}");
+            let response = self.fetch_range(&range).await?;
+            let begin = if let Some(begin) = response.range_begin() {
+                begin as usize
+            } else {
+                // Backend doesn't report Content-Range
+                let total = self.get_content_length().await?.ok_or_else(|| {
+                    HttpError::HttpError("Suffix range failed - no content-length received".into())
+                })?;
+                (total as usize).saturating_sub(response.data.len())
+            };
+            self.buffer
+                .http_stats
+                .log(begin, response.data.len(), length);
+            self.buffer.replace(begin, response.data);
+            Ok(&self.buffer.buf[..])
+        }
+
+        /// Get all bytes from offset `begin` to the end of the file (`bytes=begin-`).
+        pub async fn get_from(&mut self, begin: usize) -> Result<&[u8]> {
+            let range = format!("bytes={begin}-");
+            let response = self.fetch_range(&range).await?;
+            self.buffer
+                .http_stats
+                .log(begin, response.data.len(), response.data.len());
+            self.buffer.replace(begin, response.data);
+            Ok(&self.buffer.buf[..])
+        }
+
+        /// Total length of the file, if known from a previous response.
+        pub fn content_length(&self) -> Option<u64> {
+            self.length_info.flatten()
+        }
+
+        /// Offset following the last read bytes.
+        pub fn position(&self) -> usize {
+            self.buffer.offset
+        }
+
+        /// Reuse the final URL of redirected requests for subsequent requests.
+        ///
+        /// Falls back to the original URL when the pinned URL responds with 403 or 404.
+        pub fn set_pin_redirects(&mut self, pin: bool) {
+            self.pin_redirects = pin;
+            if !pin {
+                self.pinned_url = None;
+            }
+        }
+
+        /// Reuse the final URL of redirected requests for subsequent requests.
+        ///
+        /// `Authorization`, `Proxy-Authorization`, `Cookie` and credential provider
+        /// headers are not sent to a pinned URL of another origin.
+        pub fn pin_redirects(&mut self, pin: bool) -> &mut Self {
+            self.set_pin_redirects(pin);
+            self
+        }
+
+        /// URL used for requests, after resolving redirects
+        pub fn request_url(&self) -> &str {
+            self.pinned_url.as_deref().unwrap_or(&self.url)
+        }
+
+        /// Set provider for expiring credentials or presigned URLs.
+        pub fn set_credential_provider(
+            &mut self,
+            provider: impl AsyncCredentialProvider + 'static,
+        ) {
+            self.credential_provider = Some(Arc::new(provider));
+        }
+
+        /// Set provider for expiring credentials or presigned URLs.
+        pub fn credential_provider(
+            &mut self,
+            provider: impl AsyncCredentialProvider + 'static,
+        ) -> &mut Self {
+            self.set_credential_provider(provider);
+            self
+        }
+
+        /// Set retry policy for range requests failing with transient errors.
+        pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+            self.retry_policy = policy;
+        }
+
+        /// Set retry policy for range requests failing with transient errors.
+        pub fn retry_policy(&mut self, policy: RetryPolicy) -> &mut Self {
+            self.set_retry_policy(policy);
+            self
+        }
+
+        /// Verify fetched ranges against expected block hashes.
+        ///
+        /// Requests are widened to block boundaries. Download chunk sizes should be
+        /// multiples of the block size to avoid overlapping requests.
+        #[cfg(feature = "checksum")]
+        pub fn set_block_hashes(&mut self, hashes: BlockHashes) {
+            self.block_hashes = Some(Arc::new(hashes));
+        }
+
+        /// Verify fetched ranges against expected block hashes.
+        #[cfg(feature = "checksum")]
+        pub fn block_hashes(&mut self, hashes: BlockHashes) -> &mut Self {
+            self.set_block_hashes(hashes);
+            self
+        }
+
+        /// Verify the whole-object checksum when the file has been read to the end.
+        ///
+        /// Only ranges read sequentially from the start of the file are hashed.
+        #[cfg(feature = "checksum")]
+        pub fn set_expected_checksum(&mut self, checksum: Checksum) {
+            self.object_verifier = Some(ObjectVerifier::new(checksum));
+        }
+
+        /// Verify the whole-object checksum when the file has been read to the end.
+        #[cfg(feature = "checksum")]
+        pub fn expected_checksum(&mut self, checksum: Checksum) -> &mut Self {
+            self.set_expected_checksum(checksum);
+            self
+        }
+
+        /// Credentials from the credential provider
+        async fn credentials(&self, refresh: bool) -> Result<Option<Credentials>> {
+            match &self.credential_provider {
+                Some(provider) => provider.credentials(refresh).await.map(Some),
+                None => Ok(None),
+            }
+        }
+
+        /// URL and headers for the next request
+        async fn request_target(&self, refresh: bool) -> Result<(String, Vec<(String, String)>)> {
+            let credentials = self.credentials(refresh).await?;
+            Ok(request_target(
+                &self.url,
+                self.pinned_url.as_deref(),
+                &self.headers,
+                credentials,
+            ))
+        }
+
+        /// Handle a failed request. Returns `true` if the request should be retried.
+        fn retry_request(&mut self, error: &HttpError, retry: &mut RetryState) -> bool {
+            retry_request(
+                error,
+                retry,
+                self.credential_provider.is_some(),
+                &mut self.pinned_url,
+                &self.retry_policy,
+            )
+        }
+
+        /// Send a GET range request, verifying block hashes if configured
+        async fn fetch_range(&mut self, range: &str) -> Result<RangeResponse> {
+            #[cfg(feature = "checksum")]
+            if let Some(hashes) = self.block_hashes.clone() {
+                let block_range = hashes.align(range, self.content_length())?;
+                let response = self.send_range_request(&block_range.header).await?;
+                return hashes.verify_response(&block_range, response);
+            }
+            self.send_range_request(range).await
+        }
+
+        /// Send a GET range request, refreshing credentials and expired pinned URLs
+        /// and retrying transient errors
+        async fn send_range_request(&mut self, range: &str) -> Result<RangeResponse> {
+            let mut retry = RetryState::default();
+            loop {
+                let (url, headers) = self.request_target(retry.refresh).await?;
+                match self
+                    .http_client
+                    .get_range_response(&url, range, &headers)
+                    .await
+                {
+                    Err(e) if self.retry_request(&e, &mut retry) => sleep(retry.delay).await,
+                    result => {
+                        let response = result?;
+                        self.update_response_info(&url, &response);
+                        #[cfg(feature = "checksum")]
+                        self.verify_object(range, &response)?;
+                        return Ok(response);
+                    }
+                }
+            }
+        }
+
+        /// Send concurrent GET range requests with additional request headers,
+        /// verifying block hashes if configured
+        pub(crate) async fn fetch_ranges(
+            &mut self,
+            ranges: &[String],
+            extra_headers: &[(String, String)],
+        ) -> Result<Vec<RangeResponse>> {
+            #[cfg(feature = "checksum")]
+            if let Some(hashes) = self.block_hashes.clone() {
+                let block_ranges = ranges
+                    .iter()
+                    .map(|range| hashes.align(range, self.content_length()))
+                    .collect::<Result<Vec<_>>>()?;
+                let headers: Vec<String> = block_ranges.iter().map(|r| r.header.clone()).collect();
+                let responses = self.send_range_requests(&headers, extra_headers).await?;
+                return block_ranges
+                    .iter()
+                    .zip(responses)
+                    .map(|(range, response)| hashes.verify_response(range, response))
+                    .collect();
+            }
+            self.send_range_requests(ranges, extra_headers).await
+        }
+
+        /// Send concurrent GET range requests
+        async fn send_range_requests(
+            &mut self,
+            ranges: &[String],
+            extra_headers: &[(String, String)],
+        ) -> Result<Vec<RangeResponse>> {
+            let mut retry = RetryState::default();
+            loop {
+                let (url, mut headers) = self.request_target(retry.refresh).await?;
+                for (name, value) in extra_headers {
+                    set_header(&mut headers, name, value);
+                }
+                let http_client = &self.http_client;
+                match try_join_all(
+                    ranges
+                        .iter()
+                        .map(|range| http_client.get_range_response(&url, range, &headers)),
+                )
+                .await
+                {
+                    Err(e) if self.retry_request(&e, &mut retry) => sleep(retry.delay).await,
+                    result => {
+                        let responses = result?;
+                        for response in &responses {
+                            self.update_response_info(&url, response);
+                        }
+                        #[cfg(feature = "checksum")]
+                        for (range, response) in ranges.iter().zip(&responses) {
+                            self.verify_object(range, response)?;
+                        }
+                        return Ok(responses);
+                    }
+                }
+            }
+        }
+
+        /// Check the whole-object checksum if configured
+        #[cfg(feature = "checksum")]
+        fn verify_object(&mut self, range: &str, response: &RangeResponse) -> Result<()> {
+            let total = self.length_info.flatten();
+            match &mut self.object_verifier {
+                Some(verifier) => verifier.update(range, response, total),
+                None => Ok(()),
+            }
+        }
+
+        fn update_response_info(&mut self, url: &str, response: &RangeResponse) {
+            if let Some(final_url) = &response.url {
+                if self.pin_redirects && final_url != url {
+                    self.pinned_url = Some(final_url.clone());
+                }
+            }
+            if let Some(length) = response.total_length() {
+                self.length_info = Some(Some(length));
+            }
+        }
+
         /// Send a HEAD request and return response header value
         pub async fn head_response_header(&self, header: &str) -> Result<Option<String>> {
-            self.http_client
-                .head_response_header(&self.url, header)
+            let (url, headers) = self.request_target(false).await?;
+            match self
+                .http_client
+                .head_response_header(&url, header, &headers)
+                .await
+            {
+                Err(HttpError::HttpStatus(401 | 403)) if self.credential_provider.is_some() => {
+                    let (url, headers) = self.request_target(true).await?;
+                    self.http_client
+                        .head_response_header(&url, header, &headers)
+                        .await
+                }
+                Err(HttpError::HttpStatus(403 | 404)) if self.pinned_url.is_some() => {
+                    // Pinned URL expired
+                    let credentials = self.credentials(false).await?;
+                    let (url, headers) =
+                        request_target(&self.url, None, &self.headers, credentials);
+                    self.http_client
+                        .head_response_header(&url, header, &headers)
+                        .await
+                }
+                result => result,
+            }
+        }
+
+        /// Send a single HEAD request and return the values of all `names`
+        pub async fn head_response_headers(&self, names: &[&str]) -> Result<Vec<Option<String>>> {
+            let (url, headers) = self.request_target(false).await?;
+            match self
+                .http_client
+                .head_response_headers(&url, names, &headers)
                 .await
+            {
+                Err(HttpError::HttpStatus(401 | 403)) if self.credential_provider.is_some() => {
+                    let (url, headers) = self.request_target(true).await?;
+                    self.http_client
+                        .head_response_headers(&url, names, &headers)
+                        .await
+                }
+                Err(HttpError::HttpStatus(403 | 404)) if self.pinned_url.is_some() => {
+                    // Pinned URL expired
+                    let credentials = self.credentials(false).await?;
+                    let (url, headers) =
+                        request_target(&self.url, None, &self.headers, credentials);
+                    self.http_client
+                        .head_response_headers(&url, names, &headers)
+                        .await
+                }
+                result => result,
+            }
+        }
+
+        /// Send a HEAD request and get content-length
+        pub async fn get_content_length(&mut self) -> Result<Option<u64>> {
+            let header_val = self.head_response_header("content-length").await?;
+            let length_info = parse_content_length(header_val.as_deref())?;
+            self.length_info = Some(length_info);
+            Ok(length_info)
         }
     }
 }
 
 pub(crate) mod sync {
     use super::*;
-    use crate::range_client::SyncHttpRangeClient;
-    use crate::HttpError;
+    use crate::range_client::{basic_auth, RangeResponse, SyncHttpRangeClient};
     use bytes::Buf;
     use std::io::{BufRead, Read, Seek, SeekFrom};
+    use std::sync::Arc;
 
     /// HTTP client adapter for HTTP Range requests with a buffer optimized for sequential reading
     pub struct SyncBufferedHttpRangeClient<T: SyncHttpRangeClient> {
@@ -151,6 +949,17 @@ pub(crate) mod sync {
         url: String,
         buffer: HttpRangeBuffer,
         length_info: Option<Option<u64>>,
+        pin_redirects: bool,
+        /// Final URL of a redirected request
+        pinned_url: Option<String>,
+        /// Additional request headers
+        headers: Vec<(String, String)>,
+        credential_provider: Option<Arc<dyn CredentialProvider>>,
+        retry_policy: RetryPolicy,
+        #[cfg(feature = "checksum")]
+        block_hashes: Option<Arc<BlockHashes>>,
+        #[cfg(feature = "checksum")]
+        object_verifier: Option<ObjectVerifier>,
     }
 
     impl<T: SyncHttpRangeClient> SyncBufferedHttpRangeClient<T> {
@@ -160,6 +969,15 @@ pub(crate) mod sync {
                 url: url.to_string(),
                 buffer: HttpRangeBuffer::new(),
                 length_info: None,
+                pin_redirects: false,
+                pinned_url: None,
+                headers: Vec::new(),
+                credential_provider: None,
+                retry_policy: RetryPolicy::default(),
+                #[cfg(feature = "checksum")]
+                block_hashes: None,
+                #[cfg(feature = "checksum")]
+                object_verifier: None,
             }
         }
 
@@ -174,6 +992,27 @@ pub(crate) mod sync {
             self
         }
 
+        /// Set a request header sent with every request.
+        pub fn set_header(&mut self, name: &str, value: &str) {
+            set_header(&mut self.headers, name, value);
+        }
+
+        /// Set a request header sent with every request.
+        pub fn with_header(&mut self, name: &str, value: &str) -> &mut Self {
+            self.set_header(name, value);
+            self
+        }
+
+        /// Use HTTP Bearer authentication.
+        pub fn bearer_auth(&mut self, token: &str) -> &mut Self {
+            self.with_header("Authorization", &format!("Bearer {token}"))
+        }
+
+        /// Use HTTP Basic authentication.
+        pub fn basic_auth(&mut self, username: &str, password: Option<&str>) -> &mut Self {
+            self.with_header("Authorization", &basic_auth(username, password))
+        }
+
         /// Get `length` bytes with offset `begin`.
         pub fn get_range(&mut self, begin: usize, length: usize) -> Result<&[u8]> {
             let slice_len = if let Some((range_begin, range_length)) =
@@ -181,9 +1020,9 @@ pub(crate) mod sync {
             {
                 self.buffer.http_stats.log(begin, range_length, length);
                 let range = self.buffer.range(range_begin, range_length);
-                let bytes = self.http_client.get_range(&self.url, &range)?;
-                let eff_len = bytes.len();
-                self.buffer.buf.put(bytes);
+                let response = self.fetch_range(&range)?;
+                let eff_len = response.data.len();
+                self.buffer.buf.put(response.data);
                 min(range_begin - begin + eff_len, length)
             } else {
                 length
@@ -199,22 +1038,456 @@ pub(crate) mod sync {
             self.get_range(self.buffer.offset, length)
         }
 
+        /// Read `N` bytes at the current position.
+        fn read_array<const N: usize>(&mut self) -> Result<[u8; N]> {
+            to_array(self.get_bytes(N)?)
+        }
+
+        /// Read an unsigned 8 bit integer.
+        pub fn get_u8(&mut self) -> Result<u8> {
+            Ok(self.read_array::<1>()?[0])
+        }
+
+        /// Read a signed 8 bit integer.
+        pub fn get_i8(&mut self) -> Result<i8> {
+            Ok(self.read_array::<1>()?[0] as i8)
+        }
+
+        /// Read an unsigned 16 bit integer in byte order `B`.
+        pub fn get_u16<B: ByteOrder>(&mut self) -> Result<u16> {
+            Ok(B::read_u16(&self.read_array::<2>()?))
+        }
+
+        /// Read a signed 16 bit integer in byte order `B`.
+        pub fn get_i16<B: ByteOrder>(&mut self) -> Result<i16> {
+            Ok(B::read_i16(&self.read_array::<2>()?))
+        }
+
+        /// Read an unsigned 32 bit integer in byte order `B`.
+        pub fn get_u32<B: ByteOrder>(&mut self) -> Result<u32> {
+            Ok(B::read_u32(&self.read_array::<4>()?))
+        }
+
+        /// Read a signed 32 bit integer in byte order `B`.
+        pub fn get_i32<B: ByteOrder>(&mut self) -> Result<i32> {
+            Ok(B::read_i32(&self.read_array::<4>()?))
+        }
+
+        /// Read an unsigned 64 bit integer in byte order `B`.
+        pub fn get_u64<B: ByteOrder>(&mut self) -> Result<u64> {
+            Ok(B::read_u64(&self.read_array::<8>()?))
+        }
+
+        /// Read a signed 64 bit integer in byte order `B`.
+        pub fn get_i64<B: ByteOrder>(&mut self) -> Result<i64> {
+            Ok(B::read_i64(&self.read_array::<8>()?))
+        }
+
+        /// Read a IEEE754 single-precision floating point number in byte order `B`.
+        pub fn get_f32<B: ByteOrder>(&mut self) -> Result<f32> {
+            Ok(B::read_f32(&self.read_array::<4>()?))
+        }
+
+        /// Read a IEEE754 double-precision floating point number in byte order `B`.
+        pub fn get_f64<B: ByteOrder>(&mut self) -> Result<f64> {
+            Ok(B::read_f64(&self.read_array::<8>()?))
+        }
+
+        /// Read a plain old data value, e.g. a `#[repr(C)]` struct, in native byte order.
+        #[cfg(feature = "bytemuck")]
+        pub fn get_pod<P: bytemuck::Pod>(&mut self) -> Result<P> {
+            let size = std::mem::size_of::<P>();
+            let bytes = self.get_bytes(size)?;
+            if bytes.len() < size {
+                return Err(unexpected_end(size));
+            }
+            Ok(bytemuck::pod_read_unaligned(bytes))
+        }
+
+        /// Set maximal request size for merged ranges.
+        pub fn set_max_req_size(&mut self, size: usize) {
+            self.buffer.max_req_size = size;
+        }
+
+        /// Set maximal request size for merged ranges.
+        pub fn max_req_size(&mut self, size: usize) -> &mut Self {
+            self.set_max_req_size(size);
+            self
+        }
+
+        /// Get multiple `(begin, length)` ranges with as few requests as possible.
+        ///
+        /// Ranges with gaps up to `max_gap` bytes are merged into a single request.
+        /// Results are returned in the order of `ranges`.
+        pub fn get_ranges_coalesced(
+            &mut self,
+            ranges: &[(usize, usize)],
+            max_gap: usize,
+        ) -> Result<Vec<Bytes>> {
+            let buffered: Vec<Option<Bytes>> = ranges
+                .iter()
+                .map(|&(begin, length)| self.buffer.get_buffered(begin, length))
+                .collect();
+            let skip: Vec<bool> = buffered.iter().map(Option::is_some).collect();
+            let mut results: Vec<Bytes> = buffered
+                .into_iter()
+                .map(Option::unwrap_or_default)
+                .collect();
+            let requests = coalesce_ranges(ranges, &skip, max_gap, self.buffer.max_req_size);
+            let mut responses = Vec::with_capacity(requests.len());
+            for req in &requests {
+                self.buffer
+                    .http_stats
+                    .log(req.begin, req.length, req.length);
+                let range = self.buffer.range(req.begin, req.length);
+                responses.push(self.fetch_range(&range)?.data);
+            }
+            split_coalesced(ranges, &requests, responses, &mut results);
+            Ok(results)
+        }
+
+        /// Get the last `length` bytes with a suffix range request (`bytes=-length`).
+        ///
+        /// The total length of the file is taken from the `Content-Range` response header.
+        pub fn get_suffix(&mut self, length: usize) -> Result<&[u8]> {
+            // `bytes=-0` is unsatisfiable
+            if length == 0 {
+                return Ok(&[]);
+            }
+            #[cfg(feature = "checksum")]
+            if self.block_hashes.is_some() && self.length_info.is_none() {
+                self.get_content_length()?;
+            }
+            if let Some(Some(total)) = self.length_info {
+                let begin = (total as usize).saturating_sub(length);
+                return self.get_range(begin, total as usize - begin);
+            }
+            let range = format!("bytes=-{length}");
+            let response = self.fetch_range(&range)?;
+            let begin = if let Some(begin) = response.range_begin() {
+                begin as usize
+            } else {
+                // Backend doesn't report Content-Range
+                let total = self.get_content_length()?.ok_or_else(|| {
+                    HttpError::HttpError("Suffix range failed - no content-length received".into())
+                })?;
+                (total as usize).saturating_sub(response.data.len())
+            };
+            self.buffer
+                .http_stats
+                .log(begin, response.data.len(), length);
+            self.buffer.replace(begin, response.data);
+            Ok(&self.buffer.buf[..])
+        }
+
+        /// Get all bytes from offset `begin` to the end of the file (`bytes=begin-`).
+        pub fn get_from(&mut self, begin: usize) -> Result<&[u8]> {
+            let range = format!("bytes={begin}-");
+            let response = self.fetch_range(&range)?;
+            self.buffer
+                .http_stats
+                .log(begin, response.data.len(), response.data.len());
+            self.buffer.replace(begin, response.data);
+            Ok(&self.buffer.buf[..])
+        }
+
+        /// Total length of the file, if known from a previous response.
+        pub fn content_length(&self) -> Option<u64> {
+            self.length_info.flatten()
+        }
+
+        /// Offset following the last read bytes.
+        pub fn position(&self) -> usize {
+            self.buffer.offset
+        }
+
+        /// Reuse the final URL of redirected requests for subsequent requests.
+        ///
+        /// Falls back to the original URL when the pinned URL responds with 403 or 404.
+        pub fn set_pin_redirects(&mut self, pin: bool) {
+            self.pin_redirects = pin;
+            if !pin {
+                self.pinned_url = None;
+            }
+        }
+
+        /// Reuse the final URL of redirected requests for subsequent requests.
+        ///
+        /// `Authorization`, `Proxy-Authorization`, `Cookie` and credential provider
+        /// headers are not sent to a pinned URL of another origin.
+        pub fn pin_redirects(&mut self, pin: bool) -> &mut Self {
+            self.set_pin_redirects(pin);
+            self
+        }
+
+        /// URL used for requests, after resolving redirects
+        pub fn request_url(&self) -> &str {
+            self.pinned_url.as_deref().unwrap_or(&self.url)
+        }
+
+        /// Set provider for expiring credentials or presigned URLs.
+        pub fn set_credential_provider(&mut self, provider: impl CredentialProvider + 'static) {
+            self.credential_provider = Some(Arc::new(provider));
+        }
+
+        /// Set provider for expiring credentials or presigned URLs.
+        pub fn credential_provider(
+            &mut self,
+            provider: impl CredentialProvider + 'static,
+        ) -> &mut Self {
+            self.set_credential_provider(provider);
+            self
+        }
+
+        /// Set retry policy for range requests failing with transient errors.
+        pub fn set_retry_policy(&mut self, policy: RetryPolicy) {
+            self.retry_policy = policy;
+        }
+
+        /// Set retry policy for range requests failing with transient errors.
+        pub fn retry_policy(&mut self, policy: RetryPolicy) -> &mut Self {
+            self.set_retry_policy(policy);
+            self
+        }
+
+        /// Verify fetched ranges against expected block hashes.
+        ///
+        /// Requests are widened to block boundaries. Download chunk sizes should be
+        /// multiples of the block size to avoid overlapping requests.
+        #[cfg(feature = "checksum")]
+        pub fn set_block_hashes(&mut self, hashes: BlockHashes) {
+            self.block_hashes = Some(Arc::new(hashes));
+        }
+
+        /// Verify fetched ranges against expected block hashes.
+        #[cfg(feature = "checksum")]
+        pub fn block_hashes(&mut self, hashes: BlockHashes) -> &mut Self {
+            self.set_block_hashes(hashes);
+            self
+        }
+
+        /// Verify the whole-object checksum when the file has been read to the end.
+        ///
+        /// Only ranges read sequentially from the start of the file are hashed.
+        #[cfg(feature = "checksum")]
+        pub fn set_expected_checksum(&mut self, checksum: Checksum) {
+            self.object_verifier = Some(ObjectVerifier::new(checksum));
+        }
+
+        /// Verify the whole-object checksum when the file has been read to the end.
+        #[cfg(feature = "checksum")]
+        pub fn expected_checksum(&mut self, checksum: Checksum) -> &mut Self {
+            self.set_expected_checksum(checksum);
+            self
+        }
+
+        /// Credentials from the credential provider
+        fn credentials(&self, refresh: bool) -> Result<Option<Credentials>> {
+            self.credential_provider
+                .as_ref()
+                .map(|provider| CredentialProvider::credentials(provider, refresh))
+                .transpose()
+        }
+
+        /// URL and headers for the next request
+        fn request_target(&self, refresh: bool) -> Result<(String, Vec<(String, String)>)> {
+            let credentials = self.credentials(refresh)?;
+            Ok(request_target(
+                &self.url,
+                self.pinned_url.as_deref(),
+                &self.headers,
+                credentials,
+            ))
+        }
+
+        /// Handle a failed request. Returns `true` if the request should be retried.
+        fn retry_request(&mut self, error: &HttpError, retry: &mut RetryState) -> bool {
+            retry_request(
+                error,
+                retry,
+                self.credential_provider.is_some(),
+                &mut self.pinned_url,
+                &self.retry_policy,
+            )
+        }
+
+        /// Send a GET range request, verifying block hashes if configured
+        fn fetch_range(&mut self, range: &str) -> Result<RangeResponse> {
+            #[cfg(feature = "checksum")]
+            if let Some(hashes) = self.block_hashes.clone() {
+                let block_range = hashes.align(range, self.content_length())?;
+                let response = self.send_range_request(&block_range.header)?;
+                return hashes.verify_response(&block_range, response);
+            }
+            self.send_range_request(range)
+        }
+
+        /// Send a GET range request, refreshing credentials and expired pinned URLs
+        /// and retrying transient errors
+        fn send_range_request(&mut self, range: &str) -> Result<RangeResponse> {
+            let mut retry = RetryState::default();
+            loop {
+                let (url, headers) = self.request_target(retry.refresh)?;
+                match self.http_client.get_range_response(&url, range, &headers) {
+                    Err(e) if self.retry_request(&e, &mut retry) => std::thread::sleep(retry.delay),
+                    result => {
+                        let response = result?;
+                        self.update_response_info(&url, &response);
+                        #[cfg(feature = "checksum")]
+                        self.verify_object(range, &response)?;
+                        return Ok(response);
+                    }
+                }
+            }
+        }
+
+        /// Send concurrent GET range requests from scoped threads, verifying block
+        /// hashes if configured
+        pub(crate) fn fetch_ranges(
+            &mut self,
+            ranges: &[String],
+            extra_headers: &[(String, String)],
+        ) -> Result<Vec<RangeResponse>>
+        where
+            T: Sync,
+        {
+            #[cfg(feature = "checksum")]
+            if let Some(hashes) = self.block_hashes.clone() {
+                let block_ranges = ranges
+                    .iter()
+                    .map(|range| hashes.align(range, self.content_length()))
+                    .collect::<Result<Vec<_>>>()?;
+                let headers: Vec<String> = block_ranges.iter().map(|r| r.header.clone()).collect();
+                let responses = self.send_range_requests(&headers, extra_headers)?;
+                return block_ranges
+                    .iter()
+                    .zip(responses)
+                    .map(|(range, response)| hashes.verify_response(range, response))
+                    .collect();
+            }
+            self.send_range_requests(ranges, extra_headers)
+        }
+
+        /// Send concurrent GET range requests from scoped threads
+        fn send_range_requests(
+            &mut self,
+            ranges: &[String],
+            extra_headers: &[(String, String)],
+        ) -> Result<Vec<RangeResponse>>
+        where
+            T: Sync,
+        {
+            let mut retry = RetryState::default();
+            loop {
+                let (url, mut headers) = self.request_target(retry.refresh)?;
+                for (name, value) in extra_headers {
+                    set_header(&mut headers, name, value);
+                }
+                let (http_client, url_ref, headers) = (&self.http_client, &url, &headers);
+                let result = std::thread::scope(|scope| {
+                    let requests: Vec<_> = ranges
+                        .iter()
+                        .map(|range| {
+                            scope.spawn(move || {
+                                http_client.get_range_response(url_ref, range, headers)
+                            })
+                        })
+                        .collect();
+                    requests
+                        .into_iter()
+                        .map(|request| request.join().expect("range request panicked"))
+                        .collect::<Result<Vec<_>>>()
+                });
+                match result {
+                    Err(e) if self.retry_request(&e, &mut retry) => std::thread::sleep(retry.delay),
+                    result => {
+                        let responses = result?;
+                        for response in &responses {
+                            self.update_response_info(&url, response);
+                        }
+                        #[cfg(feature = "checksum")]
+                        for (range, response) in ranges.iter().zip(&responses) {
+                            self.verify_object(range, response)?;
+                        }
+                        return Ok(responses);
+                    }
+                }
+            }
+        }
+
+        /// Check the whole-object checksum if configured
+        #[cfg(feature = "checksum")]
+        fn verify_object(&mut self, range: &str, response: &RangeResponse) -> Result<()> {
+            let total = self.length_info.flatten();
+            match &mut self.object_verifier {
+                Some(verifier) => verifier.update(range, response, total),
+                None => Ok(()),
+            }
+        }
+
+        fn update_response_info(&mut self, url: &str, response: &RangeResponse) {
+            if let Some(final_url) = &response.url {
+                if self.pin_redirects && final_url != url {
+                    self.pinned_url = Some(final_url.clone());
+                }
+            }
+            if let Some(length) = response.total_length() {
+                self.length_info = Some(Some(length));
+            }
+        }
+
         /// Send a HEAD request and return response header value
         pub fn head_response_header(&self, header: &str) -> Result<Option<String>> {
-            self.http_client.head_response_header(&self.url, header)
+            let (url, headers) = self.request_target(false)?;
+            match self
+                .http_client
+                .head_response_header(&url, header, &headers)
+            {
+                Err(HttpError::HttpStatus(401 | 403)) if self.credential_provider.is_some() => {
+                    let (url, headers) = self.request_target(true)?;
+                    self.http_client
+                        .head_response_header(&url, header, &headers)
+                }
+                Err(HttpError::HttpStatus(403 | 404)) if self.pinned_url.is_some() => {
+                    // Pinned URL expired
+                    let credentials = self.credentials(false)?;
+                    let (url, headers) =
+                        request_target(&self.url, None, &self.headers, credentials);
+                    self.http_client
+                        .head_response_header(&url, header, &headers)
+                }
+                result => result,
+            }
+        }
+
+        /// Send a single HEAD request and return the values of all `names`
+        pub fn head_response_headers(&self, names: &[&str]) -> Result<Vec<Option<String>>> {
+            let (url, headers) = self.request_target(false)?;
+            match self
+                .http_client
+                .head_response_headers(&url, names, &headers)
+            {
+                Err(HttpError::HttpStatus(401 | 403)) if self.credential_provider.is_some() => {
+                    let (url, headers) = self.request_target(true)?;
+                    self.http_client
+                        .head_response_headers(&url, names, &headers)
+                }
+                Err(HttpError::HttpStatus(403 | 404)) if self.pinned_url.is_some() => {
+                    // Pinned URL expired
+                    let credentials = self.credentials(false)?;
+                    let (url, headers) =
+                        request_target(&self.url, None, &self.headers, credentials);
+                    self.http_client
+                        .head_response_headers(&url, names, &headers)
+                }
+                result => result,
+            }
         }
 
         /// Send a HEAD request and get content-length
         pub fn get_content_length(&mut self) -> Result<Option<u64>> {
             let header_val = self.head_response_header("content-length")?;
-            let length_info = if let Some(val) = header_val {
-                let length = u64::from_str(&val).map_err(|_| {
-                    HttpError::HttpError("Invalid content-length received".to_string())
-                })?;
-                Some(length)
-            } else {
-                None
-            };
+            let length_info = parse_content_length(header_val.as_deref())?;
             self.length_info = Some(length_info);
             Ok(length_info)
         }
@@ -227,7 +1500,7 @@ pub(crate) mod sync {
                 HttpError::HttpStatus(416) => {
                     std::io::Error::from(std::io::ErrorKind::UnexpectedEof)
                 }
-                e => std::io::Error::new(std::io::ErrorKind::Other, e.to_string()),
+                e => std::io::Error::other(e.to_string()),
             })?;
             bytes.copy_to_slice(&mut buf[0..bytes.len()]);
             Ok(length)
@@ -237,15 +1510,17 @@ pub(crate) mod sync {
     impl<T: SyncHttpRangeClient> BufRead for SyncBufferedHttpRangeClient<T> {
         fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
             if self.buffer.offset >= self.buffer.tail() || self.buffer.offset < self.buffer.head {
+                let begin = self.buffer.offset;
                 let res = self.get_bytes(self.buffer.min_req_size);
                 if let Some(HttpError::HttpStatus(416)) = res.as_ref().err() {
                     // An empty buffer indicates that the stream has reached EOF
                     return Ok(&[]);
                 }
-                res.map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))?;
-                self.buffer.offset = self.buffer.head;
+                res.map_err(|e| std::io::Error::other(e.to_string()))?;
+                self.buffer.offset = begin;
             }
-            Ok(&self.buffer.buf[..])
+            let lower = self.buffer.offset - self.buffer.head;
+            Ok(&self.buffer.buf[lower..])
         }
 
         fn consume(&mut self, amt: usize) {
@@ -263,16 +1538,15 @@ pub(crate) mod sync {
                 SeekFrom::End(p) => {
                     if self.length_info.is_none() {
                         // Read content-length with HEAD request
-                        let _ = self.get_content_length().map_err(|e| {
-                            std::io::Error::new(std::io::ErrorKind::Other, e.to_string())
-                        })?;
+                        let _ = self
+                            .get_content_length()
+                            .map_err(|e| std::io::Error::other(e.to_string()))?;
                     }
                     if let Some(Some(length)) = self.length_info {
                         self.buffer.offset = length.saturating_add_signed(p) as usize;
                         Ok(self.buffer.offset as u64)
                     } else {
-                        Err(std::io::Error::new(
-                            std::io::ErrorKind::Other,
+                        Err(std::io::Error::other(
                             "SeekFrom::End failed - no content-length received",
                         ))
                     }
@@ -377,6 +1651,7 @@ mod test_async {
 
 #[cfg(test)]
 #[cfg(any(feature = "reqwest-sync", feature = "ureq-sync"))]
+#[allow(clippy::unused_io_amount, clippy::seek_from_current)]
 mod test_sync {
     #[cfg(feature = "reqwest-sync")]
     use crate::HttpReader;
@@ -559,3 +1834,525 @@ mod test_sync {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test_coalesce {
+    use super::*;
+    use crate::mock_client::MockHttpRangeClient;
+    use crate::{AsyncBufferedHttpRangeClient, SyncBufferedHttpRangeClient};
+
+    #[test]
+    fn merge_ranges() {
+        let ranges = [(100, 10), (0, 10), (15, 5), (112, 4), (50, 0)];
+        let requests = coalesce_ranges(&ranges, &[false; 5], 5, 1024);
+        assert_eq!(
+            requests,
+            vec![
+                CoalescedRange {
+                    begin: 0,
+                    length: 20,
+                    members: vec![1, 2]
+                },
+                CoalescedRange {
+                    begin: 100,
+                    length: 16,
+                    members: vec![0, 3]
+                },
+            ]
+        );
+        // Respect max request size
+        let requests = coalesce_ranges(&ranges, &[false; 5], 5, 16);
+        assert_eq!(requests.len(), 3);
+    }
+
+    #[test]
+    fn sync_coalesced() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000);
+        let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        let ranges = [(500, 4), (10, 2), (505, 3), (990, 20), (0, 0)];
+        let bytes = client.get_ranges_coalesced(&ranges, 8)?;
+        assert_eq!(bytes[0], [249, 250, 0, 1][..]);
+        assert_eq!(bytes[1], [10, 11][..]);
+        assert_eq!(bytes[2], [3, 4, 5][..]);
+        assert_eq!(bytes[3].len(), 10);
+        assert!(bytes[4].is_empty());
+        assert_eq!(
+            backend.requests(),
+            ["GET bytes=10-11", "GET bytes=500-507", "GET bytes=990-1009"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_coalesced() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000);
+        let mut client = AsyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        client.min_req_size(64).get_range(0, 4).await?;
+        let ranges = [(700, 4), (20, 2), (600, 3)];
+        let bytes = client.get_ranges_coalesced(&ranges, 100).await?;
+        assert_eq!(bytes[0], [198, 199, 200, 201][..]);
+        assert_eq!(bytes[1], [20, 21][..]);
+        assert_eq!(bytes[2], [98, 99, 100][..]);
+        // (20, 2) is served from buffer
+        assert_eq!(backend.requests(), ["GET bytes=0-63", "GET bytes=600-703"]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_suffix {
+    use crate::mock_client::MockHttpRangeClient;
+    use crate::{AsyncBufferedHttpRangeClient, Result, SyncBufferedHttpRangeClient};
+    use std::io::{Seek, SeekFrom};
+
+    #[test]
+    fn sync_suffix() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000);
+        let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        assert_eq!(client.get_suffix(4)?, [243, 244, 245, 246]);
+        assert_eq!(client.content_length(), Some(1000));
+        // From buffer
+        assert_eq!(client.get_range(997, 2)?, [244, 245]);
+        // SeekFrom::End without HEAD request
+        assert_eq!(client.seek(SeekFrom::End(-10)).unwrap(), 990);
+        assert_eq!(client.get_from(998)?, [245, 246]);
+        assert_eq!(backend.requests(), ["GET bytes=-4", "GET bytes=998-"]);
+
+        let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        assert!(client.get_suffix(0)?.is_empty());
+        assert_eq!(backend.requests().len(), 2);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_suffix() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000);
+        let mut client = AsyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        assert!(client.get_suffix(0).await?.is_empty());
+        assert!(backend.requests().is_empty());
+        assert_eq!(client.get_suffix(2000).await?.len(), 1000);
+        assert_eq!(client.get_from(990).await?.len(), 10);
+        assert_eq!(
+            client.get_bytes(1).await.unwrap_err().to_string(),
+            "http status 416"
+        );
+        let mut client = AsyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        client.get_range(0, 4).await?;
+        assert_eq!(client.content_length(), Some(1000));
+        // Served from buffer
+        assert_eq!(client.get_suffix(1).await?, [246]);
+        assert_eq!(backend.requests()[3..], ["GET bytes=0-1023"]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_redirect {
+    use crate::mock_client::MockHttpRangeClient;
+    use crate::{AsyncBufferedHttpRangeClient, Result, SyncBufferedHttpRangeClient};
+
+    #[test]
+    fn sync_pin_redirect() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000).redirect_to("https://cdn/file");
+        let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "https://short/file");
+        client.pin_redirects(true).min_req_size(10);
+        client.get_range(0, 10)?;
+        assert_eq!(client.request_url(), "https://cdn/file");
+        client.get_range(100, 10)?;
+        assert_eq!(
+            backend.request_urls(),
+            ["https://short/file", "https://cdn/file"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_pinned_url_expired() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000).redirect_to("https://cdn/file");
+        let mut client = AsyncBufferedHttpRangeClient::with(backend.clone(), "https://short/file");
+        client.pin_redirects(true).min_req_size(10);
+        client.get_range(0, 10).await?;
+        assert_eq!(client.request_url(), "https://cdn/file");
+        // Pinned URL expires, the original URL redirects to a new location
+        backend.expire("https://cdn/file");
+        let backend = backend.redirect_to("https://cdn2/file");
+        let bytes = client.get_range(100, 4).await?;
+        assert_eq!(bytes, [100, 101, 102, 103]);
+        assert_eq!(client.request_url(), "https://cdn2/file");
+        assert_eq!(
+            backend.request_urls(),
+            [
+                "https://short/file",
+                "https://cdn/file",
+                "https://short/file"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sync_pinned_url_other_origin() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000).redirect_to("https://cdn/file");
+        let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "https://short/file");
+        client
+            .pin_redirects(true)
+            .min_req_size(10)
+            .bearer_auth("token")
+            .with_header("Accept", "*/*");
+        client.get_range(0, 10)?;
+        client.get_range(100, 10)?;
+        let headers = backend.request_headers();
+        assert!(headers[0].iter().any(|(name, _)| name == "Authorization"));
+        assert_eq!(headers[1], [("Accept".to_string(), "*/*".to_string())]);
+
+        // Same origin
+        let backend = MockHttpRangeClient::with_len(1000).redirect_to("https://SHORT/v2/file");
+        let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "https://short/file");
+        client
+            .pin_redirects(true)
+            .min_req_size(10)
+            .bearer_auth("token");
+        client.get_range(0, 10)?;
+        client.get_range(100, 10)?;
+        assert!(backend.request_headers()[1]
+            .iter()
+            .any(|(name, _)| name == "Authorization"));
+        Ok(())
+    }
+
+    #[test]
+    fn sync_head_pinned_url_expired() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000).redirect_to("https://cdn/file");
+        let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "https://short/file");
+        client.pin_redirects(true).min_req_size(10);
+        client.get_range(0, 10)?;
+        backend.expire("https://cdn/file");
+        assert_eq!(client.get_content_length()?, Some(1000));
+        assert_eq!(
+            backend.request_urls(),
+            [
+                "https://short/file",
+                "https://cdn/file",
+                "https://short/file"
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn no_pinning_by_default() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000).redirect_to("https://cdn/file");
+        let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "https://short/file");
+        client.min_req_size(10).get_range(0, 10)?;
+        client.get_range(100, 10)?;
+        assert_eq!(
+            backend.request_urls(),
+            ["https://short/file", "https://short/file"]
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_headers {
+    use crate::mock_client::MockHttpRangeClient;
+    use crate::{AsyncBufferedHttpRangeClient, Result, SyncBufferedHttpRangeClient};
+
+    fn header(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    #[test]
+    fn sync_headers() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000);
+        let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        client
+            .with_header("X-Api-Key", "secret")
+            .bearer_auth("token1")
+            .bearer_auth("token2");
+        client.get_range(0, 10)?;
+        client.get_content_length()?;
+        let expected = vec![
+            header("X-Api-Key", "secret"),
+            header("Authorization", "Bearer token2"),
+        ];
+        assert_eq!(backend.request_headers(), [expected.clone(), expected]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_headers() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000);
+        let mut client = AsyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        client.basic_auth("user", Some("pass"));
+        client
+            .get_ranges_coalesced(&[(0, 10), (500, 10)], 0)
+            .await?;
+        assert_eq!(
+            backend.request_headers(),
+            [
+                vec![header("Authorization", "Basic dXNlcjpwYXNz")],
+                vec![header("Authorization", "Basic dXNlcjpwYXNz")]
+            ]
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_credentials {
+    use crate::mock_client::MockHttpRangeClient;
+    use crate::{
+        AsyncBufferedHttpRangeClient, AsyncCredentialProvider, Credentials, Result,
+        SyncBufferedHttpRangeClient,
+    };
+    use async_trait::async_trait;
+    use std::io::Read;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn sync_token_refresh() -> std::io::Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000);
+        backend.require_header("Authorization", "Bearer token1");
+        let mut reader = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        let version = Arc::new(AtomicUsize::new(1));
+        let token_version = version.clone();
+        reader.min_req_size(16).credential_provider(move |refresh| {
+            if refresh {
+                token_version.fetch_add(1, Ordering::SeqCst);
+            }
+            let token = format!("token{}", token_version.load(Ordering::SeqCst));
+            Ok(Credentials::bearer(&token))
+        });
+        let mut bytes = [0; 16];
+        reader.read_exact(&mut bytes)?;
+        // Token expires
+        backend.require_header("Authorization", "Bearer token2");
+        reader.read_exact(&mut bytes)?;
+        assert_eq!(bytes[0], 16);
+        assert_eq!(version.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            backend.requests(),
+            ["GET bytes=0-15", "GET bytes=16-31", "GET bytes=16-31"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_presigned_url() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000);
+        let mut client = AsyncBufferedHttpRangeClient::with(backend.clone(), "https://s3/file");
+        let signature = Arc::new(AtomicUsize::new(1));
+        let provider_signature = signature.clone();
+        client.min_req_size(16).credential_provider(move |refresh| {
+            if refresh {
+                provider_signature.fetch_add(1, Ordering::SeqCst);
+            }
+            let sig = provider_signature.load(Ordering::SeqCst);
+            Ok(Credentials::url(&format!("https://s3/file?sig={sig}")))
+        });
+        client.get_range(0, 4).await?;
+        backend.expire("https://s3/file?sig=1");
+        client
+            .get_ranges_coalesced(&[(200, 4), (900, 4)], 0)
+            .await?;
+        assert_eq!(
+            backend.request_urls(),
+            [
+                "https://s3/file?sig=1",
+                "https://s3/file?sig=1",
+                "https://s3/file?sig=2",
+                "https://s3/file?sig=2"
+            ]
+        );
+        // Give up after refreshing once
+        backend.expire("https://s3/file?sig=2");
+        backend.expire("https://s3/file?sig=3");
+        let result = client.get_range(500, 4).await;
+        assert_eq!(result.unwrap_err().to_string(), "http status 403");
+        Ok(())
+    }
+
+    /// Token service with an async API
+    struct TokenService {
+        version: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl AsyncCredentialProvider for TokenService {
+        async fn credentials(&self, refresh: bool) -> Result<Credentials> {
+            if refresh {
+                self.version.fetch_add(1, Ordering::SeqCst);
+            }
+            tokio::task::yield_now().await;
+            let token = format!("token{}", self.version.load(Ordering::SeqCst));
+            Ok(Credentials::bearer(&token))
+        }
+    }
+
+    #[tokio::test]
+    async fn async_credential_provider() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000);
+        backend.require_header("Authorization", "Bearer token2");
+        let mut client = AsyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        client.credential_provider(TokenService {
+            version: AtomicUsize::new(1),
+        });
+        assert_eq!(client.get_range(0, 4).await?, [0, 1, 2, 3]);
+        assert_eq!(client.get_content_length().await?, Some(1000));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_retry {
+    use crate::mock_client::MockHttpRangeClient;
+    use crate::{AsyncBufferedHttpRangeClient, Result, RetryPolicy, SyncBufferedHttpRangeClient};
+    use std::time::Duration;
+
+    fn policy(max_retries: u32) -> RetryPolicy {
+        RetryPolicy::exponential(max_retries)
+            .with_backoff(Duration::from_millis(1), Duration::from_millis(5))
+    }
+
+    #[test]
+    fn sync_retry() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000);
+        let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        client.min_req_size(16).retry_policy(policy(2));
+        backend.fail_next(&[503, 429]);
+        assert_eq!(client.get_range(0, 4)?, [0, 1, 2, 3]);
+        assert_eq!(backend.requests().len(), 3);
+
+        backend.fail_next(&[500, 500, 500]);
+        let result = client.get_range(100, 4);
+        assert_eq!(result.unwrap_err().to_string(), "http status 500");
+        // Not retryable
+        backend.fail_next(&[404]);
+        assert!(client.get_range(200, 4).is_err());
+        assert_eq!(backend.requests().len(), 7);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_retry() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000);
+        let mut client = AsyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        client.retry_policy(policy(1));
+        backend.fail_next(&[502]);
+        let bytes = client.get_ranges_coalesced(&[(0, 4), (900, 4)], 0).await?;
+        assert_eq!(&bytes[1][..], [147, 148, 149, 150]);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_typed {
+    use crate::mock_client::MockHttpRangeClient;
+    use crate::AsyncBufferedHttpRangeClient;
+    use crate::{Result, SyncBufferedHttpRangeClient};
+    use byteorder::{BigEndian, LittleEndian};
+
+    #[test]
+    fn sync_get_numbers() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000);
+        let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        client.min_req_size(16);
+        assert_eq!(client.get_u8()?, 0);
+        assert_eq!(client.get_u16::<LittleEndian>()?, 0x0201);
+        assert_eq!(client.get_u32::<BigEndian>()?, 0x03040506);
+        assert_eq!(client.get_i64::<LittleEndian>()?, 0x0e0d0c0b0a090807);
+        assert_eq!(backend.requests(), ["GET bytes=0-15"]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_get_numbers() -> Result<()> {
+        let data = [&1.5f64.to_le_bytes()[..], &(-2.0f64).to_be_bytes(), &[0, 0]];
+        let backend = MockHttpRangeClient::new(data.concat());
+        let mut client = AsyncBufferedHttpRangeClient::with(backend, "mock");
+        client.min_req_size(8);
+        assert_eq!(client.get_f64::<LittleEndian>().await?, 1.5);
+        assert_eq!(client.get_f64::<BigEndian>().await?, -2.0);
+        let result = client.get_u32::<BigEndian>().await;
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "http error `unexpected end of data reading 4 bytes`"
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "bytemuck")]
+    #[test]
+    fn get_pod() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000);
+        let mut client = SyncBufferedHttpRangeClient::with(backend, "mock");
+        let header: [u16; 2] = client.get_pod()?;
+        assert_eq!(
+            header,
+            [u16::from_ne_bytes([0, 1]), u16::from_ne_bytes([2, 3])]
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test_lines {
+    use crate::mock_client::MockHttpRangeClient;
+    use crate::{AsyncBufferedHttpRangeClient, Result, SyncBufferedHttpRangeClient};
+    use futures_util::TryStreamExt;
+    use std::io::{BufRead, Seek, SeekFrom};
+
+    #[tokio::test]
+    async fn async_read_until() -> Result<()> {
+        let backend = MockHttpRangeClient::new(&b"fgb\x03fgb\x00end"[..]);
+        let mut client = AsyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        client.min_req_size(3);
+        let mut bytes = Vec::new();
+        assert_eq!(client.read_until(3, &mut bytes).await?, 4);
+        assert_eq!(bytes, b"fgb\x03");
+        let mut bytes = Vec::new();
+        assert_eq!(client.read_until(0, &mut bytes).await?, 4);
+        assert_eq!(bytes, b"fgb\x00");
+        let mut bytes = Vec::new();
+        assert_eq!(client.read_until(0, &mut bytes).await?, 3);
+        assert_eq!(bytes, b"end");
+        assert_eq!(client.read_until(0, &mut bytes).await?, 0);
+        assert_eq!(
+            backend.requests(),
+            [
+                "GET bytes=0-2",
+                "GET bytes=3-5",
+                "GET bytes=6-8",
+                "GET bytes=9-11"
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_lines() -> Result<()> {
+        let backend = MockHttpRangeClient::new(&b"{\"a\":1}\r\n\n{\"b\":2}\n{\"c\":3}"[..]);
+        let mut client = AsyncBufferedHttpRangeClient::with(backend, "mock");
+        client.min_req_size(4);
+        let mut line = String::new();
+        client.read_line(&mut line).await?;
+        assert_eq!(line, "{\"a\":1}\r\n");
+        let lines: Vec<String> = client.lines().try_collect().await?;
+        assert_eq!(lines, ["", "{\"b\":2}", "{\"c\":3}"]);
+        Ok(())
+    }
+
+    #[test]
+    fn sync_fill_buf_after_seek() -> std::io::Result<()> {
+        let backend = MockHttpRangeClient::new(&b"fgb\x03fgb\x00end"[..]);
+        let mut client = SyncBufferedHttpRangeClient::with(backend, "mock");
+        client.min_req_size(8);
+        client.seek(SeekFrom::Start(4))?;
+        assert_eq!(client.fill_buf()?, b"fgb\x00end");
+        client.consume(4);
+        assert_eq!(client.fill_buf()?, b"end");
+        Ok(())
+    }
+}
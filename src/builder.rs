@@ -0,0 +1,260 @@
+//! Builder for HTTP Range readers with timeouts and request options.
+#[cfg(any(feature = "reqwest-async", feature = "reqwest-sync"))]
+use crate::error::Result;
+use crate::retry::RetryPolicy;
+use std::time::Duration;
+
+/// TLS implementation of reqwest clients
+#[cfg(any(feature = "default-tls", feature = "rustls-tls"))]
+#[derive(Clone, Copy, Debug)]
+enum TlsBackend {
+    #[cfg(feature = "default-tls")]
+    Native,
+    #[cfg(feature = "rustls-tls")]
+    Rustls,
+}
+
+/// Builder for buffered HTTP Range readers
+///
+/// Defaults to a connect timeout of 10s, a read timeout of 30s, no overall timeout,
+/// a `http-range-client/<version>` user agent and no retries.
+///
+/// ```
+/// # #[cfg(feature = "reqwest-async")]
+/// # fn build() -> http_range_client::Result<()> {
+/// use http_range_client::*;
+/// use std::time::Duration;
+///
+/// let client = HttpRangeClientBuilder::new()
+///     .timeout(Duration::from_secs(60))
+///     .min_req_size(64 * 1024)
+///     .retry_policy(RetryPolicy::exponential(3))
+///     .build_async("https://flatgeobuf.org/test/data/countries.fgb")?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct HttpRangeClientBuilder {
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    user_agent: String,
+    min_req_size: Option<usize>,
+    max_req_size: Option<usize>,
+    retry_policy: RetryPolicy,
+    #[cfg(any(feature = "default-tls", feature = "rustls-tls"))]
+    tls: Option<TlsBackend>,
+}
+
+impl Default for HttpRangeClientBuilder {
+    fn default() -> Self {
+        HttpRangeClientBuilder {
+            connect_timeout: Some(Duration::from_secs(10)),
+            read_timeout: Some(Duration::from_secs(30)),
+            timeout: None,
+            user_agent: concat!("http-range-client/", env!("CARGO_PKG_VERSION")).to_string(),
+            min_req_size: None,
+            max_req_size: None,
+            retry_policy: RetryPolicy::default(),
+            #[cfg(any(feature = "default-tls", feature = "rustls-tls"))]
+            tls: None,
+        }
+    }
+}
+
+impl HttpRangeClientBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set timeout for establishing a connection. `None` disables the timeout.
+    pub fn connect_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.connect_timeout = timeout.into();
+        self
+    }
+
+    /// Set timeout for reading response data. `None` disables the timeout.
+    ///
+    /// The blocking reqwest client has no read timeout and uses it as timeout for
+    /// complete requests, unless [timeout](Self::timeout) is set.
+    pub fn read_timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.read_timeout = timeout.into();
+        self
+    }
+
+    /// Set timeout for a complete request. `None` disables the timeout.
+    pub fn timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.timeout = timeout.into();
+        self
+    }
+
+    /// Set `User-Agent` header.
+    pub fn user_agent(mut self, user_agent: &str) -> Self {
+        self.user_agent = user_agent.to_string();
+        self
+    }
+
+    /// Set minimal request size of the reader buffer.
+    pub fn min_req_size(mut self, size: usize) -> Self {
+        self.min_req_size = Some(size);
+        self
+    }
+
+    /// Set maximal request size for merged ranges.
+    pub fn max_req_size(mut self, size: usize) -> Self {
+        self.max_req_size = Some(size);
+        self
+    }
+
+    /// Set retry policy for range requests failing with transient errors.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Use native TLS for reqwest clients.
+    #[cfg(feature = "default-tls")]
+    pub fn use_native_tls(mut self) -> Self {
+        self.tls = Some(TlsBackend::Native);
+        self
+    }
+
+    /// Use rustls for reqwest clients.
+    #[cfg(feature = "rustls-tls")]
+    pub fn use_rustls_tls(mut self) -> Self {
+        self.tls = Some(TlsBackend::Rustls);
+        self
+    }
+
+    /// Build an async reqwest client with the configured timeouts.
+    #[cfg(feature = "reqwest-async")]
+    pub fn reqwest_client(&self) -> Result<reqwest::Client> {
+        let builder = reqwest::Client::builder().user_agent(&self.user_agent);
+        #[cfg(not(target_arch = "wasm32"))]
+        let builder = {
+            let mut builder = builder;
+            if let Some(timeout) = self.connect_timeout {
+                builder = builder.connect_timeout(timeout);
+            }
+            if let Some(timeout) = self.read_timeout {
+                builder = builder.read_timeout(timeout);
+            }
+            if let Some(timeout) = self.timeout {
+                builder = builder.timeout(timeout);
+            }
+            builder
+        };
+        #[cfg(all(
+            not(target_arch = "wasm32"),
+            any(feature = "default-tls", feature = "rustls-tls")
+        ))]
+        let builder = match self.tls {
+            #[cfg(feature = "default-tls")]
+            Some(TlsBackend::Native) => builder.use_native_tls(),
+            #[cfg(feature = "rustls-tls")]
+            Some(TlsBackend::Rustls) => builder.use_rustls_tls(),
+            None => builder,
+        };
+        Ok(builder.build()?)
+    }
+
+    /// Build a blocking reqwest client with the configured timeouts.
+    #[cfg(feature = "reqwest-sync")]
+    pub fn reqwest_blocking_client(&self) -> Result<reqwest::blocking::Client> {
+        let mut builder = reqwest::blocking::Client::builder()
+            .user_agent(&self.user_agent)
+            .timeout(self.blocking_timeout());
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        #[cfg(any(feature = "default-tls", feature = "rustls-tls"))]
+        let builder = match self.tls {
+            #[cfg(feature = "default-tls")]
+            Some(TlsBackend::Native) => builder.use_native_tls(),
+            #[cfg(feature = "rustls-tls")]
+            Some(TlsBackend::Rustls) => builder.use_rustls_tls(),
+            None => builder,
+        };
+        Ok(builder.build()?)
+    }
+
+    /// Timeout for complete requests of the blocking reqwest client
+    #[cfg(feature = "reqwest-sync")]
+    fn blocking_timeout(&self) -> Option<Duration> {
+        self.timeout.or(self.read_timeout)
+    }
+
+    /// Build a ureq agent with the configured timeouts. The TLS choice does not apply.
+    #[cfg(feature = "ureq-sync")]
+    pub fn ureq_agent(&self) -> ureq::Agent {
+        let mut builder = ureq::AgentBuilder::new().user_agent(&self.user_agent);
+        if let Some(timeout) = self.connect_timeout {
+            builder = builder.timeout_connect(timeout);
+        }
+        if let Some(timeout) = self.read_timeout {
+            builder = builder.timeout_read(timeout).timeout_write(timeout);
+        }
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        builder.build()
+    }
+
+    /// Build an async reader using reqwest.
+    #[cfg(feature = "reqwest-async")]
+    pub fn build_async(&self, url: &str) -> Result<crate::BufferedHttpRangeClient> {
+        let mut client = crate::BufferedHttpRangeClient::with(self.reqwest_client()?, url);
+        if let Some(size) = self.min_req_size {
+            client.set_min_req_size(size);
+        }
+        if let Some(size) = self.max_req_size {
+            client.set_max_req_size(size);
+        }
+        client.set_retry_policy(self.retry_policy.clone());
+        Ok(client)
+    }
+
+    /// Build a sync reader using the blocking reqwest client.
+    #[cfg(feature = "reqwest-sync")]
+    pub fn build_sync(&self, url: &str) -> Result<crate::HttpReader> {
+        let client = crate::HttpReader::with(self.reqwest_blocking_client()?, url);
+        Ok(self.configure_sync(client))
+    }
+
+    /// Build a sync reader using ureq.
+    #[cfg(feature = "ureq-sync")]
+    pub fn build_ureq(&self, url: &str) -> crate::UreqHttpReader {
+        let client = crate::UreqHttpReader::with(self.ureq_agent(), url);
+        self.configure_sync(client)
+    }
+
+    #[cfg(any(feature = "reqwest-sync", feature = "ureq-sync"))]
+    fn configure_sync<T: crate::SyncHttpRangeClient>(
+        &self,
+        mut client: crate::SyncBufferedHttpRangeClient<T>,
+    ) -> crate::SyncBufferedHttpRangeClient<T> {
+        if let Some(size) = self.min_req_size {
+            client.set_min_req_size(size);
+        }
+        if let Some(size) = self.max_req_size {
+            client.set_max_req_size(size);
+        }
+        client.set_retry_policy(self.retry_policy.clone());
+        client
+    }
+}
+
+#[cfg(all(test, feature = "reqwest-sync"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn blocking_timeout() {
+        let builder = HttpRangeClientBuilder::new();
+        assert_eq!(builder.blocking_timeout(), Some(Duration::from_secs(30)));
+        let builder = builder.timeout(Duration::from_secs(60));
+        assert_eq!(builder.blocking_timeout(), Some(Duration::from_secs(60)));
+        let builder = builder.timeout(None).read_timeout(None);
+        assert_eq!(builder.blocking_timeout(), None);
+    }
+}
@@ -0,0 +1,655 @@
+//! Integrity checks with whole-object digests and per-block hashes.
+use crate::error::{HttpError, Result};
+use crate::range_client::{AsyncHttpRangeClient, RangeResponse, SyncHttpRangeClient};
+use crate::{AsyncBufferedHttpRangeClient, SyncBufferedHttpRangeClient};
+use base64::prelude::*;
+use bytes::Bytes;
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use std::fmt;
+use std::io::Read;
+use std::path::Path;
+
+/// Hash algorithm of a checksum, ordered by strength
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ChecksumAlgorithm {
+    Crc32,
+    Crc32c,
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl ChecksumAlgorithm {
+    /// Digest of `data`. CRC values are returned as 4 big-endian bytes.
+    pub fn digest(self, data: &[u8]) -> Vec<u8> {
+        let mut hasher = Hasher::new(self);
+        hasher.update(data);
+        hasher.finish()
+    }
+
+    fn output_len(self) -> usize {
+        match self {
+            ChecksumAlgorithm::Crc32 | ChecksumAlgorithm::Crc32c => 4,
+            ChecksumAlgorithm::Md5 => 16,
+            ChecksumAlgorithm::Sha1 => 20,
+            ChecksumAlgorithm::Sha256 => 32,
+            ChecksumAlgorithm::Sha512 => 64,
+        }
+    }
+
+    /// Algorithm of a `Digest`, `Repr-Digest` or `x-goog-hash` entry
+    fn from_digest_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "crc32c" => Some(ChecksumAlgorithm::Crc32c),
+            "md5" => Some(ChecksumAlgorithm::Md5),
+            "sha" => Some(ChecksumAlgorithm::Sha1),
+            "sha-256" => Some(ChecksumAlgorithm::Sha256),
+            "sha-512" => Some(ChecksumAlgorithm::Sha512),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ChecksumAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            ChecksumAlgorithm::Crc32 => "CRC32",
+            ChecksumAlgorithm::Crc32c => "CRC32C",
+            ChecksumAlgorithm::Md5 => "MD5",
+            ChecksumAlgorithm::Sha1 => "SHA-1",
+            ChecksumAlgorithm::Sha256 => "SHA-256",
+            ChecksumAlgorithm::Sha512 => "SHA-512",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Incremental hasher for all supported algorithms
+pub(crate) enum Hasher {
+    Crc32(crc32fast::Hasher),
+    Crc32c(u32),
+    Md5(Md5),
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+impl Hasher {
+    pub(crate) fn new(algorithm: ChecksumAlgorithm) -> Self {
+        match algorithm {
+            ChecksumAlgorithm::Crc32 => Hasher::Crc32(crc32fast::Hasher::new()),
+            ChecksumAlgorithm::Crc32c => Hasher::Crc32c(0),
+            ChecksumAlgorithm::Md5 => Hasher::Md5(Md5::new()),
+            ChecksumAlgorithm::Sha1 => Hasher::Sha1(Sha1::new()),
+            ChecksumAlgorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            ChecksumAlgorithm::Sha512 => Hasher::Sha512(Sha512::new()),
+        }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Crc32(hasher) => hasher.update(data),
+            Hasher::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, data),
+            Hasher::Md5(hasher) => hasher.update(data),
+            Hasher::Sha1(hasher) => hasher.update(data),
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Sha512(hasher) => hasher.update(data),
+        }
+    }
+
+    pub(crate) fn finish(self) -> Vec<u8> {
+        match self {
+            Hasher::Crc32(hasher) => hasher.finalize().to_be_bytes().to_vec(),
+            Hasher::Crc32c(crc) => crc.to_be_bytes().to_vec(),
+            Hasher::Md5(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha1(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha512(hasher) => hasher.finalize().to_vec(),
+        }
+    }
+}
+
+/// Response headers with whole-object digests, in order of preference
+const CHECKSUM_HEADERS: [&str; 8] = [
+    "repr-digest",
+    "digest",
+    "x-amz-checksum-sha256",
+    "x-amz-checksum-sha1",
+    "x-amz-checksum-crc32c",
+    "x-amz-checksum-crc32",
+    "x-goog-hash",
+    "content-md5",
+];
+
+/// First parseable checksum of the values of [CHECKSUM_HEADERS]
+fn first_checksum(values: &[Option<String>]) -> Option<Checksum> {
+    CHECKSUM_HEADERS
+        .iter()
+        .zip(values)
+        .find_map(|(header, value)| Checksum::from_header(header, value.as_deref()?))
+}
+
+/// Expected digest of a complete object
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Checksum {
+    pub algorithm: ChecksumAlgorithm,
+    pub value: Vec<u8>,
+}
+
+impl Checksum {
+    pub fn new(algorithm: ChecksumAlgorithm, value: impl Into<Vec<u8>>) -> Self {
+        Checksum {
+            algorithm,
+            value: value.into(),
+        }
+    }
+
+    /// Strongest supported digest of a `Repr-Digest`, `Digest`, `Content-MD5`,
+    /// `x-amz-checksum-*` or `x-goog-hash` response header.
+    ///
+    /// Composite checksums of multipart uploads are not supported.
+    pub fn from_header(name: &str, value: &str) -> Option<Checksum> {
+        let name = name.to_ascii_lowercase();
+        let entries: Vec<(ChecksumAlgorithm, &str)> = match name.as_str() {
+            "repr-digest" | "digest" | "x-goog-hash" => value
+                .split(',')
+                .filter_map(|entry| {
+                    let (algorithm, value) = entry.split_once('=')?;
+                    let algorithm = ChecksumAlgorithm::from_digest_name(algorithm)?;
+                    // Structured field byte sequences are enclosed in colons
+                    Some((algorithm, value.trim().trim_matches(':')))
+                })
+                .collect(),
+            "content-md5" => vec![(ChecksumAlgorithm::Md5, value)],
+            "x-amz-checksum-sha256" => vec![(ChecksumAlgorithm::Sha256, value)],
+            "x-amz-checksum-sha1" => vec![(ChecksumAlgorithm::Sha1, value)],
+            "x-amz-checksum-crc32c" => vec![(ChecksumAlgorithm::Crc32c, value)],
+            "x-amz-checksum-crc32" => vec![(ChecksumAlgorithm::Crc32, value)],
+            _ => Vec::new(),
+        };
+        entries
+            .into_iter()
+            .filter_map(|(algorithm, value)| {
+                let value = BASE64_STANDARD.decode(value.trim()).ok()?;
+                (value.len() == algorithm.output_len()).then(|| Checksum::new(algorithm, value))
+            })
+            .max_by_key(|checksum| checksum.algorithm)
+    }
+
+    /// Check the digest of `data`.
+    pub fn verify(&self, data: &[u8]) -> Result<()> {
+        self.check(&self.algorithm.digest(data))
+    }
+
+    pub(crate) fn check(&self, digest: &[u8]) -> Result<()> {
+        if digest != self.value {
+            return Err(HttpError::IntegrityError(format!(
+                "{} checksum mismatch",
+                self.algorithm
+            )));
+        }
+        Ok(())
+    }
+
+    /// Check the digest of a local file.
+    pub(crate) fn verify_file(&self, path: &Path) -> Result<()> {
+        let io_error = |e: std::io::Error| HttpError::HttpError(e.to_string());
+        let mut file = std::fs::File::open(path).map_err(io_error)?;
+        let mut hasher = Hasher::new(self.algorithm);
+        let mut buf = vec![0; 64 * 1024];
+        loop {
+            match file.read(&mut buf).map_err(io_error)? {
+                0 => break,
+                n => hasher.update(&buf[..n]),
+            }
+        }
+        self.check(&hasher.finish())
+    }
+}
+
+/// Whole-object checksum verification of ranges fetched from the start of a file
+pub(crate) struct ObjectVerifier {
+    checksum: Checksum,
+    hasher: Option<Hasher>,
+    /// Length of the hashed prefix
+    hashed: u64,
+}
+
+impl ObjectVerifier {
+    pub(crate) fn new(checksum: Checksum) -> Self {
+        ObjectVerifier {
+            hasher: Some(Hasher::new(checksum.algorithm)),
+            checksum,
+            hashed: 0,
+        }
+    }
+
+    /// Hash the part of a response continuing the hashed prefix and check the
+    /// checksum when the end of the file is reached.
+    ///
+    /// Responses not overlapping the end of the hashed prefix are ignored.
+    pub(crate) fn update(
+        &mut self,
+        range: &str,
+        response: &RangeResponse,
+        total: Option<u64>,
+    ) -> Result<()> {
+        if self.hasher.is_none() {
+            return Ok(());
+        }
+        // Requested begin and inclusive end, suffix ranges are not supported
+        let requested = range
+            .strip_prefix("bytes=")
+            .and_then(|spec| spec.split_once('-'))
+            .and_then(|(begin, end)| Some((begin.parse::<u64>().ok()?, end.parse::<u64>().ok())));
+        let complete = response.content_range.is_none() && response.url.is_some();
+        let begin = match (response.range_begin(), requested) {
+            (Some(begin), _) => begin,
+            _ if complete => 0,
+            (None, Some((begin, _))) => begin,
+            (None, None) => return Ok(()),
+        };
+        let end = begin + response.data.len() as u64;
+        if begin <= self.hashed && self.hashed < end {
+            if let Some(hasher) = &mut self.hasher {
+                hasher.update(&response.data[(self.hashed - begin) as usize..]);
+            }
+            self.hashed = end;
+        }
+        // Complete, open-ended and short responses end at the end of the file
+        let eof = match requested {
+            _ if complete => Some(end),
+            Some((_, None)) => Some(end),
+            Some((_, Some(last))) if end <= last => Some(end),
+            _ => None,
+        };
+        match total.or(eof) {
+            Some(length) if self.hashed >= length => match self.hasher.take() {
+                Some(hasher) => self.checksum.check(&hasher.finish()),
+                None => Ok(()),
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Expected hashes of consecutive fixed-size blocks of a file
+///
+/// The last block may be shorter than the block size.
+#[derive(Clone, Debug)]
+pub struct BlockHashes {
+    algorithm: ChecksumAlgorithm,
+    block_size: u64,
+    hashes: Vec<Vec<u8>>,
+}
+
+/// Range request widened to block boundaries
+pub(crate) struct BlockRange {
+    /// Range header value of the widened request
+    pub header: String,
+    /// Offset of the first requested block
+    begin: u64,
+    /// Offset of the requested range in the widened response
+    skip: usize,
+    /// Requested length, `None` for open-ended ranges
+    length: Option<usize>,
+}
+
+impl BlockHashes {
+    pub fn new(algorithm: ChecksumAlgorithm, block_size: usize, hashes: Vec<Vec<u8>>) -> Self {
+        BlockHashes {
+            algorithm,
+            block_size: block_size.max(1) as u64,
+            hashes,
+        }
+    }
+
+    /// Compute the block hashes of `data`.
+    pub fn from_data(algorithm: ChecksumAlgorithm, block_size: usize, data: &[u8]) -> Self {
+        let hashes = data
+            .chunks(block_size.max(1))
+            .map(|block| algorithm.digest(block))
+            .collect();
+        Self::new(algorithm, block_size, hashes)
+    }
+
+    pub fn algorithm(&self) -> ChecksumAlgorithm {
+        self.algorithm
+    }
+
+    pub fn block_size(&self) -> usize {
+        self.block_size as usize
+    }
+
+    /// Widen a range header value to block boundaries.
+    ///
+    /// Suffix ranges require the total length `total`.
+    pub(crate) fn align(&self, range: &str, total: Option<u64>) -> Result<BlockRange> {
+        let invalid = || HttpError::HttpError(format!("invalid range `{range}`"));
+        let parse = |s: &str| s.parse::<u64>().map_err(|_| invalid());
+        let (begin, end) = range
+            .strip_prefix("bytes=")
+            .and_then(|spec| spec.split_once('-'))
+            .ok_or_else(invalid)?;
+        let (begin, end) = if begin.is_empty() {
+            let total = total.ok_or_else(|| {
+                HttpError::HttpError(
+                    "Suffix range failed - block verification requires the content length".into(),
+                )
+            })?;
+            if total == 0 {
+                return Err(HttpError::HttpStatus(416));
+            }
+            (total.saturating_sub(parse(end)?), Some(total - 1))
+        } else if end.is_empty() {
+            (parse(begin)?, None)
+        } else {
+            (parse(begin)?, Some(parse(end)?))
+        };
+        let block_begin = begin - begin % self.block_size;
+        let header = match end {
+            Some(end) => {
+                let block_end = (end / self.block_size + 1) * self.block_size - 1;
+                format!("bytes={block_begin}-{block_end}")
+            }
+            None => format!("bytes={block_begin}-"),
+        };
+        Ok(BlockRange {
+            header,
+            begin: block_begin,
+            skip: (begin - block_begin) as usize,
+            length: end.map(|end| (end + 1).saturating_sub(begin) as usize),
+        })
+    }
+
+    /// Verify the blocks of a widened response and return the requested range.
+    pub(crate) fn verify(&self, range: &BlockRange, data: Bytes) -> Result<Bytes> {
+        let first = range.begin / self.block_size;
+        for (i, block) in data.chunks(self.block_size as usize).enumerate() {
+            let index = first as usize + i;
+            let expected = self
+                .hashes
+                .get(index)
+                .ok_or_else(|| HttpError::HttpError(format!("no hash for block {index}")))?;
+            if (block.len() as u64) < self.block_size && index + 1 != self.hashes.len() {
+                return Err(HttpError::HttpError(format!("incomplete block {index}")));
+            }
+            if self.algorithm.digest(block) != *expected {
+                return Err(HttpError::IntegrityError(format!(
+                    "{} checksum mismatch in block {index}",
+                    self.algorithm
+                )));
+            }
+        }
+        if range.skip >= data.len() && range.length != Some(0) {
+            // Requested range starts beyond the end of the file
+            return Err(HttpError::HttpStatus(416));
+        }
+        let end = range
+            .length
+            .map_or(data.len(), |length| (range.skip + length).min(data.len()));
+        Ok(data.slice(range.skip..end))
+    }
+
+    /// Verify a widened response, adjusting its `Content-Range` to the requested range.
+    pub(crate) fn verify_response(
+        &self,
+        range: &BlockRange,
+        response: RangeResponse,
+    ) -> Result<RangeResponse> {
+        let total = response.total_length();
+        let data = self.verify(range, response.data)?;
+        let begin = range.begin + range.skip as u64;
+        let content_range = match total {
+            Some(total) if !data.is_empty() => Some(format!(
+                "bytes {begin}-{}/{total}",
+                begin + data.len() as u64 - 1
+            )),
+            _ => None,
+        };
+        Ok(RangeResponse::new(data)
+            .with_content_range(content_range)
+            .with_url(response.url))
+    }
+}
+
+fn missing_checksum() -> HttpError {
+    HttpError::HttpError("no supported checksum header received".to_string())
+}
+
+impl<T: AsyncHttpRangeClient> AsyncBufferedHttpRangeClient<T> {
+    /// Whole-object checksum from the first supported digest header, requested
+    /// with a single HEAD request.
+    ///
+    /// S3 returns `x-amz-checksum-*` headers only for requests with the header
+    /// `x-amz-checksum-mode: ENABLED`.
+    pub async fn object_checksum(&self) -> Result<Option<Checksum>> {
+        let values = self.head_response_headers(&CHECKSUM_HEADERS).await?;
+        Ok(first_checksum(&values))
+    }
+
+    /// Expected checksum of a download with checksum verification
+    pub(crate) async fn download_checksum(
+        &self,
+        verify: bool,
+        expected: Option<&Checksum>,
+    ) -> Result<Option<Checksum>> {
+        match expected {
+            Some(checksum) => Ok(Some(checksum.clone())),
+            None if verify => self
+                .object_checksum()
+                .await?
+                .ok_or_else(missing_checksum)
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<T: SyncHttpRangeClient> SyncBufferedHttpRangeClient<T> {
+    /// Whole-object checksum from the first supported digest header, requested
+    /// with a single HEAD request.
+    ///
+    /// S3 returns `x-amz-checksum-*` headers only for requests with the header
+    /// `x-amz-checksum-mode: ENABLED`.
+    pub fn object_checksum(&self) -> Result<Option<Checksum>> {
+        let values = self.head_response_headers(&CHECKSUM_HEADERS)?;
+        Ok(first_checksum(&values))
+    }
+
+    /// Expected checksum of a download with checksum verification
+    pub(crate) fn download_checksum(
+        &self,
+        verify: bool,
+        expected: Option<&Checksum>,
+    ) -> Result<Option<Checksum>> {
+        match expected {
+            Some(checksum) => Ok(Some(checksum.clone())),
+            None if verify => self
+                .object_checksum()?
+                .ok_or_else(missing_checksum)
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_client::MockHttpRangeClient;
+    use crate::DownloadOptions;
+
+    #[test]
+    fn parse_headers() {
+        let sha256 = Sha256::digest(b"hello").to_vec();
+        let b64 = BASE64_STANDARD.encode(&sha256);
+        let expected = Some(Checksum::new(ChecksumAlgorithm::Sha256, sha256));
+        assert_eq!(
+            Checksum::from_header("Repr-Digest", &format!("sha-256=:{b64}:, unixsum=:AA==:")),
+            expected
+        );
+        assert_eq!(
+            Checksum::from_header(
+                "Digest",
+                &format!("MD5=XUFAKrxLKna5cZ2REBfFkg==,SHA-256={b64}")
+            ),
+            expected
+        );
+        assert_eq!(
+            Checksum::from_header("x-amz-checksum-sha256", &b64),
+            expected
+        );
+        // CRC32C of "hello"
+        let crc = crc32c::crc32c(b"hello").to_be_bytes();
+        assert_eq!(
+            Checksum::from_header(
+                "x-goog-hash",
+                &format!("crc32c={}", BASE64_STANDARD.encode(crc))
+            ),
+            Some(Checksum::new(ChecksumAlgorithm::Crc32c, crc))
+        );
+        // Composite checksum of a multipart upload
+        assert_eq!(
+            Checksum::from_header("x-amz-checksum-crc32", "i9aeUg==-3"),
+            None
+        );
+        let md5 = Checksum::from_header("Content-MD5", "XUFAKrxLKna5cZ2REBfFkg==").unwrap();
+        assert!(md5.verify(b"hello").is_ok());
+        assert!(md5.verify(b"hallo").is_err());
+    }
+
+    #[test]
+    fn align_ranges() -> Result<()> {
+        let hashes = BlockHashes::new(ChecksumAlgorithm::Crc32, 100, Vec::new());
+        let range = hashes.align("bytes=150-249", None)?;
+        assert_eq!(range.header, "bytes=100-299");
+        assert_eq!((range.skip, range.length), (50, Some(100)));
+        assert_eq!(hashes.align("bytes=200-", None)?.header, "bytes=200-");
+        assert_eq!(
+            hashes.align("bytes=-30", Some(250))?.header,
+            "bytes=200-299"
+        );
+        assert!(hashes.align("bytes=-30", None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sync_block_verification() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000);
+        let data: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        client.block_hashes(BlockHashes::from_data(
+            ChecksumAlgorithm::Sha256,
+            256,
+            &data,
+        ));
+        assert_eq!(client.min_req_size(1).get_range(300, 10)?, &data[300..310]);
+        assert_eq!(client.get_suffix(20)?, &data[980..]);
+        assert_eq!(
+            backend.requests(),
+            ["GET bytes=256-511", "GET bytes=768-1023"]
+        );
+
+        // Corrupt hash list
+        let mut hashes = BlockHashes::from_data(ChecksumAlgorithm::Sha256, 256, &data);
+        hashes.hashes[1][0] ^= 1;
+        let mut client = SyncBufferedHttpRangeClient::with(backend, "mock");
+        client.block_hashes(hashes);
+        assert!(client.min_req_size(1).get_range(0, 10).is_ok());
+        assert!(client.get_range(500, 10).is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_block_verification() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000);
+        let data: Vec<u8> = (0..1000).map(|i| (i % 251) as u8).collect();
+        let mut client = AsyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        client.block_hashes(BlockHashes::from_data(
+            ChecksumAlgorithm::Crc32c,
+            100,
+            &data,
+        ));
+        // Suffix request with unknown length
+        assert_eq!(client.get_suffix(50).await?, &data[950..]);
+        let ranges = client
+            .get_ranges_coalesced(&[(10, 5), (620, 30)], 0)
+            .await?;
+        assert_eq!(ranges[0], data[10..15]);
+        assert_eq!(ranges[1], data[620..650]);
+        Ok(())
+    }
+
+    #[test]
+    fn sync_download_verification() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(5000);
+        let data: Vec<u8> = (0..5000).map(|i| (i % 251) as u8).collect();
+        let sha256 = BASE64_STANDARD.encode(Sha256::digest(&data));
+        backend.set_response_header("x-amz-checksum-sha256", &sha256);
+        backend.set_response_header("content-md5", "invalid");
+        let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        let checksum = client.object_checksum()?.unwrap();
+        assert_eq!(checksum.algorithm, ChecksumAlgorithm::Sha256);
+        let heads = backend.requests();
+        assert_eq!(heads.len(), 1);
+        assert!(heads[0].starts_with("HEAD repr-digest, digest,"));
+
+        let options = DownloadOptions::new()
+            .with_chunk_size(1000)
+            .with_checksum_verification();
+        assert_eq!(client.download_to(&mut Vec::new(), &options)?, 5000);
+
+        let options = DownloadOptions::new()
+            .with_expected_checksum(Checksum::new(ChecksumAlgorithm::Crc32, [0; 4]));
+        assert!(client.download_to(&mut Vec::new(), &options).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sync_read_verification() -> Result<()> {
+        let data: Vec<u8> = (0..5000).map(|i| (i % 251) as u8).collect();
+        let checksum = Checksum::new(ChecksumAlgorithm::Sha256, Sha256::digest(&data).to_vec());
+        let backend = MockHttpRangeClient::with_len(5000);
+        let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        client.min_req_size(1000).expected_checksum(checksum);
+        let mut buf = vec![0; 5000];
+        client.read_exact(&mut buf).unwrap();
+        assert_eq!(buf, data);
+
+        let wrong = Checksum::new(ChecksumAlgorithm::Crc32, [0; 4]);
+        let mut client = SyncBufferedHttpRangeClient::with(backend, "mock");
+        client.min_req_size(1000).expected_checksum(wrong);
+        // Not verified before the end of the file
+        client.read_exact(&mut buf[..4000]).unwrap();
+        let err = client.read_exact(&mut buf[4000..]).unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_complete_response_verification() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(500);
+        backend.ignore_ranges();
+        let wrong = Checksum::new(ChecksumAlgorithm::Md5, [0; 16]);
+        let mut client = AsyncBufferedHttpRangeClient::with(backend, "mock");
+        client.expected_checksum(wrong);
+        assert!(matches!(
+            client.get_range(0, 100).await,
+            Err(HttpError::IntegrityError(_))
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_download_without_checksum() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(5000);
+        let mut client = AsyncBufferedHttpRangeClient::with(backend, "mock");
+        let options = DownloadOptions::new().with_checksum_verification();
+        assert!(client.download_to(&mut Vec::new(), &options).await.is_err());
+        Ok(())
+    }
+}
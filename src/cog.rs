@@ -0,0 +1,686 @@
+//! Tile access to Cloud Optimized GeoTIFF files.
+use crate::buffered_range_client::unexpected_end;
+use crate::error::{HttpError, Result};
+use crate::range_client::{AsyncHttpRangeClient, SyncHttpRangeClient};
+use crate::{AsyncBufferedHttpRangeClient, SyncBufferedHttpRangeClient};
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+use bytes::Bytes;
+use std::convert::TryFrom;
+
+/// Length of the first request, usually containing the header and all IFDs of a COG
+const HEADER_PREFETCH: usize = 16 * 1024;
+/// Maximal gap between tag values fetched with a single request
+const MAX_TAG_GAP: usize = 16 * 1024;
+/// Maximal number of IFDs, protecting against cyclic IFD chains
+const MAX_IFDS: usize = 1024;
+
+const NEW_SUBFILE_TYPE: u16 = 254;
+const IMAGE_WIDTH: u16 = 256;
+const IMAGE_LENGTH: u16 = 257;
+const BITS_PER_SAMPLE: u16 = 258;
+const COMPRESSION: u16 = 259;
+const SAMPLES_PER_PIXEL: u16 = 277;
+const PLANAR_CONFIGURATION: u16 = 284;
+const PREDICTOR: u16 = 317;
+const TILE_WIDTH: u16 = 322;
+const TILE_LENGTH: u16 = 323;
+const TILE_OFFSETS: u16 = 324;
+const TILE_BYTE_COUNTS: u16 = 325;
+const JPEG_TABLES: u16 = 347;
+/// Tags read from IFDs, sorted
+const TAGS: [u16; 13] = [
+    NEW_SUBFILE_TYPE,
+    IMAGE_WIDTH,
+    IMAGE_LENGTH,
+    BITS_PER_SAMPLE,
+    COMPRESSION,
+    SAMPLES_PER_PIXEL,
+    PLANAR_CONFIGURATION,
+    PREDICTOR,
+    TILE_WIDTH,
+    TILE_LENGTH,
+    TILE_OFFSETS,
+    TILE_BYTE_COUNTS,
+    JPEG_TABLES,
+];
+/// `NewSubfileType` flag of transparency masks
+const SUBFILE_MASK: u64 = 4;
+
+fn invalid(reason: &str) -> HttpError {
+    HttpError::HttpError(format!("invalid TIFF file: {reason}"))
+}
+
+/// Start of `len` bytes at `offset`, if their end is addressable
+fn checked_range(offset: u64, len: usize) -> Result<usize> {
+    usize::try_from(offset)
+        .ok()
+        .filter(|offset| offset.checked_add(len).is_some())
+        .ok_or_else(|| invalid("offset out of range"))
+}
+
+fn exact(data: &[u8], length: usize) -> Result<&[u8]> {
+    data.get(..length).ok_or_else(|| unexpected_end(length))
+}
+
+/// Size of a value of a TIFF field type
+fn type_len(field_type: u16) -> Option<usize> {
+    match field_type {
+        1 | 2 | 6 | 7 => Some(1),
+        3 | 8 => Some(2),
+        4 | 9 | 11 | 13 => Some(4),
+        5 | 10 | 12 | 16 | 17 | 18 => Some(8),
+        _ => None,
+    }
+}
+
+/// Byte order and offset size of a TIFF or BigTIFF file
+#[derive(Clone, Copy, Debug)]
+struct Layout {
+    big_tiff: bool,
+    little_endian: bool,
+}
+
+impl Layout {
+    /// Parse the file header, returning the layout and the offset of the first IFD.
+    fn parse(data: &[u8]) -> Result<(Self, u64)> {
+        let header = exact(data, 8)?;
+        let little_endian = match &header[..2] {
+            b"II" => true,
+            b"MM" => false,
+            _ => return Err(invalid("no TIFF header found")),
+        };
+        let mut layout = Layout {
+            big_tiff: false,
+            little_endian,
+        };
+        match layout.uint(&header[2..4]) {
+            42 => Ok((layout, layout.uint(&header[4..8]))),
+            43 => {
+                if layout.uint(&header[4..6]) != 8 {
+                    return Err(invalid("unsupported BigTIFF offset size"));
+                }
+                layout.big_tiff = true;
+                Ok((layout, layout.uint(&exact(data, 16)?[8..])))
+            }
+            _ => Err(invalid("unknown TIFF version")),
+        }
+    }
+
+    /// Unsigned integer of 1 to 8 bytes
+    fn uint(&self, data: &[u8]) -> u64 {
+        if self.little_endian {
+            LittleEndian::read_uint(data, data.len())
+        } else {
+            BigEndian::read_uint(data, data.len())
+        }
+    }
+
+    fn count_len(&self) -> usize {
+        if self.big_tiff {
+            8
+        } else {
+            2
+        }
+    }
+
+    fn entry_len(&self) -> usize {
+        if self.big_tiff {
+            20
+        } else {
+            12
+        }
+    }
+
+    fn offset_len(&self) -> usize {
+        if self.big_tiff {
+            8
+        } else {
+            4
+        }
+    }
+
+    /// Length of the IFD entries and the next IFD offset
+    fn ifd_len(&self, count: u64) -> Result<usize> {
+        usize::try_from(count)
+            .ok()
+            .and_then(|count| count.checked_mul(self.entry_len()))
+            .and_then(|len| len.checked_add(self.offset_len()))
+            .ok_or_else(|| invalid("too many IFD entries"))
+    }
+
+    /// Parse the entries of tags in [TAGS] and the offset of the next IFD.
+    fn parse_ifd(&self, data: &[u8], count: u64) -> Result<(Vec<Entry>, u64)> {
+        let entries_len = self.ifd_len(count)? - self.offset_len();
+        let mut entries = Vec::new();
+        for entry in data[..entries_len].chunks_exact(self.entry_len()) {
+            let tag = self.uint(&entry[..2]) as u16;
+            if TAGS.binary_search(&tag).is_err() {
+                continue;
+            }
+            let field_type = self.uint(&entry[2..4]) as u16;
+            let (count, value) = if self.big_tiff {
+                (self.uint(&entry[4..12]), &entry[12..20])
+            } else {
+                (self.uint(&entry[4..8]), &entry[8..12])
+            };
+            let len = type_len(field_type)
+                .zip(usize::try_from(count).ok())
+                .and_then(|(type_len, count)| count.checked_mul(type_len))
+                .ok_or_else(|| invalid("invalid IFD entry"))?;
+            let (offset, inline) = if len <= value.len() {
+                (0, Some(Bytes::copy_from_slice(&value[..len])))
+            } else {
+                (self.uint(value), None)
+            };
+            entries.push(Entry {
+                tag,
+                field_type,
+                len,
+                offset,
+                inline,
+            });
+        }
+        let next = self.uint(&data[entries_len..entries_len + self.offset_len()]);
+        Ok((entries, next))
+    }
+}
+
+/// IFD entry of a tag in [TAGS]
+struct Entry {
+    tag: u16,
+    field_type: u16,
+    /// Length of the value in bytes
+    len: usize,
+    offset: u64,
+    /// Value stored in the entry itself
+    inline: Option<Bytes>,
+}
+
+/// Ranges of values not stored in their entry
+fn external_ranges(entries: &[Entry]) -> Result<Vec<(usize, usize)>> {
+    entries
+        .iter()
+        .map(|entry| match entry.inline {
+            Some(_) => Ok((0, 0)),
+            None => Ok((checked_range(entry.offset, entry.len)?, entry.len)),
+        })
+        .collect()
+}
+
+/// Tag values of an IFD
+struct Tags {
+    layout: Layout,
+    values: Vec<(u16, u16, Bytes)>,
+}
+
+impl Tags {
+    fn new(layout: Layout, entries: Vec<Entry>, external: Vec<Bytes>) -> Result<Self> {
+        let mut values = Vec::with_capacity(entries.len());
+        for (entry, data) in entries.into_iter().zip(external) {
+            let data = entry.inline.unwrap_or(data);
+            if data.len() < entry.len {
+                return Err(unexpected_end(entry.len));
+            }
+            values.push((entry.tag, entry.field_type, data));
+        }
+        Ok(Tags { layout, values })
+    }
+
+    fn bytes(&self, tag: u16) -> Option<&Bytes> {
+        self.values
+            .iter()
+            .find(|(t, _, _)| *t == tag)
+            .map(|(_, _, data)| data)
+    }
+
+    fn uints(&self, tag: u16) -> Vec<u64> {
+        match self.values.iter().find(|(t, _, _)| *t == tag) {
+            Some((_, field_type, data)) => {
+                let len = type_len(*field_type).expect("checked field type");
+                data.chunks_exact(len)
+                    .map(|value| self.layout.uint(value))
+                    .collect()
+            }
+            None => Vec::new(),
+        }
+    }
+
+    fn uint(&self, tag: u16) -> Option<u64> {
+        self.uints(tag).first().copied()
+    }
+}
+
+/// Full resolution image or overview of a COG
+#[derive(Clone, Debug, PartialEq)]
+pub struct CogLevel {
+    pub width: u32,
+    pub height: u32,
+    pub tile_width: u32,
+    pub tile_height: u32,
+    pub tiles_across: usize,
+    pub tiles_down: usize,
+    /// TIFF compression scheme, e.g. 1 (none), 5 (LZW), 7 (JPEG) or 8 (Deflate)
+    pub compression: u16,
+    pub bits_per_sample: Vec<u16>,
+    pub samples_per_pixel: u16,
+    /// 1 (chunky) or 2 (separate planes)
+    pub planar_configuration: u16,
+    pub predictor: u16,
+    /// Tables shared by all JPEG compressed tiles
+    pub jpeg_tables: Option<Bytes>,
+    tile_offsets: Vec<u64>,
+    tile_byte_counts: Vec<u64>,
+}
+
+impl CogLevel {
+    /// Level of an IFD, `None` for transparency masks
+    fn from_tags(tags: &Tags) -> Result<Option<Self>> {
+        if tags.uint(NEW_SUBFILE_TYPE).unwrap_or(0) & SUBFILE_MASK != 0 {
+            return Ok(None);
+        }
+        let required = |tag| tags.uint(tag).ok_or_else(|| invalid("not a tiled image"));
+        let width = required(IMAGE_WIDTH)? as u32;
+        let height = required(IMAGE_LENGTH)? as u32;
+        let tile_width = required(TILE_WIDTH)? as u32;
+        let tile_height = required(TILE_LENGTH)? as u32;
+        if tile_width == 0 || tile_height == 0 {
+            return Err(invalid("empty tile size"));
+        }
+        let tiles_across = width.div_ceil(tile_width) as usize;
+        let tiles_down = height.div_ceil(tile_height) as usize;
+        let tile_offsets = tags.uints(TILE_OFFSETS);
+        let tile_byte_counts = tags.uints(TILE_BYTE_COUNTS);
+        let tiles = tiles_across
+            .checked_mul(tiles_down)
+            .ok_or_else(|| invalid("too many tiles"))?;
+        if tile_offsets.len() < tiles || tile_byte_counts.len() < tiles {
+            return Err(invalid("missing tile offsets"));
+        }
+        Ok(Some(CogLevel {
+            width,
+            height,
+            tile_width,
+            tile_height,
+            tiles_across,
+            tiles_down,
+            compression: tags.uint(COMPRESSION).unwrap_or(1) as u16,
+            bits_per_sample: tags
+                .uints(BITS_PER_SAMPLE)
+                .into_iter()
+                .map(|bits| bits as u16)
+                .collect(),
+            samples_per_pixel: tags.uint(SAMPLES_PER_PIXEL).unwrap_or(1) as u16,
+            planar_configuration: tags.uint(PLANAR_CONFIGURATION).unwrap_or(1) as u16,
+            predictor: tags.uint(PREDICTOR).unwrap_or(1) as u16,
+            jpeg_tables: tags.bytes(JPEG_TABLES).cloned(),
+            tile_offsets,
+            tile_byte_counts,
+        }))
+    }
+
+    /// Offset and length of a tile, `None` if out of range.
+    ///
+    /// With separate planes, tiles of the first plane are addressed.
+    pub fn tile_range(&self, row: usize, col: usize) -> Option<(u64, u64)> {
+        if row >= self.tiles_down || col >= self.tiles_across {
+            return None;
+        }
+        let index = row * self.tiles_across + col;
+        Some((self.tile_offsets[index], self.tile_byte_counts[index]))
+    }
+}
+
+/// Ranges of `(level, row, col)` tiles
+fn tile_ranges(
+    levels: &[CogLevel],
+    tiles: &[(usize, usize, usize)],
+) -> Result<Vec<(usize, usize)>> {
+    tiles
+        .iter()
+        .map(|&(level, row, col)| {
+            let (offset, length) = levels
+                .get(level)
+                .and_then(|l| l.tile_range(row, col))
+                .ok_or_else(|| {
+                    HttpError::HttpError(format!("tile {level}/{row}/{col} out of range"))
+                })?;
+            let length = usize::try_from(length).map_err(|_| invalid("tile too large"))?;
+            Ok((checked_range(offset, length)?, length))
+        })
+        .collect()
+}
+
+fn check_tiles(ranges: &[(usize, usize)], tiles: &[Bytes]) -> Result<()> {
+    for (&(_, length), tile) in ranges.iter().zip(tiles) {
+        if tile.len() < length {
+            return Err(unexpected_end(length));
+        }
+    }
+    Ok(())
+}
+
+/// Reader of raw tiles of a Cloud Optimized GeoTIFF
+pub struct SyncCogReader<T: SyncHttpRangeClient> {
+    client: SyncBufferedHttpRangeClient<T>,
+    big_tiff: bool,
+    levels: Vec<CogLevel>,
+}
+
+impl<T: SyncHttpRangeClient> SyncCogReader<T> {
+    /// Read the header and all IFDs, starting with a request of 16 KiB.
+    pub fn open(mut client: SyncBufferedHttpRangeClient<T>) -> Result<Self> {
+        let (layout, mut offset) = Layout::parse(client.get_range(0, HEADER_PREFETCH)?)?;
+        let mut levels = Vec::new();
+        for _ in 0..MAX_IFDS {
+            if offset == 0 {
+                return Ok(SyncCogReader {
+                    client,
+                    big_tiff: layout.big_tiff,
+                    levels,
+                });
+            }
+            let count_len = layout.count_len();
+            let start = checked_range(offset, count_len)?;
+            let count = layout.uint(exact(client.get_range(start, count_len)?, count_len)?);
+            let ifd_len = layout.ifd_len(count)?;
+            let entries_start = checked_range((start + count_len) as u64, ifd_len)?;
+            let data = client.get_range(entries_start, ifd_len)?;
+            let (entries, next) = layout.parse_ifd(exact(data, ifd_len)?, count)?;
+            let external = client.get_ranges_coalesced(&external_ranges(&entries)?, MAX_TAG_GAP)?;
+            let tags = Tags::new(layout, entries, external)?;
+            levels.extend(CogLevel::from_tags(&tags)?);
+            offset = next;
+        }
+        Err(invalid("too many IFDs"))
+    }
+
+    pub fn is_big_tiff(&self) -> bool {
+        self.big_tiff
+    }
+
+    /// Full resolution image followed by the overviews
+    pub fn levels(&self) -> &[CogLevel] {
+        &self.levels
+    }
+
+    /// Raw compressed bytes of a tile, empty for sparse tiles.
+    pub fn get_tile(&mut self, level: usize, row: usize, col: usize) -> Result<Bytes> {
+        Ok(self.get_tiles(&[(level, row, col)])?.remove(0))
+    }
+
+    /// Raw compressed bytes of `(level, row, col)` tiles, reading adjacent tiles with single requests.
+    pub fn get_tiles(&mut self, tiles: &[(usize, usize, usize)]) -> Result<Vec<Bytes>> {
+        let ranges = tile_ranges(&self.levels, tiles)?;
+        let data = self.client.get_ranges_coalesced(&ranges, 0)?;
+        check_tiles(&ranges, &data)?;
+        Ok(data)
+    }
+}
+
+/// Reader of raw tiles of a Cloud Optimized GeoTIFF
+pub struct AsyncCogReader<T: AsyncHttpRangeClient> {
+    client: AsyncBufferedHttpRangeClient<T>,
+    big_tiff: bool,
+    levels: Vec<CogLevel>,
+}
+
+impl<T: AsyncHttpRangeClient> AsyncCogReader<T> {
+    /// Read the header and all IFDs, starting with a request of 16 KiB.
+    pub async fn open(mut client: AsyncBufferedHttpRangeClient<T>) -> Result<Self> {
+        let (layout, mut offset) = Layout::parse(client.get_range(0, HEADER_PREFETCH).await?)?;
+        let mut levels = Vec::new();
+        for _ in 0..MAX_IFDS {
+            if offset == 0 {
+                return Ok(AsyncCogReader {
+                    client,
+                    big_tiff: layout.big_tiff,
+                    levels,
+                });
+            }
+            let count_len = layout.count_len();
+            let start = checked_range(offset, count_len)?;
+            let count = layout.uint(exact(client.get_range(start, count_len).await?, count_len)?);
+            let ifd_len = layout.ifd_len(count)?;
+            let entries_start = checked_range((start + count_len) as u64, ifd_len)?;
+            let data = client.get_range(entries_start, ifd_len).await?;
+            let (entries, next) = layout.parse_ifd(exact(data, ifd_len)?, count)?;
+            let external = client
+                .get_ranges_coalesced(&external_ranges(&entries)?, MAX_TAG_GAP)
+                .await?;
+            let tags = Tags::new(layout, entries, external)?;
+            levels.extend(CogLevel::from_tags(&tags)?);
+            offset = next;
+        }
+        Err(invalid("too many IFDs"))
+    }
+
+    pub fn is_big_tiff(&self) -> bool {
+        self.big_tiff
+    }
+
+    /// Full resolution image followed by the overviews
+    pub fn levels(&self) -> &[CogLevel] {
+        &self.levels
+    }
+
+    /// Raw compressed bytes of a tile, empty for sparse tiles.
+    pub async fn get_tile(&mut self, level: usize, row: usize, col: usize) -> Result<Bytes> {
+        Ok(self.get_tiles(&[(level, row, col)]).await?.remove(0))
+    }
+
+    /// Raw compressed bytes of `(level, row, col)` tiles, reading adjacent tiles with single requests.
+    pub async fn get_tiles(&mut self, tiles: &[(usize, usize, usize)]) -> Result<Vec<Bytes>> {
+        let ranges = tile_ranges(&self.levels, tiles)?;
+        let data = self.client.get_ranges_coalesced(&ranges, 0).await?;
+        check_tiles(&ranges, &data)?;
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_client::MockHttpRangeClient;
+
+    /// Offset of the tile data, behind the first request
+    const TILE_DATA: usize = 20_000;
+    const EXTERNAL_VALUES: usize = 4096;
+
+    struct TestTiff {
+        layout: Layout,
+        data: Vec<u8>,
+        /// Position of the next external value
+        external: usize,
+    }
+
+    impl TestTiff {
+        fn put(&mut self, pos: usize, value: u64, len: usize) {
+            if self.data.len() < pos + len {
+                self.data.resize(pos + len, 0);
+            }
+            let dest = &mut self.data[pos..pos + len];
+            if self.layout.little_endian {
+                LittleEndian::write_uint(dest, value, len);
+            } else {
+                BigEndian::write_uint(dest, value, len);
+            }
+        }
+
+        /// Write an IFD with `(tag, field_type, values)` entries.
+        fn put_ifd(&mut self, pos: usize, entries: &[(u16, u16, Vec<u64>)], next: usize) {
+            let layout = self.layout;
+            self.put(pos, entries.len() as u64, layout.count_len());
+            let mut pos = pos + layout.count_len();
+            for (tag, field_type, values) in entries {
+                let type_len = type_len(*field_type).unwrap();
+                self.put(pos, u64::from(*tag), 2);
+                self.put(pos + 2, u64::from(*field_type), 2);
+                self.put(pos + 4, values.len() as u64, layout.offset_len());
+                let value_pos = pos + 4 + layout.offset_len();
+                let mut values_pos = if values.len() * type_len <= layout.offset_len() {
+                    value_pos
+                } else {
+                    let external = self.external;
+                    self.put(value_pos, external as u64, layout.offset_len());
+                    self.external += values.len() * type_len;
+                    external
+                };
+                for value in values {
+                    self.put(values_pos, *value, type_len);
+                    values_pos += type_len;
+                }
+                pos += layout.entry_len();
+            }
+            self.put(pos, next as u64, layout.offset_len());
+        }
+    }
+
+    fn tile(level: usize, row: usize, col: usize) -> String {
+        format!("tile {level} {row} {col}")
+    }
+
+    /// Image of 64x48 pixels with an overview and a mask, in tiles of 16x16 pixels.
+    /// Tile (0, 1) of the overview is sparse.
+    fn tiff(big_tiff: bool, little_endian: bool) -> Vec<u8> {
+        let layout = Layout {
+            big_tiff,
+            little_endian,
+        };
+        let mut tiff = TestTiff {
+            layout,
+            data: Vec::new(),
+            external: EXTERNAL_VALUES,
+        };
+        tiff.data.extend(if little_endian { b"II" } else { b"MM" });
+        let ifd_offset = if big_tiff {
+            tiff.put(2, 43, 2);
+            tiff.put(4, 8, 2);
+            tiff.put(8, 16, 8);
+            16
+        } else {
+            tiff.put(2, 42, 2);
+            tiff.put(4, 8, 4);
+            8
+        };
+        let offset_type = if big_tiff { 16 } else { 4 };
+        let mut tile_pos = TILE_DATA;
+        let images: [(usize, usize, u64); 3] = [(64, 48, 0), (32, 24, 1), (32, 24, 5)];
+        let mut pos = ifd_offset;
+        for (level, &(width, height, subfile_type)) in images.iter().enumerate() {
+            let (mut offsets, mut counts) = (Vec::new(), Vec::new());
+            for row in 0..height.div_ceil(16) {
+                for col in 0..width.div_ceil(16) {
+                    let data = match (level, row, col) {
+                        (1, 0, 1) => String::new(),
+                        (2, _, _) => "mask".to_string(),
+                        _ => tile(level, row, col),
+                    };
+                    tiff.data.resize(tile_pos, 0);
+                    tiff.data.extend(data.as_bytes());
+                    offsets.push(if data.is_empty() { 0 } else { tile_pos as u64 });
+                    counts.push(data.len() as u64);
+                    tile_pos += data.len();
+                }
+            }
+            let entries = [
+                (NEW_SUBFILE_TYPE, 4, vec![subfile_type]),
+                (IMAGE_WIDTH, 3, vec![width as u64]),
+                (IMAGE_LENGTH, 3, vec![height as u64]),
+                (BITS_PER_SAMPLE, 3, vec![8, 8, 8]),
+                (COMPRESSION, 3, vec![8]),
+                (SAMPLES_PER_PIXEL, 3, vec![3]),
+                (PLANAR_CONFIGURATION, 3, vec![1]),
+                (TILE_WIDTH, 3, vec![16]),
+                (TILE_LENGTH, 3, vec![16]),
+                (TILE_OFFSETS, offset_type, offsets),
+                (TILE_BYTE_COUNTS, 4, counts),
+            ];
+            let next = if level + 1 < images.len() {
+                pos + layout.ifd_len(entries.len() as u64).unwrap() + layout.count_len()
+            } else {
+                0
+            };
+            tiff.put_ifd(pos, &entries, next);
+            pos = next;
+        }
+        assert!(pos < EXTERNAL_VALUES && tiff.external < TILE_DATA);
+        tiff.data
+    }
+
+    #[test]
+    fn sync_tiles() -> Result<()> {
+        for (big_tiff, little_endian) in [(false, true), (false, false), (true, true)] {
+            let backend = MockHttpRangeClient::new(tiff(big_tiff, little_endian));
+            let client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+            let mut reader = SyncCogReader::open(client)?;
+            assert_eq!(backend.requests(), ["GET bytes=0-16383"]);
+            assert_eq!(reader.is_big_tiff(), big_tiff);
+            let levels = reader.levels();
+            assert_eq!(levels.len(), 2);
+            assert_eq!((levels[0].width, levels[0].height), (64, 48));
+            assert_eq!((levels[0].tiles_across, levels[0].tiles_down), (4, 3));
+            assert_eq!((levels[1].tiles_across, levels[1].tiles_down), (2, 2));
+            assert_eq!(levels[1].bits_per_sample, [8, 8, 8]);
+            assert_eq!(levels[1].compression, 8);
+
+            let tiles = reader.get_tiles(&[(0, 1, 0), (0, 1, 1), (0, 1, 2), (0, 1, 3)])?;
+            for (col, data) in tiles.iter().enumerate() {
+                assert_eq!(data, tile(0, 1, col).as_bytes());
+            }
+            assert_eq!(backend.requests().len(), 2);
+            assert_eq!(reader.get_tile(1, 1, 1)?, tile(1, 1, 1).as_bytes());
+            assert!(reader.get_tile(1, 0, 1)?.is_empty());
+            assert_eq!(backend.requests().len(), 3);
+            assert!(reader.get_tile(1, 2, 0).is_err());
+            assert!(reader.get_tile(2, 0, 0).is_err());
+        }
+
+        let mut data = tiff(true, true);
+        data[8..16].copy_from_slice(&u64::MAX.to_le_bytes());
+        let client = SyncBufferedHttpRangeClient::with(MockHttpRangeClient::new(data), "mock");
+        assert!(SyncCogReader::open(client).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_offsets() -> Result<()> {
+        // External value offset of the tile offsets in the first BigTIFF IFD
+        let value_offset = 16 + 8 + 9 * 20 + 12;
+        let mut data = tiff(true, true);
+        data[value_offset..value_offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        let client = SyncBufferedHttpRangeClient::with(MockHttpRangeClient::new(data), "mock");
+        let err = SyncCogReader::open(client).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "http error `invalid TIFF file: offset out of range`"
+        );
+
+        let mut data = tiff(true, true);
+        let offsets = LittleEndian::read_u64(&data[value_offset..]) as usize;
+        data[offsets..offsets + 8].copy_from_slice(&(u64::MAX - 1).to_le_bytes());
+        let client = SyncBufferedHttpRangeClient::with(MockHttpRangeClient::new(data), "mock");
+        let mut reader = SyncCogReader::open(client)?;
+        let err = reader.get_tile(0, 0, 0).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "http error `invalid TIFF file: offset out of range`"
+        );
+        assert_eq!(reader.get_tile(0, 0, 1)?, tile(0, 0, 1).as_bytes());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_tiles() -> Result<()> {
+        let backend = MockHttpRangeClient::new(tiff(true, false));
+        let client = AsyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        let mut reader = AsyncCogReader::open(client).await?;
+        assert_eq!(reader.levels().len(), 2);
+        let tiles = reader.get_tiles(&[(1, 1, 0), (0, 2, 3), (1, 1, 1)]).await?;
+        assert_eq!(tiles[0], tile(1, 1, 0).as_bytes());
+        assert_eq!(tiles[1], tile(0, 2, 3).as_bytes());
+        assert_eq!(tiles[2], tile(1, 1, 1).as_bytes());
+        // (1, 1, 0) and (1, 1, 1) are adjacent
+        assert_eq!(backend.requests().len(), 3);
+        Ok(())
+    }
+}
@@ -0,0 +1,335 @@
+//! Random access to compressed files consisting of independently decompressible frames.
+use crate::error::{HttpError, Result};
+#[cfg(feature = "gzip")]
+use crate::gzip_index::GzipIndex;
+use crate::range_client::{AsyncHttpRangeClient, SyncHttpRangeClient};
+use crate::{AsyncBufferedHttpRangeClient, SyncBufferedHttpRangeClient};
+use bytes::Bytes;
+use std::io::{Read, Seek, SeekFrom};
+#[cfg(feature = "gzip")]
+use std::sync::Arc;
+
+/// Compressed frame and the uncompressed data range it decompresses to
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub compressed_offset: u64,
+    pub compressed_len: u64,
+    pub uncompressed_offset: u64,
+    pub uncompressed_len: u64,
+}
+
+/// Compression format of the frames
+#[derive(Clone)]
+enum Codec {
+    #[cfg(feature = "gzip")]
+    Bgzf,
+    #[cfg(feature = "gzip")]
+    IndexedGzip(Arc<GzipIndex>),
+    #[cfg(feature = "zstd")]
+    ZstdSeekable,
+}
+
+impl Codec {
+    fn decode(&self, index: usize, frame: &Frame, data: &[u8]) -> Result<Vec<u8>> {
+        let decoded = match self {
+            #[cfg(feature = "gzip")]
+            Codec::Bgzf => crate::bgzf::decode_block(data, frame.uncompressed_len)?,
+            #[cfg(feature = "gzip")]
+            Codec::IndexedGzip(gzip_index) => {
+                gzip_index.inflate(index, data, frame.uncompressed_len as usize)?
+            }
+            #[cfg(feature = "zstd")]
+            Codec::ZstdSeekable => {
+                crate::zstd_seekable::decode_frame(data, frame.uncompressed_len)?
+            }
+        };
+        if decoded.len() as u64 != frame.uncompressed_len {
+            return Err(HttpError::HttpError(format!(
+                "frame {index} decompressed to {} instead of {} bytes",
+                decoded.len(),
+                frame.uncompressed_len
+            )));
+        }
+        Ok(decoded)
+    }
+}
+
+pub(crate) fn decode_error(error: impl std::fmt::Display) -> HttpError {
+    HttpError::HttpError(format!("decompression failed: {error}"))
+}
+
+/// Frames with their uncompressed offsets and the last decompressed frame
+#[derive(Clone)]
+struct FrameIndex {
+    frames: Vec<Frame>,
+    codec: Codec,
+    cached: Option<(usize, Bytes)>,
+}
+
+impl FrameIndex {
+    fn new(frames: Vec<Frame>, codec: Codec) -> Self {
+        FrameIndex {
+            frames,
+            codec,
+            cached: None,
+        }
+    }
+
+    fn uncompressed_len(&self) -> u64 {
+        self.frames.last().map_or(0, |frame| {
+            frame.uncompressed_offset + frame.uncompressed_len
+        })
+    }
+
+    /// Index of the frame containing uncompressed offset `pos`
+    fn frame_at(&self, pos: u64) -> Option<usize> {
+        let index = self
+            .frames
+            .partition_point(|frame| frame.uncompressed_offset + frame.uncompressed_len <= pos);
+        (index < self.frames.len()).then_some(index)
+    }
+
+    fn cached(&self, index: usize) -> Option<Bytes> {
+        match &self.cached {
+            Some((cached, data)) if *cached == index => Some(data.clone()),
+            _ => None,
+        }
+    }
+
+    fn decode(&mut self, index: usize, compressed: &[u8]) -> Result<Bytes> {
+        let data = Bytes::from(self.codec.decode(index, &self.frames[index], compressed)?);
+        self.cached = Some((index, data.clone()));
+        Ok(data)
+    }
+
+    /// Uncompressed position of a BGZF virtual offset
+    fn virtual_offset(&self, virtual_offset: u64) -> Option<u64> {
+        let (compressed, within) = (virtual_offset >> 16, virtual_offset & 0xffff);
+        let index = self
+            .frames
+            .binary_search_by_key(&compressed, |frame| frame.compressed_offset)
+            .ok()?;
+        Some(self.frames[index].uncompressed_offset + within)
+    }
+}
+
+/// Reader of uncompressed data from compressed files with a frame index
+///
+/// Only the frames containing requested data are fetched and decompressed.
+/// The last decompressed frame is cached.
+pub struct SyncCompressedReader<T: SyncHttpRangeClient> {
+    client: SyncBufferedHttpRangeClient<T>,
+    index: FrameIndex,
+    /// Uncompressed position
+    pos: u64,
+}
+
+impl<T: SyncHttpRangeClient> SyncCompressedReader<T> {
+    fn new(client: SyncBufferedHttpRangeClient<T>, frames: Vec<Frame>, codec: Codec) -> Self {
+        SyncCompressedReader {
+            client,
+            index: FrameIndex::new(frames, codec),
+            pos: 0,
+        }
+    }
+
+    /// Open a BGZF file by reading the header of every block.
+    ///
+    /// This needs one request per 64 KiB block. Use [bgzf_with_index](Self::bgzf_with_index)
+    /// for large files.
+    #[cfg(feature = "gzip")]
+    pub fn bgzf(mut client: SyncBufferedHttpRangeClient<T>) -> Result<Self> {
+        let frames = crate::bgzf::sync::scan_blocks(&mut client)?;
+        Ok(Self::new(client, frames, Codec::Bgzf))
+    }
+
+    /// Open a BGZF file with the content of its `.gzi` index.
+    #[cfg(feature = "gzip")]
+    pub fn bgzf_with_index(mut client: SyncBufferedHttpRangeClient<T>, gzi: &[u8]) -> Result<Self> {
+        let frames = crate::bgzf::sync::indexed_blocks(&mut client, gzi)?;
+        Ok(Self::new(client, frames, Codec::Bgzf))
+    }
+
+    /// Open a gzip file with a zran-style index of access points.
+    #[cfg(feature = "gzip")]
+    pub fn indexed_gzip(client: SyncBufferedHttpRangeClient<T>, index: GzipIndex) -> Self {
+        let frames = index.frames();
+        Self::new(client, frames, Codec::IndexedGzip(Arc::new(index)))
+    }
+
+    /// Open a file in the Zstandard seekable format by reading its seek table.
+    #[cfg(feature = "zstd")]
+    pub fn zstd_seekable(mut client: SyncBufferedHttpRangeClient<T>) -> Result<Self> {
+        let frames = crate::zstd_seekable::sync::seek_table(&mut client)?;
+        Ok(Self::new(client, frames, Codec::ZstdSeekable))
+    }
+
+    /// Frames of the compressed file
+    pub fn frames(&self) -> &[Frame] {
+        &self.index.frames
+    }
+
+    /// Total uncompressed length
+    pub fn uncompressed_len(&self) -> u64 {
+        self.index.uncompressed_len()
+    }
+
+    /// Seek to a BGZF virtual offset (`compressed block offset << 16 | offset in block`),
+    /// as used by BAI and tabix indexes.
+    pub fn seek_virtual(&mut self, virtual_offset: u64) -> Result<u64> {
+        self.pos = self.index.virtual_offset(virtual_offset).ok_or_else(|| {
+            HttpError::HttpError(format!("no block at virtual offset {virtual_offset}"))
+        })?;
+        Ok(self.pos)
+    }
+
+    /// Uncompressed data of frame `index`
+    fn frame(&mut self, index: usize) -> Result<Bytes> {
+        if let Some(data) = self.index.cached(index) {
+            return Ok(data);
+        }
+        let frame = &self.index.frames[index];
+        let compressed = self.client.get_range(
+            frame.compressed_offset as usize,
+            frame.compressed_len as usize,
+        )?;
+        self.index.decode(index, compressed)
+    }
+}
+
+impl<T: SyncHttpRangeClient> Read for SyncCompressedReader<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Some(index) = self.index.frame_at(self.pos) else {
+            return Ok(0);
+        };
+        let data = self
+            .frame(index)
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        let from = (self.pos - self.index.frames[index].uncompressed_offset) as usize;
+        let length = buf.len().min(data.len() - from);
+        buf[..length].copy_from_slice(&data[from..from + length]);
+        self.pos += length as u64;
+        Ok(length)
+    }
+}
+
+impl<T: SyncHttpRangeClient> Seek for SyncCompressedReader<T> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => self.uncompressed_len().checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        self.pos = pos.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
+
+/// Reader of uncompressed data from compressed files with a frame index
+///
+/// Only the frames containing requested data are fetched and decompressed.
+/// The last decompressed frame is cached.
+pub struct AsyncCompressedReader<T: AsyncHttpRangeClient> {
+    client: AsyncBufferedHttpRangeClient<T>,
+    index: FrameIndex,
+}
+
+impl<T: AsyncHttpRangeClient> AsyncCompressedReader<T> {
+    fn new(client: AsyncBufferedHttpRangeClient<T>, frames: Vec<Frame>, codec: Codec) -> Self {
+        AsyncCompressedReader {
+            client,
+            index: FrameIndex::new(frames, codec),
+        }
+    }
+
+    /// Open a BGZF file by reading the header of every block.
+    ///
+    /// This needs one request per 64 KiB block. Use [bgzf_with_index](Self::bgzf_with_index)
+    /// for large files.
+    #[cfg(feature = "gzip")]
+    pub async fn bgzf(mut client: AsyncBufferedHttpRangeClient<T>) -> Result<Self> {
+        let frames = crate::bgzf::nonblocking::scan_blocks(&mut client).await?;
+        Ok(Self::new(client, frames, Codec::Bgzf))
+    }
+
+    /// Open a BGZF file with the content of its `.gzi` index.
+    #[cfg(feature = "gzip")]
+    pub async fn bgzf_with_index(
+        mut client: AsyncBufferedHttpRangeClient<T>,
+        gzi: &[u8],
+    ) -> Result<Self> {
+        let frames = crate::bgzf::nonblocking::indexed_blocks(&mut client, gzi).await?;
+        Ok(Self::new(client, frames, Codec::Bgzf))
+    }
+
+    /// Open a gzip file with a zran-style index of access points.
+    #[cfg(feature = "gzip")]
+    pub fn indexed_gzip(client: AsyncBufferedHttpRangeClient<T>, index: GzipIndex) -> Self {
+        let frames = index.frames();
+        Self::new(client, frames, Codec::IndexedGzip(Arc::new(index)))
+    }
+
+    /// Open a file in the Zstandard seekable format by reading its seek table.
+    #[cfg(feature = "zstd")]
+    pub async fn zstd_seekable(mut client: AsyncBufferedHttpRangeClient<T>) -> Result<Self> {
+        let frames = crate::zstd_seekable::nonblocking::seek_table(&mut client).await?;
+        Ok(Self::new(client, frames, Codec::ZstdSeekable))
+    }
+
+    /// Frames of the compressed file
+    pub fn frames(&self) -> &[Frame] {
+        &self.index.frames
+    }
+
+    /// Total uncompressed length
+    pub fn uncompressed_len(&self) -> u64 {
+        self.index.uncompressed_len()
+    }
+
+    /// Uncompressed position of a BGZF virtual offset
+    /// (`compressed block offset << 16 | offset in block`).
+    pub fn virtual_offset_position(&self, virtual_offset: u64) -> Option<u64> {
+        self.index.virtual_offset(virtual_offset)
+    }
+
+    /// Get uncompressed bytes of the given range.
+    ///
+    /// Returns fewer bytes at the end of the data.
+    pub async fn get_range(&mut self, begin: u64, length: usize) -> Result<Vec<u8>> {
+        let available = self.uncompressed_len().saturating_sub(begin);
+        let mut data = Vec::with_capacity(length.min(available as usize));
+        let mut pos = begin;
+        while data.len() < length {
+            let Some(index) = self.index.frame_at(pos) else {
+                break;
+            };
+            let frame = self.frame(index).await?;
+            let from = (pos - self.index.frames[index].uncompressed_offset) as usize;
+            let to = frame.len().min(from + length - data.len());
+            data.extend_from_slice(&frame[from..to]);
+            pos += (to - from) as u64;
+        }
+        Ok(data)
+    }
+
+    /// Uncompressed data of frame `index`
+    async fn frame(&mut self, index: usize) -> Result<Bytes> {
+        if let Some(data) = self.index.cached(index) {
+            return Ok(data);
+        }
+        let frame = &self.index.frames[index];
+        let compressed = self
+            .client
+            .get_range(
+                frame.compressed_offset as usize,
+                frame.compressed_len as usize,
+            )
+            .await?;
+        self.index.decode(index, compressed)
+    }
+}
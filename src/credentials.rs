@@ -0,0 +1,80 @@
+//! Credential provider for expiring tokens and presigned URLs.
+use crate::error::Result;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// Request URL and headers supplied by a [CredentialProvider]
+#[derive(Clone, Debug, Default)]
+pub struct Credentials {
+    /// URL replacing the reader URL, e.g. a presigned URL
+    pub url: Option<String>,
+    /// Request headers, e.g. `Authorization`
+    pub headers: Vec<(String, String)>,
+}
+
+impl Credentials {
+    /// Credentials with a `Authorization: Bearer` header.
+    pub fn bearer(token: &str) -> Self {
+        Credentials {
+            url: None,
+            headers: vec![("Authorization".to_string(), format!("Bearer {token}"))],
+        }
+    }
+
+    /// Credentials with a presigned URL.
+    pub fn url(url: &str) -> Self {
+        Credentials {
+            url: Some(url.to_string()),
+            headers: Vec::new(),
+        }
+    }
+}
+
+/// Provider of credentials, consulted before every request
+///
+/// `refresh` is `true` when the previous request failed with status 401 or 403,
+/// asking for a new token or a re-signed URL. The failed request is then retried once.
+pub trait CredentialProvider: Send + Sync {
+    fn credentials(&self, refresh: bool) -> Result<Credentials>;
+}
+
+impl<F> CredentialProvider for F
+where
+    F: Fn(bool) -> Result<Credentials> + Send + Sync,
+{
+    fn credentials(&self, refresh: bool) -> Result<Credentials> {
+        self(refresh)
+    }
+}
+
+impl<P: CredentialProvider + ?Sized> CredentialProvider for Arc<P> {
+    fn credentials(&self, refresh: bool) -> Result<Credentials> {
+        (**self).credentials(refresh)
+    }
+}
+
+/// Async provider of credentials, e.g. fetching tokens from an identity service
+///
+/// Implemented for all [CredentialProvider]s.
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait]
+pub trait AsyncCredentialProvider: Send + Sync {
+    async fn credentials(&self, refresh: bool) -> Result<Credentials>;
+}
+
+/// Async provider of credentials, e.g. fetching tokens from an identity service
+///
+/// Implemented for all [CredentialProvider]s.
+#[cfg(target_arch = "wasm32")]
+#[async_trait(?Send)]
+pub trait AsyncCredentialProvider {
+    async fn credentials(&self, refresh: bool) -> Result<Credentials>;
+}
+
+#[cfg_attr(not(target_arch = "wasm32"), async_trait)]
+#[cfg_attr(target_arch = "wasm32", async_trait(?Send))]
+impl<P: CredentialProvider + ?Sized> AsyncCredentialProvider for P {
+    async fn credentials(&self, refresh: bool) -> Result<Credentials> {
+        CredentialProvider::credentials(self, refresh)
+    }
+}
@@ -0,0 +1,751 @@
+//! Parallel chunked download of whole files or large ranges.
+use crate::buffered_range_client::{parse_content_length, unexpected_end};
+#[cfg(feature = "checksum")]
+use crate::checksum::{Checksum, Hasher};
+use crate::error::{HttpError, Result};
+use crate::range_client::{AsyncHttpRangeClient, RangeResponse, SyncHttpRangeClient};
+use crate::{AsyncBufferedHttpRangeClient, SyncBufferedHttpRangeClient};
+use bytes::Bytes;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Download progress passed to the progress callback
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Progress {
+    /// Bytes written so far
+    pub downloaded: u64,
+    /// Total bytes to download
+    pub total: u64,
+}
+
+/// Options for [download_to](SyncBufferedHttpRangeClient::download_to)
+#[derive(Clone)]
+pub struct DownloadOptions {
+    concurrency: usize,
+    chunk_size: usize,
+    range: Option<(u64, u64)>,
+    progress: Option<Arc<dyn Fn(Progress) + Send + Sync>>,
+    #[cfg(feature = "checksum")]
+    verify_checksum: bool,
+    #[cfg(feature = "checksum")]
+    checksum: Option<Checksum>,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            concurrency: 4,
+            chunk_size: 8 * 1024 * 1024,
+            range: None,
+            progress: None,
+            #[cfg(feature = "checksum")]
+            verify_checksum: false,
+            #[cfg(feature = "checksum")]
+            checksum: None,
+        }
+    }
+}
+
+impl DownloadOptions {
+    /// Download with 4 concurrent requests of 8 MiB.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set number of concurrent requests.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Set size of a single request.
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Download `length` bytes starting at `begin` instead of the whole file.
+    pub fn with_range(mut self, begin: u64, length: u64) -> Self {
+        self.range = Some((begin, length));
+        self
+    }
+
+    /// Call `progress` after every written chunk.
+    pub fn with_progress(mut self, progress: impl Fn(Progress) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    /// Verify the whole file against the checksum reported in the response headers.
+    ///
+    /// Fails if the server doesn't report a supported checksum.
+    /// Not supported for downloads of a range.
+    #[cfg(feature = "checksum")]
+    pub fn with_checksum_verification(mut self) -> Self {
+        self.verify_checksum = true;
+        self
+    }
+
+    /// Verify the whole file against `checksum`.
+    #[cfg(feature = "checksum")]
+    pub fn with_expected_checksum(mut self, checksum: Checksum) -> Self {
+        self.checksum = Some(checksum);
+        self
+    }
+
+    /// Fail for checksum verification of partial downloads
+    #[cfg(feature = "checksum")]
+    fn check_verification_range(&self) -> Result<()> {
+        if (self.verify_checksum || self.checksum.is_some()) && self.range.is_some() {
+            return Err(HttpError::HttpError(
+                "checksum verification requires a complete download".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// `(begin, length)` chunks of `ranges`, grouped into batches of concurrent requests
+    fn batches(&self, ranges: &[(u64, u64)]) -> Vec<Vec<(u64, u64)>> {
+        let chunk_size = self.chunk_size as u64;
+        let chunks: Vec<(u64, u64)> = ranges
+            .iter()
+            .flat_map(|&(begin, length)| {
+                (0..length.div_ceil(chunk_size)).map(move |i| {
+                    let chunk_begin = begin + i * chunk_size;
+                    (chunk_begin, chunk_size.min(begin + length - chunk_begin))
+                })
+            })
+            .collect();
+        chunks
+            .chunks(self.concurrency)
+            .map(|batch| batch.to_vec())
+            .collect()
+    }
+}
+
+/// Range header values of `(begin, length)` chunks
+fn range_headers(chunks: &[(u64, u64)]) -> Vec<String> {
+    chunks
+        .iter()
+        .map(|(begin, length)| format!("bytes={begin}-{}", begin + length - 1))
+        .collect()
+}
+
+fn io_error(error: std::io::Error) -> HttpError {
+    HttpError::HttpError(error.to_string())
+}
+
+fn unknown_length() -> HttpError {
+    HttpError::HttpError("content length unknown".to_string())
+}
+
+/// Data of the chunk `(begin, length)`, rejecting responses for a different range
+///
+/// A response without `Content-Range` holds the complete file and is rejected if
+/// `partial` is set. Backends not reporting response info are trusted.
+fn chunk_data(
+    (begin, length): (u64, u64),
+    response: RangeResponse,
+    partial: bool,
+) -> Result<Bytes> {
+    let range_begin = response.range_begin();
+    let complete = response.content_range.is_none() && response.url.is_some();
+    if range_begin.is_some_and(|b| b != begin) || (complete && (partial || begin != 0)) {
+        return Err(HttpError::HttpError(format!(
+            "response doesn't match requested range bytes={begin}-{}",
+            begin + length - 1
+        )));
+    }
+    match response.data.len() as u64 {
+        len if len < length => Err(unexpected_end(length as usize)),
+        len if len > length => Err(HttpError::HttpError(format!(
+            "received {len} bytes for a chunk of {length} bytes"
+        ))),
+        _ => Ok(response.data),
+    }
+}
+
+/// Write downloaded chunks in order and report progress
+struct ChunkWriter<'a, W: Write> {
+    writer: &'a mut W,
+    options: &'a DownloadOptions,
+    progress: Progress,
+    /// Download of a range or of multiple chunks, requiring partial responses
+    partial: bool,
+    /// Expected checksum and hasher of the written data
+    #[cfg(feature = "checksum")]
+    checksum: Option<(Checksum, Hasher)>,
+}
+
+impl<'a, W: Write> ChunkWriter<'a, W> {
+    fn new(writer: &'a mut W, options: &'a DownloadOptions, length: u64) -> Self {
+        ChunkWriter {
+            writer,
+            options,
+            progress: Progress {
+                downloaded: 0,
+                total: length,
+            },
+            partial: options.range.is_some() || length > options.chunk_size as u64,
+            #[cfg(feature = "checksum")]
+            checksum: None,
+        }
+    }
+
+    /// Verify the written data against `checksum`.
+    #[cfg(feature = "checksum")]
+    fn verify(&mut self, checksum: Option<Checksum>) {
+        self.checksum = checksum.map(|checksum| {
+            let hasher = Hasher::new(checksum.algorithm);
+            (checksum, hasher)
+        });
+    }
+
+    fn write(&mut self, batch: &[(u64, u64)], responses: Vec<RangeResponse>) -> Result<()> {
+        for (&range, response) in batch.iter().zip(responses) {
+            let chunk = chunk_data(range, response, self.partial)?;
+            self.writer.write_all(&chunk).map_err(io_error)?;
+            #[cfg(feature = "checksum")]
+            if let Some((_, hasher)) = &mut self.checksum {
+                hasher.update(&chunk);
+            }
+            self.progress.downloaded += chunk.len() as u64;
+            if let Some(progress) = &self.options.progress {
+                progress(self.progress);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<u64> {
+        self.writer.flush().map_err(io_error)?;
+        if self.progress.downloaded < self.progress.total {
+            return Err(unexpected_end(self.progress.total as usize));
+        }
+        #[cfg(feature = "checksum")]
+        if let Some((checksum, hasher)) = self.checksum {
+            checksum.check(&hasher.finish())?;
+        }
+        Ok(self.progress.downloaded)
+    }
+}
+
+/// First line of the sidecar state file
+const STATE_HEADER: &str = "http-range-client download v1";
+
+/// Resumable download into a target file
+///
+/// Completed ranges are appended to a sidecar state file `<target>.download`,
+/// which starts with the ETag and the range of the remote resource.
+struct ResumableDownload {
+    target: File,
+    state: File,
+    state_path: PathBuf,
+    /// Remote offset of the target file
+    begin: u64,
+    /// Completed `(begin, length)` ranges relative to the target file
+    completed: Vec<(u64, u64)>,
+    progress: Progress,
+}
+
+impl ResumableDownload {
+    /// Continue a previous download of the same remote resource or start a new one.
+    ///
+    /// A previous download is only continued if the remote resource has an ETag
+    /// matching the one recorded in the state file.
+    fn open(path: &Path, etag: Option<&str>, begin: u64, length: u64) -> Result<Self> {
+        let mut state_path = path.as_os_str().to_owned();
+        state_path.push(".download");
+        let state_path = PathBuf::from(state_path);
+        let identity = format!(
+            "{STATE_HEADER}\netag {}\nrange {begin} {length}\n",
+            etag.unwrap_or("-")
+        );
+        let completed = match std::fs::read_to_string(&state_path) {
+            Ok(state) if etag.is_some() && path.exists() => state
+                .strip_prefix(&identity)
+                .map(|ranges| ranges.lines().filter_map(parse_range).collect()),
+            _ => None,
+        };
+        let resume = completed.is_some();
+        let target = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(!resume)
+            .open(path)
+            .map_err(io_error)?;
+        target.set_len(length).map_err(io_error)?;
+        let state = if resume {
+            OpenOptions::new().append(true).open(&state_path)
+        } else {
+            File::create(&state_path).and_then(|mut state| {
+                state.write_all(identity.as_bytes())?;
+                Ok(state)
+            })
+        }
+        .map_err(io_error)?;
+        let completed: Vec<(u64, u64)> = completed.unwrap_or_default();
+        let downloaded = completed.iter().map(|(_, length)| length).sum();
+        Ok(ResumableDownload {
+            target,
+            state,
+            state_path,
+            begin,
+            completed,
+            progress: Progress {
+                downloaded,
+                total: length,
+            },
+        })
+    }
+
+    /// Missing remote `(begin, length)` ranges
+    fn missing(&self) -> Vec<(u64, u64)> {
+        let mut completed = self.completed.clone();
+        completed.sort_unstable();
+        let mut missing = Vec::new();
+        let mut pos = 0;
+        for (begin, length) in completed {
+            if begin > pos {
+                missing.push((self.begin + pos, begin - pos));
+            }
+            pos = pos.max(begin + length);
+        }
+        if pos < self.progress.total {
+            missing.push((self.begin + pos, self.progress.total - pos));
+        }
+        missing
+    }
+
+    /// Write downloaded chunks at their position and record them as completed.
+    fn write(
+        &mut self,
+        batch: &[(u64, u64)],
+        responses: Vec<RangeResponse>,
+        options: &DownloadOptions,
+    ) -> Result<()> {
+        for (&(begin, length), response) in batch.iter().zip(responses) {
+            // A complete response is a changed file with `If-Range`
+            let chunk = chunk_data((begin, length), response, true)?;
+            let offset = begin - self.begin;
+            self.target
+                .seek(SeekFrom::Start(offset))
+                .and_then(|_| self.target.write_all(&chunk))
+                .map_err(io_error)?;
+            writeln!(self.state, "{offset} {length}").map_err(io_error)?;
+            self.completed.push((offset, length));
+            self.progress.downloaded += length;
+            if let Some(progress) = &options.progress {
+                progress(self.progress);
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the state file of the completed download.
+    fn finish(self) -> Result<u64> {
+        self.target.sync_all().map_err(io_error)?;
+        drop(self.state);
+        std::fs::remove_file(&self.state_path).map_err(io_error)?;
+        Ok(self.progress.downloaded)
+    }
+}
+
+/// `If-Range` header for a strong ETag, making servers send the complete file if it changed
+/// ETag and download range from the `ETag` and `Content-Length` values of a HEAD
+/// request, falling back to the `known_length` of a previous response
+fn parse_resume_target(
+    headers: Vec<Option<String>>,
+    options: &DownloadOptions,
+    known_length: Option<u64>,
+) -> Result<(Option<String>, (u64, u64))> {
+    let mut headers = headers.into_iter();
+    let etag = headers.next().flatten();
+    let content_length = parse_content_length(headers.next().flatten().as_deref())?;
+    let range = match options.range {
+        Some(range) => range,
+        None => (
+            0,
+            content_length.or(known_length).ok_or_else(unknown_length)?,
+        ),
+    };
+    Ok((etag, range))
+}
+
+fn if_range(etag: Option<&str>) -> Vec<(String, String)> {
+    match etag {
+        Some(etag) if !etag.starts_with("W/") => vec![("If-Range".to_string(), etag.to_string())],
+        _ => Vec::new(),
+    }
+}
+
+/// Parse a completed range line of a state file
+fn parse_range(line: &str) -> Option<(u64, u64)> {
+    let (begin, length) = line.split_once(' ')?;
+    Some((begin.parse().ok()?, length.parse().ok()?))
+}
+
+impl<T: AsyncHttpRangeClient> AsyncBufferedHttpRangeClient<T> {
+    /// `(begin, length)` of the range selected in `options` or of the whole file
+    async fn download_range(&mut self, options: &DownloadOptions) -> Result<(u64, u64)> {
+        if let Some(range) = options.range {
+            return Ok(range);
+        }
+        let length = match self.content_length() {
+            Some(length) => length,
+            None => self
+                .get_content_length()
+                .await?
+                .ok_or_else(unknown_length)?,
+        };
+        Ok((0, length))
+    }
+
+    /// ETag and `(begin, length)` of a resumable download, with a single HEAD request
+    async fn resume_target(
+        &mut self,
+        options: &DownloadOptions,
+    ) -> Result<(Option<String>, (u64, u64))> {
+        let headers = self
+            .head_response_headers(&["etag", "content-length"])
+            .await?;
+        parse_resume_target(headers, options, self.content_length())
+    }
+
+    /// Download the file or the range selected in `options` with concurrent requests
+    /// and write it to `writer`. Returns the number of bytes written.
+    ///
+    /// The file length is taken from a previous response or requested with a HEAD request.
+    pub async fn download_to<W: Write>(
+        &mut self,
+        writer: &mut W,
+        options: &DownloadOptions,
+    ) -> Result<u64> {
+        let (begin, length) = self.download_range(options).await?;
+        let mut chunk_writer = ChunkWriter::new(writer, options, length);
+        #[cfg(feature = "checksum")]
+        {
+            options.check_verification_range()?;
+            let checksum = self
+                .download_checksum(options.verify_checksum, options.checksum.as_ref())
+                .await?;
+            chunk_writer.verify(checksum);
+        }
+        for batch in options.batches(&[(begin, length)]) {
+            let responses = self.fetch_ranges(&range_headers(&batch), &[]).await?;
+            chunk_writer.write(&batch, responses)?;
+        }
+        chunk_writer.finish()
+    }
+
+    /// Download the file or the range selected in `options` into a local file.
+    pub async fn download_to_file(
+        &mut self,
+        path: impl AsRef<Path>,
+        options: &DownloadOptions,
+    ) -> Result<u64> {
+        let file = File::create(path).map_err(io_error)?;
+        self.download_to(&mut BufWriter::new(file), options).await
+    }
+
+    /// Download into a local file, continuing an interrupted download.
+    ///
+    /// Completed ranges are recorded in the state file `<path>.download`, which is
+    /// removed after a successful download. A previous download is only continued
+    /// if the ETag of the remote file is unchanged. Range requests are sent with
+    /// `If-Range` for strong ETags and fail if the file changes during the download.
+    pub async fn download_resumable(
+        &mut self,
+        path: impl AsRef<Path>,
+        options: &DownloadOptions,
+    ) -> Result<u64> {
+        #[cfg(feature = "checksum")]
+        let checksum = {
+            options.check_verification_range()?;
+            self.download_checksum(options.verify_checksum, options.checksum.as_ref())
+                .await?
+        };
+        let (etag, (begin, length)) = self.resume_target(options).await?;
+        let mut download = ResumableDownload::open(path.as_ref(), etag.as_deref(), begin, length)?;
+        let if_range = if_range(etag.as_deref());
+        for batch in options.batches(&download.missing()) {
+            let responses = self.fetch_ranges(&range_headers(&batch), &if_range).await?;
+            download.write(&batch, responses, options)?;
+        }
+        let downloaded = download.finish()?;
+        #[cfg(feature = "checksum")]
+        if let Some(checksum) = checksum {
+            checksum.verify_file(path.as_ref())?;
+        }
+        Ok(downloaded)
+    }
+}
+
+impl<T: SyncHttpRangeClient + Sync> SyncBufferedHttpRangeClient<T> {
+    /// `(begin, length)` of the range selected in `options` or of the whole file
+    fn download_range(&mut self, options: &DownloadOptions) -> Result<(u64, u64)> {
+        if let Some(range) = options.range {
+            return Ok(range);
+        }
+        let length = match self.content_length() {
+            Some(length) => length,
+            None => self.get_content_length()?.ok_or_else(unknown_length)?,
+        };
+        Ok((0, length))
+    }
+
+    /// ETag and `(begin, length)` of a resumable download, with a single HEAD request
+    fn resume_target(&mut self, options: &DownloadOptions) -> Result<(Option<String>, (u64, u64))> {
+        let headers = self.head_response_headers(&["etag", "content-length"])?;
+        parse_resume_target(headers, options, self.content_length())
+    }
+
+    /// Download the file or the range selected in `options` with concurrent requests
+    /// from scoped threads and write it to `writer`. Returns the number of bytes written.
+    ///
+    /// The file length is taken from a previous response or requested with a HEAD request.
+    pub fn download_to<W: Write>(
+        &mut self,
+        writer: &mut W,
+        options: &DownloadOptions,
+    ) -> Result<u64> {
+        let (begin, length) = self.download_range(options)?;
+        let mut chunk_writer = ChunkWriter::new(writer, options, length);
+        #[cfg(feature = "checksum")]
+        {
+            options.check_verification_range()?;
+            let checksum =
+                self.download_checksum(options.verify_checksum, options.checksum.as_ref())?;
+            chunk_writer.verify(checksum);
+        }
+        for batch in options.batches(&[(begin, length)]) {
+            let responses = self.fetch_ranges(&range_headers(&batch), &[])?;
+            chunk_writer.write(&batch, responses)?;
+        }
+        chunk_writer.finish()
+    }
+
+    /// Download the file or the range selected in `options` into a local file.
+    pub fn download_to_file(
+        &mut self,
+        path: impl AsRef<Path>,
+        options: &DownloadOptions,
+    ) -> Result<u64> {
+        let file = File::create(path).map_err(io_error)?;
+        self.download_to(&mut BufWriter::new(file), options)
+    }
+
+    /// Download into a local file, continuing an interrupted download.
+    ///
+    /// Completed ranges are recorded in the state file `<path>.download`, which is
+    /// removed after a successful download. A previous download is only continued
+    /// if the ETag of the remote file is unchanged. Range requests are sent with
+    /// `If-Range` for strong ETags and fail if the file changes during the download.
+    pub fn download_resumable(
+        &mut self,
+        path: impl AsRef<Path>,
+        options: &DownloadOptions,
+    ) -> Result<u64> {
+        #[cfg(feature = "checksum")]
+        let checksum = {
+            options.check_verification_range()?;
+            self.download_checksum(options.verify_checksum, options.checksum.as_ref())?
+        };
+        let (etag, (begin, length)) = self.resume_target(options)?;
+        let mut download = ResumableDownload::open(path.as_ref(), etag.as_deref(), begin, length)?;
+        let if_range = if_range(etag.as_deref());
+        for batch in options.batches(&download.missing()) {
+            let responses = self.fetch_ranges(&range_headers(&batch), &if_range)?;
+            download.write(&batch, responses, options)?;
+        }
+        let downloaded = download.finish()?;
+        #[cfg(feature = "checksum")]
+        if let Some(checksum) = checksum {
+            checksum.verify_file(path.as_ref())?;
+        }
+        Ok(downloaded)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_client::MockHttpRangeClient;
+    use std::sync::Mutex;
+
+    #[test]
+    fn sync_download() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(10_000);
+        let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        let reported = Arc::new(Mutex::new(Vec::new()));
+        let progress = reported.clone();
+        let options = DownloadOptions::new()
+            .with_concurrency(3)
+            .with_chunk_size(3000)
+            .with_progress(move |p| progress.lock().unwrap().push(p.downloaded));
+        let mut data = Vec::new();
+        assert_eq!(client.download_to(&mut data, &options)?, 10_000);
+        assert!(data.iter().enumerate().all(|(i, b)| *b == (i % 251) as u8));
+        assert_eq!(*reported.lock().unwrap(), [3000, 6000, 9000, 10_000]);
+        let mut requests = backend.requests();
+        requests.sort();
+        assert_eq!(
+            requests,
+            [
+                "GET bytes=0-2999",
+                "GET bytes=3000-5999",
+                "GET bytes=6000-8999",
+                "GET bytes=9000-9999",
+                "HEAD content-length"
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_download_range() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(10_000);
+        let mut client = AsyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        let options = DownloadOptions::new()
+            .with_chunk_size(1000)
+            .with_range(500, 2500);
+        let mut data = Vec::new();
+        assert_eq!(client.download_to(&mut data, &options).await?, 2500);
+        assert_eq!(&data[..2], [(500 % 251) as u8, (501 % 251) as u8]);
+        assert_eq!(backend.requests().len(), 3);
+
+        // Range beyond the end of the file
+        let options = options.with_range(9500, 1000);
+        let result = client.download_to(&mut Vec::new(), &options).await;
+        assert!(result.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sync_download_without_range_support() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(10_000);
+        backend.ignore_ranges();
+        let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        let options = DownloadOptions::new().with_chunk_size(3000);
+        assert!(client.download_to(&mut Vec::new(), &options).is_err());
+        let options = options.with_range(0, 3000);
+        assert!(client.download_to(&mut Vec::new(), &options).is_err());
+
+        // Complete file in a single chunk
+        let options = DownloadOptions::new();
+        let mut data = Vec::new();
+        assert_eq!(client.download_to(&mut data, &options)?, 10_000);
+        assert_eq!(data.len(), 10_000);
+        Ok(())
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("http-range-client-{}-{name}", std::process::id()))
+    }
+
+    #[test]
+    fn sync_resume() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(10_000);
+        backend.set_etag("\"v1\"");
+        let path = temp_path("sync_resume");
+        // Interrupt download after 3 chunks
+        let failing = backend.clone();
+        let options = DownloadOptions::new()
+            .with_concurrency(1)
+            .with_chunk_size(1000)
+            .with_progress(move |p| {
+                if p.downloaded == 3000 {
+                    failing.fail_next(&[500]);
+                }
+            });
+        let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        assert!(client.download_resumable(&path, &options).is_err());
+
+        let backend = MockHttpRangeClient::with_len(10_000);
+        backend.set_etag("\"v1\"");
+        let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        let options = DownloadOptions::new().with_chunk_size(4000);
+        assert_eq!(client.download_resumable(&path, &options)?, 10_000);
+        assert_eq!(
+            backend.requests(),
+            [
+                "HEAD etag, content-length",
+                "GET bytes=3000-6999",
+                "GET bytes=7000-9999"
+            ]
+        );
+        let data = std::fs::read(&path).unwrap();
+        assert!(data.iter().enumerate().all(|(i, b)| *b == (i % 251) as u8));
+        assert!(!path.with_extension("download").exists());
+        std::fs::remove_file(&path).unwrap();
+        Ok(())
+    }
+
+    #[test]
+    fn sync_resume_changed_during_download() {
+        let backend = MockHttpRangeClient::with_len(5000);
+        backend.set_etag("\"v1\"");
+        let path = temp_path("sync_resume_changed");
+        let changing = backend.clone();
+        let options = DownloadOptions::new()
+            .with_concurrency(1)
+            .with_chunk_size(1000)
+            .with_progress(move |p| {
+                if p.downloaded == 2000 {
+                    changing.set_etag("\"v2\"");
+                }
+            });
+        let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        assert!(client.download_resumable(&path, &options).is_err());
+        let if_range = ("If-Range".to_string(), "\"v1\"".to_string());
+        assert!(backend
+            .request_headers()
+            .last()
+            .unwrap()
+            .contains(&if_range));
+        assert_eq!(
+            backend
+                .requests()
+                .iter()
+                .filter(|r| r.starts_with("GET"))
+                .count(),
+            3
+        );
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(path.with_extension("download")).ok();
+    }
+
+    #[tokio::test]
+    async fn async_resume_changed_etag() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(5000);
+        backend.set_etag("\"v1\"");
+        let path = temp_path("async_resume");
+        let failing = backend.clone();
+        let options = DownloadOptions::new()
+            .with_concurrency(1)
+            .with_chunk_size(1000)
+            .with_progress(move |p| {
+                if p.downloaded == 2000 {
+                    failing.fail_next(&[404]);
+                }
+            });
+        let mut client = AsyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        assert!(client.download_resumable(&path, &options).await.is_err());
+
+        backend.set_etag("\"v2\"");
+        let mut client = AsyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        let options = DownloadOptions::new().with_chunk_size(1000);
+        assert_eq!(client.download_resumable(&path, &options).await?, 5000);
+        // Restarted from zero
+        let requests = backend.requests();
+        assert_eq!(
+            requests.iter().filter(|r| r.starts_with("GET")).count(),
+            3 + 5
+        );
+        assert_eq!(requests.iter().filter(|r| r.starts_with("HEAD")).count(), 2);
+        std::fs::remove_file(&path).unwrap();
+        Ok(())
+    }
+}
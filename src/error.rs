@@ -7,6 +7,11 @@ pub enum HttpError {
     HttpStatus(u16),
     #[error("http error `{0}`")]
     HttpError(String),
+    #[error("integrity check failed: {0}")]
+    IntegrityError(String),
+    /// Connection or transfer failure, retried by a [RetryPolicy](crate::RetryPolicy)
+    #[error("transport error `{0}`")]
+    TransportError(String),
 }
 
 pub type Result<T> = std::result::Result<T, HttpError>;
@@ -0,0 +1,453 @@
+//! zran-style index of access points for random access to gzip files.
+use crate::buffered_range_client::unexpected_end;
+use crate::compressed::{decode_error, Frame};
+use crate::error::{HttpError, Result};
+use miniz_oxide::inflate::core::inflate_flags::{
+    TINFL_FLAG_HAS_MORE_INPUT, TINFL_FLAG_STOP_ON_BLOCK_BOUNDARY,
+    TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+};
+use miniz_oxide::inflate::core::{decompress, BlockBoundaryState, DecompressorOxide};
+use miniz_oxide::inflate::TINFLStatus;
+use std::convert::TryInto;
+use std::io::Read;
+
+/// Size of the deflate window preceding an access point
+const WINDOW_SIZE: usize = 32 * 1024;
+const GZIDX_MAGIC: &[u8; 5] = b"GZIDX";
+const GZIDX_VERSION: u8 = 1;
+/// Length of the GZIDX header up to the point entries
+const GZIDX_HEADER_LEN: usize = 7 + 8 + 8 + 4 + 4 + 4;
+const GZIDX_POINT_LEN: usize = 8 + 8 + 1 + 1;
+
+/// Position in the compressed stream where decompression can start
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AccessPoint {
+    /// Offset of the first complete byte of the next deflate block
+    pub compressed_offset: u64,
+    pub uncompressed_offset: u64,
+    /// Number of bits of the byte before `compressed_offset` belonging to the next block
+    pub bits: u8,
+    /// 32 KiB of uncompressed data preceding the access point, `None` at the stream start
+    pub window: Option<Vec<u8>>,
+}
+
+/// Index of access points of a single-member gzip file
+///
+/// Compatible with the GZIDX index files of `indexed_gzip`.
+#[derive(Clone, Debug)]
+pub struct GzipIndex {
+    compressed_size: u64,
+    uncompressed_size: u64,
+    spacing: u32,
+    points: Vec<AccessPoint>,
+}
+
+/// Length of the gzip header at the start of `data`, `None` if incomplete
+fn gzip_header_len(data: &[u8]) -> Result<Option<usize>> {
+    if data.len() < 10 {
+        return Ok(None);
+    }
+    if data[..3] != [0x1f, 0x8b, 8] {
+        return Err(HttpError::HttpError("no gzip header found".to_string()));
+    }
+    let flags = data[3];
+    let mut len = 10;
+    if flags & 4 != 0 {
+        // FEXTRA
+        let Some(xlen) = data.get(len..len + 2) else {
+            return Ok(None);
+        };
+        len += 2 + u16::from_le_bytes([xlen[0], xlen[1]]) as usize;
+    }
+    for flag in [8, 16] {
+        // FNAME and FCOMMENT are zero terminated
+        if flags & flag != 0 {
+            let Some(end) = data
+                .get(len..)
+                .and_then(|rest| rest.iter().position(|b| *b == 0))
+            else {
+                return Ok(None);
+            };
+            len += end + 1;
+        }
+    }
+    if flags & 2 != 0 {
+        // FHCRC
+        len += 2;
+    }
+    Ok((len <= data.len()).then_some(len))
+}
+
+/// Last 32 KiB of a circular output buffer ending at `pos`
+fn window(buf: &[u8], pos: usize, total: u64) -> Vec<u8> {
+    let mut window = vec![0; WINDOW_SIZE];
+    let available = (total as usize).min(WINDOW_SIZE);
+    for (i, byte) in window[WINDOW_SIZE - available..].iter_mut().enumerate() {
+        *byte = buf[(pos + buf.len() - available + i) % buf.len()];
+    }
+    window
+}
+
+fn inflate_error(status: TINFLStatus) -> HttpError {
+    decode_error(format!("{status:?}"))
+}
+
+impl GzipIndex {
+    /// Build an index by decompressing a complete gzip stream, creating an access
+    /// point at the first deflate block boundary after every `spacing` bytes.
+    ///
+    /// The stream can be read from any reader, e.g. a `SyncBufferedHttpRangeClient`.
+    pub fn build(mut reader: impl Read, spacing: u32) -> Result<Self> {
+        let io_error = |e: std::io::Error| HttpError::HttpError(e.to_string());
+        let mut input = Vec::new();
+        let mut chunk = vec![0; 64 * 1024];
+        let mut eof = false;
+        let mut fill = |input: &mut Vec<u8>, eof: &mut bool| -> Result<()> {
+            let n = reader.read(&mut chunk).map_err(io_error)?;
+            input.extend_from_slice(&chunk[..n]);
+            *eof = n == 0;
+            Ok(())
+        };
+        let header_len = loop {
+            match gzip_header_len(&input)? {
+                Some(len) => break len,
+                None if eof => return Err(unexpected_end(10)),
+                None => fill(&mut input, &mut eof)?,
+            }
+        };
+        let mut points = vec![AccessPoint {
+            compressed_offset: header_len as u64,
+            uncompressed_offset: 0,
+            bits: 0,
+            window: None,
+        }];
+        // Circular output buffer, at least twice the window size
+        let mut out = vec![0; 2 * WINDOW_SIZE];
+        let (mut out_pos, mut total_out) = (0, 0u64);
+        // Compressed offset of `input[0]` and position of the next input byte
+        let (mut input_offset, mut pos) = (0u64, header_len);
+        let mut decompressor = DecompressorOxide::new();
+        loop {
+            let mut flags = TINFL_FLAG_STOP_ON_BLOCK_BOUNDARY;
+            if !eof {
+                flags |= TINFL_FLAG_HAS_MORE_INPUT;
+            }
+            let (status, consumed, written) =
+                decompress(&mut decompressor, &input[pos..], &mut out, out_pos, flags);
+            pos += consumed;
+            out_pos = (out_pos + written) % out.len();
+            total_out += written as u64;
+            match status {
+                TINFLStatus::Done => break,
+                TINFLStatus::HasMoreOutput => {}
+                TINFLStatus::BlockBoundary => {
+                    let last = points.last().map_or(0, |point| point.uncompressed_offset);
+                    if total_out > last && total_out - last >= u64::from(spacing) {
+                        let state = decompressor
+                            .block_boundary_state()
+                            .ok_or_else(|| inflate_error(status))?;
+                        points.push(AccessPoint {
+                            compressed_offset: input_offset + pos as u64,
+                            uncompressed_offset: total_out,
+                            bits: state.num_bits,
+                            window: Some(window(&out, out_pos, total_out)),
+                        });
+                    }
+                }
+                TINFLStatus::NeedsMoreInput if !eof => {
+                    input.drain(..pos);
+                    input_offset += pos as u64;
+                    pos = 0;
+                    fill(&mut input, &mut eof)?;
+                }
+                status => return Err(inflate_error(status)),
+            }
+        }
+        // Skip the trailer with CRC32 and ISIZE
+        let trailer_end = pos + 8;
+        while input.len() < trailer_end && !eof {
+            fill(&mut input, &mut eof)?;
+        }
+        if input.len() < trailer_end {
+            return Err(unexpected_end(8));
+        }
+        let mut rest = Vec::new();
+        reader.read_to_end(&mut rest).map_err(io_error)?;
+        if input.len() > trailer_end || !rest.is_empty() {
+            return Err(HttpError::HttpError(
+                "gzip files with multiple members are not supported".to_string(),
+            ));
+        }
+        Ok(GzipIndex {
+            compressed_size: input_offset + trailer_end as u64,
+            uncompressed_size: total_out,
+            spacing,
+            points,
+        })
+    }
+
+    /// Read an index in the GZIDX format (version 1).
+    pub fn from_gzidx(data: &[u8]) -> Result<Self> {
+        let invalid = || HttpError::HttpError("invalid GZIDX index".to_string());
+        if data.len() < GZIDX_HEADER_LEN {
+            return Err(unexpected_end(GZIDX_HEADER_LEN));
+        }
+        if &data[..5] != GZIDX_MAGIC || data[5] != GZIDX_VERSION {
+            return Err(invalid());
+        }
+        let u32_at = |pos: usize| u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap());
+        let u64_at = |pos: usize| u64::from_le_bytes(data[pos..pos + 8].try_into().unwrap());
+        let window_size = u32_at(27) as usize;
+        if window_size != WINDOW_SIZE {
+            return Err(invalid());
+        }
+        let count = u32_at(31) as usize;
+        let windows_offset = count
+            .checked_mul(GZIDX_POINT_LEN)
+            .and_then(|len| len.checked_add(GZIDX_HEADER_LEN))
+            .ok_or_else(invalid)?;
+        if data.len() < windows_offset {
+            return Err(unexpected_end(windows_offset));
+        }
+        let mut window_pos = windows_offset;
+        let mut points = Vec::with_capacity(count);
+        for i in 0..count {
+            let entry = GZIDX_HEADER_LEN + i * GZIDX_POINT_LEN;
+            let window = if data[entry + 17] != 0 {
+                let window = data
+                    .get(window_pos..window_pos + window_size)
+                    .ok_or_else(|| unexpected_end(window_pos + window_size))?;
+                window_pos += window_size;
+                Some(window.to_vec())
+            } else {
+                None
+            };
+            points.push(AccessPoint {
+                compressed_offset: u64_at(entry),
+                uncompressed_offset: u64_at(entry + 8),
+                bits: data[entry + 16],
+                window,
+            });
+        }
+        let index = GzipIndex {
+            compressed_size: u64_at(7),
+            uncompressed_size: u64_at(15),
+            spacing: u32_at(23),
+            points,
+        };
+        if !index.is_valid() {
+            return Err(invalid());
+        }
+        Ok(index)
+    }
+
+    /// Serialize the index in the GZIDX format (version 1).
+    pub fn to_gzidx(&self) -> Vec<u8> {
+        let mut data = GZIDX_MAGIC.to_vec();
+        data.extend([GZIDX_VERSION, 0]);
+        data.extend(self.compressed_size.to_le_bytes());
+        data.extend(self.uncompressed_size.to_le_bytes());
+        data.extend(self.spacing.to_le_bytes());
+        data.extend((WINDOW_SIZE as u32).to_le_bytes());
+        data.extend((self.points.len() as u32).to_le_bytes());
+        for point in &self.points {
+            data.extend(point.compressed_offset.to_le_bytes());
+            data.extend(point.uncompressed_offset.to_le_bytes());
+            data.extend([point.bits, point.window.is_some() as u8]);
+        }
+        for window in self.points.iter().filter_map(|point| point.window.as_ref()) {
+            data.extend(window);
+        }
+        data
+    }
+
+    pub fn points(&self) -> &[AccessPoint] {
+        &self.points
+    }
+
+    pub fn compressed_size(&self) -> u64 {
+        self.compressed_size
+    }
+
+    pub fn uncompressed_size(&self) -> u64 {
+        self.uncompressed_size
+    }
+
+    /// Check that access points are ordered and within the compressed and uncompressed size.
+    fn is_valid(&self) -> bool {
+        let in_bounds = self.points.iter().all(|point| {
+            point.bits <= 7
+                && (point.bits == 0 || point.compressed_offset > 0)
+                && point.compressed_offset <= self.compressed_size
+                && point.uncompressed_offset <= self.uncompressed_size
+        });
+        let ordered = self.points.windows(2).all(|pair| {
+            pair[0].compressed_offset <= pair[1].compressed_offset
+                && pair[0].uncompressed_offset <= pair[1].uncompressed_offset
+        });
+        !self.points.is_empty() && in_bounds && ordered
+    }
+
+    /// Compressed data required for decompressing from each access point
+    /// up to the next one
+    pub(crate) fn frames(&self) -> Vec<Frame> {
+        self.points
+            .iter()
+            .enumerate()
+            .map(|(i, point)| {
+                let begin = point.compressed_offset - u64::from(point.bits > 0);
+                let (end, uncompressed_end) = match self.points.get(i + 1) {
+                    // Include the byte with the first bits of the next block
+                    Some(next) => (
+                        (next.compressed_offset + 1).min(self.compressed_size),
+                        next.uncompressed_offset,
+                    ),
+                    None => (self.compressed_size, self.uncompressed_size),
+                };
+                Frame {
+                    compressed_offset: begin,
+                    compressed_len: end - begin,
+                    uncompressed_offset: point.uncompressed_offset,
+                    uncompressed_len: uncompressed_end - point.uncompressed_offset,
+                }
+            })
+            .collect()
+    }
+
+    /// Decompress `length` bytes from access point `index`.
+    pub(crate) fn inflate(&self, index: usize, data: &[u8], length: usize) -> Result<Vec<u8>> {
+        let point = &self.points[index];
+        let (bit_buf, data) = match data.split_first() {
+            Some((first, rest)) if point.bits > 0 => (first >> (8 - point.bits), rest),
+            _ => (0, data),
+        };
+        let mut decompressor = DecompressorOxide::from_block_boundary_state(&BlockBoundaryState {
+            num_bits: point.bits,
+            bit_buf,
+            ..Default::default()
+        });
+        let window = point.window.as_deref().unwrap_or_default();
+        let mut out = window.to_vec();
+        out.resize(window.len() + length, 0);
+        let (status, _, written) = decompress(
+            &mut decompressor,
+            data,
+            &mut out,
+            window.len(),
+            TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF | TINFL_FLAG_HAS_MORE_INPUT,
+        );
+        if (status as i32) < 0 {
+            return Err(inflate_error(status));
+        }
+        out.truncate(window.len() + written);
+        out.drain(..window.len());
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_client::MockHttpRangeClient;
+    use crate::{
+        AsyncBufferedHttpRangeClient, AsyncCompressedReader, SyncBufferedHttpRangeClient,
+        SyncCompressedReader,
+    };
+    use flate2::write::GzEncoder;
+    use flate2::{Compression, GzBuilder};
+    use std::io::{Seek, SeekFrom, Write};
+
+    fn test_data() -> Vec<u8> {
+        let mut state = 1u32;
+        (0..100_000)
+            .flat_map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                format!("{} ", state >> 20).into_bytes()
+            })
+            .collect()
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn build_index() -> Result<()> {
+        let data = test_data();
+        let file = gzip(&data);
+        let index = GzipIndex::build(&file[..], 32 * 1024)?;
+        assert!(index.points().len() > 3);
+        assert!(index.points().iter().any(|point| point.bits > 0));
+        assert_eq!(index.uncompressed_size(), data.len() as u64);
+        assert_eq!(index.compressed_size(), file.len() as u64);
+        for (i, point) in index.points().iter().enumerate() {
+            let frame = &index.frames()[i];
+            let compressed =
+                &file[frame.compressed_offset as usize..][..frame.compressed_len as usize];
+            let decoded = index.inflate(i, compressed, frame.uncompressed_len as usize)?;
+            let begin = point.uncompressed_offset as usize;
+            assert_eq!(decoded, data[begin..begin + decoded.len()]);
+            assert_eq!(decoded.len() as u64, frame.uncompressed_len);
+        }
+
+        let restored = GzipIndex::from_gzidx(&index.to_gzidx())?;
+        assert_eq!(restored.points(), index.points());
+        let mut gzidx = index.to_gzidx();
+        gzidx[27..31].copy_from_slice(&(16 * 1024u32).to_le_bytes());
+        assert!(GzipIndex::from_gzidx(&gzidx).is_err());
+        let mut invalid = index.clone();
+        invalid.points.swap(1, 2);
+        assert!(GzipIndex::from_gzidx(&invalid.to_gzidx()).is_err());
+        let mut invalid = index.clone();
+        invalid.points[0].compressed_offset = 0;
+        invalid.points[0].bits = 3;
+        assert!(GzipIndex::from_gzidx(&invalid.to_gzidx()).is_err());
+        let mut invalid = index.clone();
+        invalid.points[1].uncompressed_offset = data.len() as u64 + 1;
+        assert!(GzipIndex::from_gzidx(&invalid.to_gzidx()).is_err());
+
+        // File name in header
+        let mut encoder = GzBuilder::new()
+            .filename("data.txt")
+            .write(Vec::new(), Compression::fast());
+        encoder.write_all(&data[..1000]).unwrap();
+        let index = GzipIndex::build(&encoder.finish().unwrap()[..], 100_000)?;
+        assert_eq!(index.points()[0].compressed_offset, 10 + 9);
+        Ok(())
+    }
+
+    #[test]
+    fn sync_indexed_gzip() -> std::io::Result<()> {
+        let data = test_data();
+        let file = gzip(&data);
+        let index = GzipIndex::build(&file[..], 64 * 1024).unwrap();
+        let backend = MockHttpRangeClient::new(file);
+        let client = SyncBufferedHttpRangeClient::with(backend, "mock");
+        let mut reader = SyncCompressedReader::indexed_gzip(client, index);
+        let mut buf = vec![0; 3000];
+        for pos in [400_000, 12, 250_000] {
+            reader.seek(SeekFrom::Start(pos))?;
+            reader.read_exact(&mut buf)?;
+            assert_eq!(buf, data[pos as usize..pos as usize + 3000]);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_indexed_gzip() -> Result<()> {
+        let data = test_data();
+        let file = gzip(&data);
+        let index = GzipIndex::build(&file[..], 64 * 1024)?;
+        let backend = MockHttpRangeClient::new(file);
+        let client = AsyncBufferedHttpRangeClient::with(backend, "mock");
+        let mut reader = AsyncCompressedReader::indexed_gzip(client, index);
+        let len = data.len() as u64;
+        assert_eq!(
+            reader.get_range(len - 100, 200).await?,
+            data[data.len() - 100..]
+        );
+        Ok(())
+    }
+}
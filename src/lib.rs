@@ -40,22 +40,93 @@
 //! # }
 //! ```
 
+#[cfg(feature = "bao")]
+mod bao;
+#[cfg(feature = "gzip")]
+mod bgzf;
 mod buffered_range_client;
+mod builder;
+#[cfg(feature = "checksum")]
+mod checksum;
+#[cfg(feature = "cog")]
+mod cog;
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+mod compressed;
+mod credentials;
+mod download;
 mod error;
+#[cfg(feature = "gzip")]
+mod gzip_index;
+pub mod middleware;
+#[cfg(test)]
+mod mock_client;
+#[cfg(feature = "parquet")]
+mod parquet;
+#[cfg(feature = "pmtiles")]
+mod pmtiles;
 mod range_client;
+#[cfg(not(target_arch = "wasm32"))]
+mod rate_limit;
+mod records;
 #[cfg(any(feature = "reqwest-async", feature = "reqwest-sync"))]
 mod reqwest_client;
+#[cfg(feature = "reqwest-middleware")]
+mod reqwest_middleware_client;
+mod retry;
+mod shared_range_client;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "tar")]
+mod tar;
+#[cfg(all(feature = "tower", not(target_arch = "wasm32")))]
+mod tower_client;
 #[cfg(feature = "ureq-sync")]
 mod ureq_client;
+#[cfg(feature = "zip")]
+mod zip;
+#[cfg(feature = "zstd")]
+mod zstd_seekable;
 
+#[cfg(feature = "parquet")]
+pub use crate::parquet::{ParquetChunkRead, SyncParquetReader};
+#[cfg(feature = "pmtiles")]
+pub use crate::pmtiles::{AsyncPmtilesReader, PmtilesCompression, PmtilesHeader, PmtilesTileType};
+#[cfg(feature = "bao")]
+pub use bao::{AsyncVerifiedHttpRangeClient, SyncVerifiedHttpRangeClient};
 pub use buffered_range_client::nonblocking::AsyncBufferedHttpRangeClient;
 pub use buffered_range_client::sync::SyncBufferedHttpRangeClient;
+pub use builder::HttpRangeClientBuilder;
+#[cfg(feature = "checksum")]
+pub use checksum::{BlockHashes, Checksum, ChecksumAlgorithm};
+#[cfg(feature = "cog")]
+pub use cog::{AsyncCogReader, CogLevel, SyncCogReader};
+#[cfg(any(feature = "gzip", feature = "zstd"))]
+pub use compressed::{AsyncCompressedReader, Frame, SyncCompressedReader};
+pub use credentials::*;
+pub use download::{DownloadOptions, Progress};
 pub use error::*;
+#[cfg(feature = "gzip")]
+pub use gzip_index::{AccessPoint, GzipIndex};
+pub use middleware::{Middleware, MiddlewareClient};
 pub use range_client::*;
+#[cfg(not(target_arch = "wasm32"))]
+pub use rate_limit::{RateLimitedClient, RateLimiter};
+pub use records::{LengthPrefix, Records};
+pub use retry::RetryPolicy;
+pub use shared_range_client::nonblocking::AsyncSharedHttpRangeClient;
+pub use shared_range_client::sync::SyncSharedHttpRangeClient;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteVfs;
+#[cfg(feature = "tar")]
+pub use tar::{SyncTarArchive, TarEntry, TarEntryReader, TarIndex};
 
 #[cfg(feature = "reqwest-async")]
-pub use crate::reqwest_client::nonblocking::BufferedHttpRangeClient;
+pub use crate::reqwest_client::nonblocking::{BufferedHttpRangeClient, SharedHttpRangeClient};
 #[cfg(feature = "reqwest-sync")]
-pub use crate::reqwest_client::sync::HttpReader;
+pub use crate::reqwest_client::sync::{HttpReader, SharedHttpReader};
+#[cfg(all(feature = "tower", not(target_arch = "wasm32")))]
+pub use crate::tower_client::TowerHttpRangeClient;
 #[cfg(feature = "ureq-sync")]
-pub use crate::ureq_client::sync::UreqHttpReader;
+pub use crate::ureq_client::sync::{SharedUreqHttpReader, UreqHttpReader};
+#[cfg(feature = "zip")]
+pub use crate::zip::{AsyncZipArchive, SyncZipArchive, ZipEntry, ZipEntryReader};
@@ -0,0 +1,429 @@
+//! Composable request middleware for HTTP Range clients.
+use crate::error::{HttpError, Result};
+use crate::range_client::{AsyncHttpRangeClient, RangeResponse, SyncHttpRangeClient};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::sync::Arc;
+
+/// HTTP request method
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+}
+
+/// Request passed through the middleware chain
+#[derive(Clone, Debug)]
+pub struct Request {
+    pub method: Method,
+    pub url: String,
+    /// `Range` header value of GET requests
+    pub range: Option<String>,
+    /// Additional request headers
+    pub headers: Vec<(String, String)>,
+}
+
+impl Request {
+    /// `Range` header value, falling back to `range` if a middleware removed it
+    fn range<'a>(&'a self, range: &'a str) -> &'a str {
+        self.range.as_deref().unwrap_or(range)
+    }
+
+    /// Report `url` instead of the URL modified by middlewares if the request
+    /// wasn't redirected, so that readers pinning redirects keep the original URL.
+    fn original_url(&self, response: RangeResponse, url: &str) -> RangeResponse {
+        if response.url.as_deref() == Some(self.url.as_str()) {
+            response.with_url(Some(url.to_string()))
+        } else {
+            response
+        }
+    }
+}
+
+/// Response passed to [Middleware::after_response]
+#[derive(Debug)]
+pub enum Response<'a> {
+    /// Response of a GET range request
+    Range(&'a RangeResponse),
+    /// Requested header value of a HEAD request
+    Header(Option<&'a str>),
+    /// Requested header values of a HEAD request
+    Headers(&'a [Option<String>]),
+}
+
+/// Hooks called for every request of a [MiddlewareClient]
+pub trait Middleware: Send + Sync {
+    /// Called before sending a request. URL and headers may be modified.
+    /// Returning an error aborts the request.
+    fn before_request(&self, _request: &mut Request) -> Result<()> {
+        Ok(())
+    }
+    /// Called after a successful response.
+    fn after_response(&self, _request: &Request, _response: &Response) {}
+    /// Called after a failed request.
+    fn on_error(&self, _request: &Request, _error: &HttpError) {}
+}
+
+impl<M: Middleware + ?Sized> Middleware for Arc<M> {
+    fn before_request(&self, request: &mut Request) -> Result<()> {
+        (**self).before_request(request)
+    }
+    fn after_response(&self, request: &Request, response: &Response) {
+        (**self).after_response(request, response)
+    }
+    fn on_error(&self, request: &Request, error: &HttpError) {
+        (**self).on_error(request, error)
+    }
+}
+
+/// HTTP client wrapper calling a chain of [Middleware] hooks
+///
+/// `before_request` hooks are called in the order the middlewares were added,
+/// `after_response` and `on_error` hooks in reverse order.
+pub struct MiddlewareClient<T> {
+    inner: T,
+    middlewares: Vec<Arc<dyn Middleware>>,
+}
+
+impl<T> MiddlewareClient<T> {
+    pub fn new(inner: T) -> Self {
+        MiddlewareClient {
+            inner,
+            middlewares: Vec::new(),
+        }
+    }
+
+    /// Add a middleware to the chain.
+    pub fn with_middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middlewares.push(Arc::new(middleware));
+        self
+    }
+
+    /// Wrapped HTTP client
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    fn before_request(
+        &self,
+        method: Method,
+        url: &str,
+        range: Option<&str>,
+        headers: &[(String, String)],
+    ) -> Result<Request> {
+        let mut request = Request {
+            method,
+            url: url.to_string(),
+            range: range.map(str::to_string),
+            headers: headers.to_vec(),
+        };
+        for middleware in &self.middlewares {
+            middleware.before_request(&mut request)?;
+        }
+        Ok(request)
+    }
+
+    fn after_request(&self, request: &Request, result: std::result::Result<Response, &HttpError>) {
+        for middleware in self.middlewares.iter().rev() {
+            match &result {
+                Ok(response) => middleware.after_response(request, response),
+                Err(e) => middleware.on_error(request, e),
+            }
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait]
+impl<T: AsyncHttpRangeClient> AsyncHttpRangeClient for MiddlewareClient<T> {
+    async fn get_range(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<Bytes> {
+        self.get_range_response(url, range, headers)
+            .await
+            .map(|response| response.data)
+    }
+    async fn get_range_response(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<RangeResponse> {
+        let request = self.before_request(Method::Get, url, Some(range), headers)?;
+        let result = self
+            .inner
+            .get_range_response(&request.url, request.range(range), &request.headers)
+            .await;
+        self.after_request(&request, result.as_ref().map(Response::Range));
+        result.map(|response| request.original_url(response, url))
+    }
+    async fn head_response_header(
+        &self,
+        url: &str,
+        header: &str,
+        headers: &[(String, String)],
+    ) -> Result<Option<String>> {
+        let request = self.before_request(Method::Head, url, None, headers)?;
+        let result = self
+            .inner
+            .head_response_header(&request.url, header, &request.headers)
+            .await;
+        self.after_request(
+            &request,
+            result
+                .as_ref()
+                .map(|value| Response::Header(value.as_deref())),
+        );
+        result
+    }
+    async fn head_response_headers(
+        &self,
+        url: &str,
+        names: &[&str],
+        headers: &[(String, String)],
+    ) -> Result<Vec<Option<String>>> {
+        let request = self.before_request(Method::Head, url, None, headers)?;
+        let result = self
+            .inner
+            .head_response_headers(&request.url, names, &request.headers)
+            .await;
+        self.after_request(
+            &request,
+            result.as_ref().map(|values| Response::Headers(values)),
+        );
+        result
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+#[async_trait(?Send)]
+impl<T: AsyncHttpRangeClient> AsyncHttpRangeClient for MiddlewareClient<T> {
+    async fn get_range(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<Bytes> {
+        self.get_range_response(url, range, headers)
+            .await
+            .map(|response| response.data)
+    }
+    async fn get_range_response(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<RangeResponse> {
+        let request = self.before_request(Method::Get, url, Some(range), headers)?;
+        let result = self
+            .inner
+            .get_range_response(&request.url, request.range(range), &request.headers)
+            .await;
+        self.after_request(&request, result.as_ref().map(Response::Range));
+        result.map(|response| request.original_url(response, url))
+    }
+    async fn head_response_header(
+        &self,
+        url: &str,
+        header: &str,
+        headers: &[(String, String)],
+    ) -> Result<Option<String>> {
+        let request = self.before_request(Method::Head, url, None, headers)?;
+        let result = self
+            .inner
+            .head_response_header(&request.url, header, &request.headers)
+            .await;
+        self.after_request(
+            &request,
+            result
+                .as_ref()
+                .map(|value| Response::Header(value.as_deref())),
+        );
+        result
+    }
+    async fn head_response_headers(
+        &self,
+        url: &str,
+        names: &[&str],
+        headers: &[(String, String)],
+    ) -> Result<Vec<Option<String>>> {
+        let request = self.before_request(Method::Head, url, None, headers)?;
+        let result = self
+            .inner
+            .head_response_headers(&request.url, names, &request.headers)
+            .await;
+        self.after_request(
+            &request,
+            result.as_ref().map(|values| Response::Headers(values)),
+        );
+        result
+    }
+}
+
+impl<T: SyncHttpRangeClient> SyncHttpRangeClient for MiddlewareClient<T> {
+    fn get_range(&self, url: &str, range: &str, headers: &[(String, String)]) -> Result<Bytes> {
+        self.get_range_response(url, range, headers)
+            .map(|response| response.data)
+    }
+    fn get_range_response(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<RangeResponse> {
+        let request = self.before_request(Method::Get, url, Some(range), headers)?;
+        let result =
+            self.inner
+                .get_range_response(&request.url, request.range(range), &request.headers);
+        self.after_request(&request, result.as_ref().map(Response::Range));
+        result.map(|response| request.original_url(response, url))
+    }
+    fn head_response_header(
+        &self,
+        url: &str,
+        header: &str,
+        headers: &[(String, String)],
+    ) -> Result<Option<String>> {
+        let request = self.before_request(Method::Head, url, None, headers)?;
+        let result = self
+            .inner
+            .head_response_header(&request.url, header, &request.headers);
+        self.after_request(
+            &request,
+            result
+                .as_ref()
+                .map(|value| Response::Header(value.as_deref())),
+        );
+        result
+    }
+    fn head_response_headers(
+        &self,
+        url: &str,
+        names: &[&str],
+        headers: &[(String, String)],
+    ) -> Result<Vec<Option<String>>> {
+        let request = self.before_request(Method::Head, url, None, headers)?;
+        let result = self
+            .inner
+            .head_response_headers(&request.url, names, &request.headers);
+        self.after_request(
+            &request,
+            result.as_ref().map(|values| Response::Headers(values)),
+        );
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_client::MockHttpRangeClient;
+    use crate::{AsyncBufferedHttpRangeClient, SyncBufferedHttpRangeClient};
+    use std::sync::Mutex;
+
+    /// Records requests and responses
+    #[derive(Default)]
+    struct Recorder {
+        log: Mutex<Vec<String>>,
+    }
+
+    impl Middleware for Recorder {
+        fn after_response(&self, request: &Request, response: &Response) {
+            let entry = match response {
+                Response::Range(r) => format!("{} {} bytes", request.url, r.data.len()),
+                Response::Header(value) => format!("{} HEAD {value:?}", request.url),
+                Response::Headers(values) => format!("{} HEAD {values:?}", request.url),
+            };
+            self.log.lock().unwrap().push(entry);
+        }
+        fn on_error(&self, request: &Request, error: &HttpError) {
+            let entry = format!("{} {:?} {error}", request.url, request.range);
+            self.log.lock().unwrap().push(entry);
+        }
+    }
+
+    struct ApiKey;
+
+    impl Middleware for ApiKey {
+        fn before_request(&self, request: &mut Request) -> Result<()> {
+            request.url.push_str("?key=secret");
+            request
+                .headers
+                .push(("X-Client".to_string(), "test".to_string()));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn sync_middleware_chain() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000);
+        let recorder = Arc::new(Recorder::default());
+        let http_client = MiddlewareClient::new(backend.clone())
+            .with_middleware(ApiKey)
+            .with_middleware(recorder.clone());
+        let mut client = SyncBufferedHttpRangeClient::with(http_client, "mock");
+        client.min_req_size(16).get_range(0, 4)?;
+        assert!(client.get_range(2000, 4).is_err());
+        client.get_content_length()?;
+        assert_eq!(
+            *recorder.log.lock().unwrap(),
+            [
+                "mock?key=secret 16 bytes",
+                "mock?key=secret Some(\"bytes=2000-2015\") http status 416",
+                "mock?key=secret HEAD Some(\"1000\")"
+            ]
+        );
+        assert_eq!(backend.request_urls()[0], "mock?key=secret");
+        assert_eq!(
+            backend.request_headers()[0],
+            [("X-Client".to_string(), "test".to_string())]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn sync_modified_request() -> Result<()> {
+        struct Align;
+        impl Middleware for Align {
+            fn before_request(&self, request: &mut Request) -> Result<()> {
+                request.range = Some("bytes=0-31".to_string());
+                Ok(())
+            }
+        }
+        let backend = MockHttpRangeClient::with_len(1000);
+        let http_client = MiddlewareClient::new(backend.clone())
+            .with_middleware(ApiKey)
+            .with_middleware(Align);
+        let mut client = SyncBufferedHttpRangeClient::with(http_client, "mock");
+        client.pin_redirects(true).min_req_size(16);
+        assert_eq!(client.get_range(0, 4)?, [0, 1, 2, 3]);
+        client.get_range(500, 4)?;
+        assert_eq!(client.request_url(), "mock");
+        assert_eq!(backend.requests(), ["GET bytes=0-31", "GET bytes=0-31"]);
+        assert_eq!(
+            backend.request_urls(),
+            ["mock?key=secret", "mock?key=secret"]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_abort_request() -> Result<()> {
+        struct Deny;
+        impl Middleware for Deny {
+            fn before_request(&self, _request: &mut Request) -> Result<()> {
+                Err(HttpError::HttpError("denied".to_string()))
+            }
+        }
+        let backend = MockHttpRangeClient::with_len(1000);
+        let http_client = MiddlewareClient::new(backend.clone()).with_middleware(Deny);
+        let mut client = AsyncBufferedHttpRangeClient::with(http_client, "mock");
+        let result = client.get_range(0, 4).await;
+        assert_eq!(result.unwrap_err().to_string(), "http error `denied`");
+        assert!(backend.requests().is_empty());
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+//! In-memory HTTP Range backend for tests without network access.
+use crate::error::{HttpError, Result};
+use crate::range_client::{AsyncHttpRangeClient, RangeResponse, SyncHttpRangeClient};
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::sync::{Arc, Mutex};
+
+type Headers = Vec<(String, String)>;
+
+/// Serves Range requests from a byte buffer and records every request.
+#[derive(Clone)]
+pub(crate) struct MockHttpRangeClient {
+    data: Bytes,
+    requests: Arc<Mutex<Vec<String>>>,
+    urls: Arc<Mutex<Vec<String>>>,
+    headers: Arc<Mutex<Vec<Headers>>>,
+    /// Final URL for redirected requests
+    redirect: Arc<Mutex<Option<String>>>,
+    /// URLs responding with 403
+    expired: Arc<Mutex<Vec<String>>>,
+    /// Header required for authorization, responding with 401 otherwise
+    required_header: Arc<Mutex<Option<(String, String)>>>,
+    /// Status codes of the next failing requests
+    failures: Arc<Mutex<Vec<u16>>>,
+    /// Headers returned by HEAD requests
+    response_headers: Arc<Mutex<Headers>>,
+    /// Respond to range requests with the complete data
+    ignore_ranges: Arc<Mutex<bool>>,
+}
+
+impl MockHttpRangeClient {
+    pub fn new(data: impl Into<Bytes>) -> Self {
+        MockHttpRangeClient {
+            data: data.into(),
+            requests: Arc::new(Mutex::new(Vec::new())),
+            urls: Arc::new(Mutex::new(Vec::new())),
+            headers: Arc::new(Mutex::new(Vec::new())),
+            redirect: Arc::new(Mutex::new(None)),
+            expired: Arc::new(Mutex::new(Vec::new())),
+            required_header: Arc::new(Mutex::new(None)),
+            failures: Arc::new(Mutex::new(Vec::new())),
+            response_headers: Arc::new(Mutex::new(Vec::new())),
+            ignore_ranges: Arc::new(Mutex::new(false)),
+        }
+    }
+
+    /// Redirect requests for all URLs to `url`.
+    pub fn redirect_to(self, url: &str) -> Self {
+        *self.redirect.lock().unwrap() = Some(url.to_string());
+        self
+    }
+
+    /// Respond with status 403 to requests for `url`.
+    pub fn expire(&self, url: &str) {
+        self.expired.lock().unwrap().push(url.to_string());
+    }
+
+    /// Respond with status 401 to requests without the header `name: value`.
+    pub fn require_header(&self, name: &str, value: &str) {
+        *self.required_header.lock().unwrap() = Some((name.to_string(), value.to_string()));
+    }
+
+    /// Respond to the next requests with the given status codes.
+    pub fn fail_next(&self, statuses: &[u16]) {
+        self.failures.lock().unwrap().extend(statuses.iter().rev());
+    }
+
+    /// Set the `ETag` header returned by HEAD requests.
+    pub fn set_etag(&self, etag: &str) {
+        self.set_response_header("etag", etag);
+    }
+
+    /// Set a header returned by HEAD requests.
+    pub fn set_response_header(&self, name: &str, value: &str) {
+        crate::range_client::set_header(&mut self.response_headers.lock().unwrap(), name, value);
+    }
+
+    /// Respond to range requests with the complete data like a server without
+    /// range support. Requests with an `If-Range` header not matching the ETag
+    /// always get the complete data.
+    pub fn ignore_ranges(&self) {
+        *self.ignore_ranges.lock().unwrap() = true;
+    }
+
+    /// Requested URLs before redirection.
+    pub fn request_urls(&self) -> Vec<String> {
+        self.urls.lock().unwrap().clone()
+    }
+
+    /// Additional request headers of all requests.
+    pub fn request_headers(&self) -> Vec<Headers> {
+        self.headers.lock().unwrap().clone()
+    }
+
+    /// Final URL after redirection
+    fn resolve(&self, url: &str, headers: &[(String, String)]) -> Result<String> {
+        self.urls.lock().unwrap().push(url.to_string());
+        self.headers.lock().unwrap().push(headers.to_vec());
+        if let Some(status) = self.failures.lock().unwrap().pop() {
+            return Err(HttpError::HttpStatus(status));
+        }
+        if self.expired.lock().unwrap().iter().any(|e| e == url) {
+            return Err(HttpError::HttpStatus(403));
+        }
+        if let Some((name, value)) = &*self.required_header.lock().unwrap() {
+            if !headers.iter().any(|(n, v)| n == name && v == value) {
+                return Err(HttpError::HttpStatus(401));
+            }
+        }
+        let redirect = self.redirect.lock().unwrap().clone();
+        Ok(redirect.unwrap_or_else(|| url.to_string()))
+    }
+
+    /// Test data with byte value `i % 251` at offset `i`.
+    pub fn with_len(len: usize) -> Self {
+        Self::new((0..len).map(|i| (i % 251) as u8).collect::<Vec<_>>())
+    }
+
+    /// Requests received so far (`GET bytes=..` or `HEAD <headers>`).
+    pub fn requests(&self) -> Vec<String> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn response(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<RangeResponse> {
+        self.requests.lock().unwrap().push(format!("GET {range}"));
+        let url = self.resolve(url, headers)?;
+        let etag = self.header_value("etag");
+        let if_range = headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case("if-range"));
+        let changed = if_range.is_some_and(|(_, value)| Some(value) != etag.as_ref());
+        if *self.ignore_ranges.lock().unwrap() || changed {
+            return Ok(RangeResponse::new(self.data.clone()).with_url(Some(url)));
+        }
+        let len = self.data.len();
+        let spec = range
+            .strip_prefix("bytes=")
+            .ok_or_else(|| HttpError::HttpError(format!("invalid range `{range}`")))?;
+        let (begin, end) = spec
+            .split_once('-')
+            .ok_or_else(|| HttpError::HttpError(format!("invalid range `{range}`")))?;
+        let parse = |s: &str| {
+            s.parse::<usize>()
+                .map_err(|_| HttpError::HttpError(format!("invalid range `{range}`")))
+        };
+        let (begin, end) = if begin.is_empty() {
+            // Suffix range
+            let n = parse(end)?;
+            (len.saturating_sub(n), len)
+        } else if end.is_empty() {
+            (parse(begin)?, len)
+        } else {
+            (parse(begin)?, (parse(end)? + 1).min(len))
+        };
+        if begin >= len {
+            return Err(HttpError::HttpStatus(416));
+        }
+        let content_range = format!("bytes {begin}-{}/{len}", end - 1);
+        Ok(RangeResponse::new(self.data.slice(begin..end))
+            .with_content_range(Some(content_range))
+            .with_url(Some(url)))
+    }
+
+    fn headers(
+        &self,
+        url: &str,
+        names: &[&str],
+        headers: &[(String, String)],
+    ) -> Result<Vec<Option<String>>> {
+        self.requests
+            .lock()
+            .unwrap()
+            .push(format!("HEAD {}", names.join(", ")));
+        self.resolve(url, headers)?;
+        Ok(names
+            .iter()
+            .map(|name| {
+                if name.eq_ignore_ascii_case("content-length") {
+                    Some(self.data.len().to_string())
+                } else {
+                    self.header_value(name)
+                }
+            })
+            .collect())
+    }
+
+    fn header_value(&self, header: &str) -> Option<String> {
+        let response_headers = self.response_headers.lock().unwrap();
+        response_headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(header))
+            .map(|(_, value)| value.clone())
+    }
+}
+
+#[async_trait]
+impl AsyncHttpRangeClient for MockHttpRangeClient {
+    async fn get_range(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<Bytes> {
+        self.response(url, range, headers)
+            .map(|response| response.data)
+    }
+    async fn get_range_response(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<RangeResponse> {
+        self.response(url, range, headers)
+    }
+    async fn head_response_header(
+        &self,
+        url: &str,
+        header: &str,
+        headers: &[(String, String)],
+    ) -> Result<Option<String>> {
+        Ok(self.headers(url, &[header], headers)?.pop().flatten())
+    }
+    async fn head_response_headers(
+        &self,
+        url: &str,
+        names: &[&str],
+        headers: &[(String, String)],
+    ) -> Result<Vec<Option<String>>> {
+        self.headers(url, names, headers)
+    }
+}
+
+impl SyncHttpRangeClient for MockHttpRangeClient {
+    fn get_range(&self, url: &str, range: &str, headers: &[(String, String)]) -> Result<Bytes> {
+        self.response(url, range, headers)
+            .map(|response| response.data)
+    }
+    fn get_range_response(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<RangeResponse> {
+        self.response(url, range, headers)
+    }
+    fn head_response_header(
+        &self,
+        url: &str,
+        header: &str,
+        headers: &[(String, String)],
+    ) -> Result<Option<String>> {
+        Ok(self.headers(url, &[header], headers)?.pop().flatten())
+    }
+    fn head_response_headers(
+        &self,
+        url: &str,
+        names: &[&str],
+        headers: &[(String, String)],
+    ) -> Result<Vec<Option<String>>> {
+        self.headers(url, names, headers)
+    }
+}
@@ -0,0 +1,264 @@
+//! Parquet readers fetching the footer and column chunks with range requests.
+use crate::error::HttpError;
+use crate::range_client::SyncHttpRangeClient;
+use crate::SyncBufferedHttpRangeClient;
+use ::parquet::errors::{ParquetError, Result};
+use ::parquet::file::metadata::{ParquetMetaData, ParquetMetaDataReader};
+use ::parquet::file::reader::{ChunkReader, Length};
+use ::parquet::file::FOOTER_SIZE;
+use bytes::Bytes;
+use std::convert::TryInto;
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+
+/// Length of the suffix read for the footer, usually containing the whole metadata
+const FOOTER_PREFETCH: usize = 64 * 1024;
+/// Maximal gap between column chunks fetched with a single request
+const MAX_COALESCE_GAP: usize = 1024 * 1024;
+
+fn parquet_error(error: HttpError) -> ParquetError {
+    ParquetError::External(Box::new(error))
+}
+
+fn check_length(data: &[u8], begin: u64, length: usize) -> Result<()> {
+    if data.len() < length {
+        return Err(ParquetError::EOF(format!(
+            "expected {length} bytes at offset {begin}, got {}",
+            data.len()
+        )));
+    }
+    Ok(())
+}
+
+/// Metadata length from the footer at the end of `suffix`
+fn metadata_len(suffix: &[u8]) -> Result<usize> {
+    let footer: &[u8; FOOTER_SIZE] = suffix
+        .get(suffix.len().saturating_sub(FOOTER_SIZE)..)
+        .and_then(|footer| footer.try_into().ok())
+        .ok_or_else(|| ParquetError::EOF("file too small for a Parquet footer".to_string()))?;
+    let tail = ParquetMetaDataReader::decode_footer_tail(footer)?;
+    if tail.is_encrypted_footer() {
+        return Err(ParquetError::General(
+            "encrypted Parquet footers are not supported".to_string(),
+        ));
+    }
+    Ok(tail.metadata_length())
+}
+
+/// Decode metadata of `metadata_len` bytes preceding the footer at the end of `suffix`.
+fn decode_metadata(suffix: &[u8], metadata_len: usize) -> Result<ParquetMetaData> {
+    let end = suffix.len() - FOOTER_SIZE;
+    ParquetMetaDataReader::decode_metadata(&suffix[end - metadata_len..end])
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod nonblocking {
+    use super::*;
+    use crate::range_client::AsyncHttpRangeClient;
+    use crate::AsyncBufferedHttpRangeClient;
+    use ::parquet::arrow::async_reader::AsyncFileReader;
+    use futures_util::future::BoxFuture;
+    use std::ops::Range;
+
+    /// Column chunks are fetched with coalesced range requests.
+    impl<T: AsyncHttpRangeClient + Send> AsyncFileReader for AsyncBufferedHttpRangeClient<T> {
+        fn get_bytes(&mut self, range: Range<usize>) -> BoxFuture<'_, Result<Bytes>> {
+            Box::pin(async move {
+                let data = self
+                    .get_range(range.start, range.len())
+                    .await
+                    .map_err(parquet_error)?;
+                check_length(data, range.start as u64, range.len())?;
+                Ok(Bytes::copy_from_slice(data))
+            })
+        }
+
+        fn get_byte_ranges(
+            &mut self,
+            ranges: Vec<Range<usize>>,
+        ) -> BoxFuture<'_, Result<Vec<Bytes>>> {
+            Box::pin(async move {
+                let ranges: Vec<_> = ranges
+                    .iter()
+                    .map(|range| (range.start, range.len()))
+                    .collect();
+                let results = self
+                    .get_ranges_coalesced(&ranges, MAX_COALESCE_GAP)
+                    .await
+                    .map_err(parquet_error)?;
+                for (data, (begin, length)) in results.iter().zip(&ranges) {
+                    check_length(data, *begin as u64, *length)?;
+                }
+                Ok(results)
+            })
+        }
+
+        /// Read the footer with a suffix request of 64 KiB, followed by a second
+        /// request for larger metadata.
+        fn get_metadata(&mut self) -> BoxFuture<'_, Result<Arc<ParquetMetaData>>> {
+            Box::pin(async move {
+                let suffix = self
+                    .get_suffix(FOOTER_PREFETCH)
+                    .await
+                    .map_err(parquet_error)?;
+                let metadata_len = metadata_len(suffix)?;
+                let suffix = if metadata_len + FOOTER_SIZE > suffix.len() {
+                    self.get_suffix(metadata_len + FOOTER_SIZE)
+                        .await
+                        .map_err(parquet_error)?
+                } else {
+                    suffix
+                };
+                check_length(suffix, 0, metadata_len + FOOTER_SIZE)?;
+                Ok(Arc::new(decode_metadata(suffix, metadata_len)?))
+            })
+        }
+    }
+}
+
+/// Parquet [ChunkReader] over a [SyncBufferedHttpRangeClient]
+///
+/// The client is shared by all readers returned by [get_read](ChunkReader::get_read).
+pub struct SyncParquetReader<T: SyncHttpRangeClient> {
+    client: Arc<Mutex<SyncBufferedHttpRangeClient<T>>>,
+    length: u64,
+}
+
+impl<T: SyncHttpRangeClient> SyncParquetReader<T> {
+    /// Create a reader, prefetching the footer with a suffix request of 64 KiB.
+    pub fn new(mut client: SyncBufferedHttpRangeClient<T>) -> crate::Result<Self> {
+        client.get_suffix(FOOTER_PREFETCH)?;
+        let length = match client.content_length() {
+            Some(length) => length,
+            None => client
+                .get_content_length()?
+                .ok_or_else(|| HttpError::HttpError("content length unknown".to_string()))?,
+        };
+        Ok(SyncParquetReader {
+            client: Arc::new(Mutex::new(client)),
+            length,
+        })
+    }
+}
+
+impl<T: SyncHttpRangeClient> Length for SyncParquetReader<T> {
+    fn len(&self) -> u64 {
+        self.length
+    }
+}
+
+impl<T: SyncHttpRangeClient + Send> ChunkReader for SyncParquetReader<T> {
+    type T = ParquetChunkRead<T>;
+
+    fn get_read(&self, start: u64) -> Result<Self::T> {
+        Ok(ParquetChunkRead {
+            client: self.client.clone(),
+            pos: start,
+            length: self.length,
+        })
+    }
+
+    fn get_bytes(&self, start: u64, length: usize) -> Result<Bytes> {
+        let mut client = self.client.lock().unwrap();
+        let data = client
+            .get_range(start as usize, length)
+            .map_err(parquet_error)?;
+        check_length(data, start, length)?;
+        Ok(Bytes::copy_from_slice(data))
+    }
+}
+
+/// Sequential reader of a [SyncParquetReader]
+pub struct ParquetChunkRead<T: SyncHttpRangeClient> {
+    client: Arc<Mutex<SyncBufferedHttpRangeClient<T>>>,
+    pos: u64,
+    length: u64,
+}
+
+impl<T: SyncHttpRangeClient> Read for ParquetChunkRead<T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let length = (buf.len() as u64).min(self.length.saturating_sub(self.pos)) as usize;
+        if length == 0 {
+            return Ok(0);
+        }
+        let mut client = self.client.lock().unwrap();
+        let data = client
+            .get_range(self.pos as usize, length)
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        buf[..data.len()].copy_from_slice(data);
+        self.pos += data.len() as u64;
+        Ok(data.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_client::MockHttpRangeClient;
+    use crate::AsyncBufferedHttpRangeClient;
+    use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use ::parquet::arrow::async_reader::AsyncFileReader;
+    use ::parquet::arrow::{ArrowWriter, ParquetRecordBatchStreamBuilder, ProjectionMask};
+    use ::parquet::file::properties::WriterProperties;
+    use arrow_array::{ArrayRef, Int32Array, RecordBatch, StringArray};
+    use futures_util::TryStreamExt;
+
+    /// File with 4 row groups of 2500 rows
+    fn parquet_file() -> Vec<u8> {
+        let ids = Int32Array::from_iter_values(0..10_000);
+        let names = StringArray::from_iter_values((0..10_000).map(|i| format!("name {i}")));
+        let batch = RecordBatch::try_from_iter([
+            ("id", Arc::new(ids) as ArrayRef),
+            ("name", Arc::new(names) as ArrayRef),
+        ])
+        .unwrap();
+        let properties = WriterProperties::builder()
+            .set_max_row_group_size(2500)
+            .build();
+        let mut writer =
+            ArrowWriter::try_new(Vec::new(), batch.schema(), Some(properties)).unwrap();
+        writer.write(&batch).unwrap();
+        writer.into_inner().unwrap()
+    }
+
+    fn ids(batch: &RecordBatch) -> &Int32Array {
+        batch.column(0).as_any().downcast_ref().unwrap()
+    }
+
+    #[tokio::test]
+    async fn async_file_reader() -> Result<()> {
+        let backend = MockHttpRangeClient::new(parquet_file());
+        let client = AsyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        let builder = ParquetRecordBatchStreamBuilder::new(client).await?;
+        assert_eq!(builder.metadata().num_row_groups(), 4);
+        assert_eq!(backend.requests(), ["GET bytes=-65536"]);
+        let projection = ProjectionMask::leaves(builder.parquet_schema(), [0]);
+        let stream = builder
+            .with_row_groups(vec![2])
+            .with_projection(projection)
+            .build()?;
+        let batches: Vec<RecordBatch> = stream.try_collect().await?;
+        assert_eq!(batches[0].num_columns(), 1);
+        assert_eq!(ids(&batches[0]).value(0), 5000);
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2500);
+
+        let backend = MockHttpRangeClient::with_len(100);
+        let mut client = AsyncBufferedHttpRangeClient::with(backend, "mock");
+        assert!(client.get_byte_ranges(vec![0..10, 90..120]).await.is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn sync_chunk_reader() -> Result<()> {
+        let backend = MockHttpRangeClient::new(parquet_file());
+        let client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        let reader = SyncParquetReader::new(client).map_err(parquet_error)?;
+        let batches = ParquetRecordBatchReaderBuilder::try_new(reader)?
+            .with_row_groups(vec![3])
+            .build()?
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        assert_eq!(ids(&batches[0]).value(0), 7500);
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 2500);
+        Ok(())
+    }
+}
@@ -0,0 +1,614 @@
+//! Reader of PMTiles v3 tile archives.
+use crate::buffered_range_client::unexpected_end;
+use crate::error::{HttpError, Result};
+use crate::range_client::AsyncHttpRangeClient;
+use crate::AsyncBufferedHttpRangeClient;
+use bytes::Bytes;
+use std::collections::{HashMap, VecDeque};
+use std::convert::{TryFrom, TryInto};
+use std::io::Read;
+use std::sync::Arc;
+
+const HEADER_LEN: usize = 127;
+/// Length of the first request, containing the header and the root directory
+const FIRST_REQUEST_LEN: usize = 16 * 1024;
+/// Maximal number of leaf directory levels
+const MAX_DEPTH: usize = 3;
+/// Maximal decompressed length of directories and metadata
+const MAX_DECOMPRESSED_LEN: u64 = 64 * 1024 * 1024;
+
+fn invalid(reason: &str) -> HttpError {
+    HttpError::HttpError(format!("invalid PMTiles archive: {reason}"))
+}
+
+fn u64_le(data: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(data[pos..pos + 8].try_into().expect("8 byte slice"))
+}
+
+fn i32_le(data: &[u8], pos: usize) -> i32 {
+    i32::from_le_bytes(data[pos..pos + 4].try_into().expect("4 byte slice"))
+}
+
+/// Compression of directories, metadata or tiles
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PmtilesCompression {
+    Unknown,
+    None,
+    Gzip,
+    Brotli,
+    Zstd,
+}
+
+impl From<u8> for PmtilesCompression {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => PmtilesCompression::None,
+            2 => PmtilesCompression::Gzip,
+            3 => PmtilesCompression::Brotli,
+            4 => PmtilesCompression::Zstd,
+            _ => PmtilesCompression::Unknown,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PmtilesTileType {
+    Unknown,
+    Mvt,
+    Png,
+    Jpeg,
+    Webp,
+    Avif,
+}
+
+impl From<u8> for PmtilesTileType {
+    fn from(value: u8) -> Self {
+        match value {
+            1 => PmtilesTileType::Mvt,
+            2 => PmtilesTileType::Png,
+            3 => PmtilesTileType::Jpeg,
+            4 => PmtilesTileType::Webp,
+            5 => PmtilesTileType::Avif,
+            _ => PmtilesTileType::Unknown,
+        }
+    }
+}
+
+/// PMTiles v3 header
+#[derive(Clone, Debug, PartialEq)]
+pub struct PmtilesHeader {
+    pub root_directory_offset: u64,
+    pub root_directory_length: u64,
+    pub metadata_offset: u64,
+    pub metadata_length: u64,
+    pub leaf_directories_offset: u64,
+    pub leaf_directories_length: u64,
+    pub tile_data_offset: u64,
+    pub tile_data_length: u64,
+    pub addressed_tiles: u64,
+    pub tile_entries: u64,
+    pub tile_contents: u64,
+    pub clustered: bool,
+    /// Compression of directories and metadata
+    pub internal_compression: PmtilesCompression,
+    pub tile_compression: PmtilesCompression,
+    pub tile_type: PmtilesTileType,
+    pub min_zoom: u8,
+    pub max_zoom: u8,
+    pub min_longitude: f64,
+    pub min_latitude: f64,
+    pub max_longitude: f64,
+    pub max_latitude: f64,
+    pub center_zoom: u8,
+    pub center_longitude: f64,
+    pub center_latitude: f64,
+}
+
+impl PmtilesHeader {
+    fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < HEADER_LEN {
+            return Err(unexpected_end(HEADER_LEN));
+        }
+        if &data[..7] != b"PMTiles" {
+            return Err(invalid("no PMTiles header found"));
+        }
+        if data[7] != 3 {
+            return Err(HttpError::HttpError(format!(
+                "unsupported PMTiles version {}",
+                data[7]
+            )));
+        }
+        let degrees = |pos| f64::from(i32_le(data, pos)) / 10_000_000.0;
+        Ok(PmtilesHeader {
+            root_directory_offset: u64_le(data, 8),
+            root_directory_length: u64_le(data, 16),
+            metadata_offset: u64_le(data, 24),
+            metadata_length: u64_le(data, 32),
+            leaf_directories_offset: u64_le(data, 40),
+            leaf_directories_length: u64_le(data, 48),
+            tile_data_offset: u64_le(data, 56),
+            tile_data_length: u64_le(data, 64),
+            addressed_tiles: u64_le(data, 72),
+            tile_entries: u64_le(data, 80),
+            tile_contents: u64_le(data, 88),
+            clustered: data[96] == 1,
+            internal_compression: data[97].into(),
+            tile_compression: data[98].into(),
+            tile_type: data[99].into(),
+            min_zoom: data[100],
+            max_zoom: data[101],
+            min_longitude: degrees(102),
+            min_latitude: degrees(106),
+            max_longitude: degrees(110),
+            max_latitude: degrees(114),
+            center_zoom: data[118],
+            center_longitude: degrees(119),
+            center_latitude: degrees(123),
+        })
+    }
+}
+
+/// Tile ID on the Hilbert curve of all zoom levels
+fn tile_id(z: u8, x: u32, y: u32) -> Option<u64> {
+    if z > 31 || u64::from(x) >= 1 << z || u64::from(y) >= 1 << z {
+        return None;
+    }
+    // Number of tiles of all lower zoom levels
+    let acc = ((1u64 << (2 * u32::from(z))) - 1) / 3;
+    let n = 1u64 << z;
+    let (mut x, mut y) = (u64::from(x), u64::from(y));
+    let mut d = 0;
+    let mut s = n / 2;
+    while s > 0 {
+        let rx = u64::from(x & s > 0);
+        let ry = u64::from(y & s > 0);
+        d += s * s * ((3 * rx) ^ ry);
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    Some(acc + d)
+}
+
+/// Directory entry pointing to tile data or, with a run length of 0, to a leaf directory
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Entry {
+    tile_id: u64,
+    offset: u64,
+    length: u32,
+    run_length: u32,
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Result<u64> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        let byte = *data
+            .get(*pos)
+            .ok_or_else(|| invalid("truncated directory"))?;
+        *pos += 1;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("varint too long"))
+}
+
+/// Decode a decompressed directory with columns of tile IDs (delta encoded),
+/// run lengths, lengths and offsets (0 for data following the previous entry).
+fn parse_directory(data: &[u8]) -> Result<Vec<Entry>> {
+    let mut pos = 0;
+    let count = read_varint(data, &mut pos)? as usize;
+    if count > data.len() {
+        return Err(invalid("truncated directory"));
+    }
+    let mut entries = vec![Entry::default(); count];
+    let mut tile_id = 0u64;
+    for entry in entries.iter_mut() {
+        tile_id = tile_id
+            .checked_add(read_varint(data, &mut pos)?)
+            .ok_or_else(|| invalid("tile id overflow"))?;
+        entry.tile_id = tile_id;
+    }
+    for entry in entries.iter_mut() {
+        entry.run_length = read_varint(data, &mut pos)? as u32;
+    }
+    for entry in entries.iter_mut() {
+        entry.length = read_varint(data, &mut pos)? as u32;
+    }
+    for i in 0..count {
+        let offset = read_varint(data, &mut pos)?;
+        entries[i].offset = match (offset, i.checked_sub(1)) {
+            (0, Some(prev)) => entries[prev]
+                .offset
+                .checked_add(u64::from(entries[prev].length))
+                .ok_or_else(|| invalid("offset overflow"))?,
+            (0, None) => return Err(invalid("missing offset of first entry")),
+            (offset, _) => offset - 1,
+        };
+    }
+    Ok(entries)
+}
+
+/// Entry containing `tile_id`
+fn find_entry(entries: &[Entry], tile_id: u64) -> Option<&Entry> {
+    let index = entries.partition_point(|entry| entry.tile_id <= tile_id);
+    let entry = &entries[index.checked_sub(1)?];
+    (entry.run_length == 0 || tile_id - entry.tile_id < u64::from(entry.run_length))
+        .then_some(entry)
+}
+
+/// Decompress at most `max_len` bytes.
+fn decompress(data: &[u8], compression: PmtilesCompression, max_len: u64) -> Result<Vec<u8>> {
+    let decoded = match compression {
+        PmtilesCompression::None => data.to_vec(),
+        PmtilesCompression::Gzip => {
+            let mut decoded = Vec::new();
+            flate2::read::GzDecoder::new(data)
+                .take(max_len.saturating_add(1))
+                .read_to_end(&mut decoded)
+                .map_err(|e| HttpError::HttpError(format!("decompression failed: {e}")))?;
+            decoded
+        }
+        PmtilesCompression::Zstd => crate::zstd_seekable::decode_frame(data, max_len)?,
+        compression => {
+            return Err(HttpError::HttpError(format!(
+                "unsupported PMTiles compression {compression:?}"
+            )))
+        }
+    };
+    if decoded.len() as u64 > max_len {
+        return Err(invalid("decompressed data too large"));
+    }
+    Ok(decoded)
+}
+
+/// Reader of tiles from PMTiles v3 archives
+///
+/// Reads the header and root directory with a single request.
+/// Leaf directories are cached.
+pub struct AsyncPmtilesReader<T: AsyncHttpRangeClient> {
+    client: AsyncBufferedHttpRangeClient<T>,
+    header: PmtilesHeader,
+    root: Arc<Vec<Entry>>,
+    leaves: HashMap<u64, Arc<Vec<Entry>>>,
+    /// Insertion order of leaf directories for eviction
+    leaf_order: VecDeque<u64>,
+    max_cached_leaves: usize,
+}
+
+impl<T: AsyncHttpRangeClient> AsyncPmtilesReader<T> {
+    /// Open an archive by reading its header and root directory.
+    pub async fn open(mut client: AsyncBufferedHttpRangeClient<T>) -> Result<Self> {
+        let header = PmtilesHeader::parse(client.get_range(0, FIRST_REQUEST_LEN).await?)?;
+        let mut reader = AsyncPmtilesReader {
+            client,
+            root: Arc::default(),
+            leaves: HashMap::new(),
+            leaf_order: VecDeque::new(),
+            max_cached_leaves: 64,
+            header,
+        };
+        reader.root = Arc::new(
+            reader
+                .directory(
+                    reader.header.root_directory_offset,
+                    reader.header.root_directory_length,
+                )
+                .await?,
+        );
+        Ok(reader)
+    }
+
+    pub fn header(&self) -> &PmtilesHeader {
+        &self.header
+    }
+
+    /// Set maximal number of cached leaf directories (default 64, 0 disables caching).
+    pub fn set_max_cached_directories(&mut self, count: usize) {
+        self.max_cached_leaves = count;
+    }
+
+    /// Builder variant of [set_max_cached_directories](Self::set_max_cached_directories).
+    pub fn max_cached_directories(&mut self, count: usize) -> &mut Self {
+        self.set_max_cached_directories(count);
+        self
+    }
+
+    /// Decompressed JSON metadata
+    pub async fn metadata(&mut self) -> Result<String> {
+        let data = self
+            .get_range(self.header.metadata_offset, self.header.metadata_length)
+            .await?;
+        let json = decompress(
+            &data,
+            self.header.internal_compression,
+            MAX_DECOMPRESSED_LEN,
+        )?;
+        String::from_utf8(json).map_err(|_| invalid("metadata is not UTF-8"))
+    }
+
+    /// Data of tile `z/x/y`, compressed according to `tile_compression` of the header
+    ///
+    /// Returns `None` for tiles not contained in the archive.
+    pub async fn get_tile(&mut self, z: u8, x: u32, y: u32) -> Result<Option<Bytes>> {
+        let Some(tile_id) = tile_id(z, x, y) else {
+            return Ok(None);
+        };
+        let mut directory = self.root.clone();
+        for _ in 0..=MAX_DEPTH {
+            let Some(entry) = find_entry(&directory, tile_id).cloned() else {
+                return Ok(None);
+            };
+            if entry.run_length > 0 {
+                let offset = self
+                    .header
+                    .tile_data_offset
+                    .checked_add(entry.offset)
+                    .ok_or_else(|| invalid("tile offset out of range"))?;
+                return Ok(Some(self.get_range(offset, entry.length.into()).await?));
+            }
+            directory = self.leaf_directory(entry.offset, entry.length).await?;
+        }
+        Err(invalid("too many leaf directory levels"))
+    }
+
+    async fn get_range(&mut self, offset: u64, length: u64) -> Result<Bytes> {
+        let out_of_range = || invalid("offset out of range");
+        let offset = usize::try_from(offset).map_err(|_| out_of_range())?;
+        let length = usize::try_from(length).map_err(|_| out_of_range())?;
+        offset.checked_add(length).ok_or_else(out_of_range)?;
+        let data = self.client.get_range(offset, length).await?;
+        if data.len() < length {
+            return Err(unexpected_end(length));
+        }
+        Ok(Bytes::copy_from_slice(data))
+    }
+
+    async fn directory(&mut self, offset: u64, length: u64) -> Result<Vec<Entry>> {
+        let data = self.get_range(offset, length).await?;
+        let compression = self.header.internal_compression;
+        parse_directory(&decompress(&data, compression, MAX_DECOMPRESSED_LEN)?)
+    }
+
+    /// Leaf directory at `offset` within the leaf directory section
+    async fn leaf_directory(&mut self, offset: u64, length: u32) -> Result<Arc<Vec<Entry>>> {
+        if let Some(directory) = self.leaves.get(&offset) {
+            return Ok(directory.clone());
+        }
+        let offset_in_file = self
+            .header
+            .leaf_directories_offset
+            .checked_add(offset)
+            .ok_or_else(|| invalid("leaf directory offset out of range"))?;
+        let directory = Arc::new(self.directory(offset_in_file, length.into()).await?);
+        if self.max_cached_leaves > 0 {
+            if self.leaves.len() >= self.max_cached_leaves {
+                if let Some(oldest) = self.leaf_order.pop_front() {
+                    self.leaves.remove(&oldest);
+                }
+            }
+            self.leaves.insert(offset, directory.clone());
+            self.leaf_order.push_back(offset);
+        }
+        Ok(directory)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_client::MockHttpRangeClient;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+        while value >= 0x80 {
+            out.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        out.push(value as u8);
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn encode_directory(entries: &[Entry]) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_varint(&mut out, entries.len() as u64);
+        let mut last_id = 0;
+        for entry in entries {
+            write_varint(&mut out, entry.tile_id - last_id);
+            last_id = entry.tile_id;
+        }
+        for entry in entries {
+            write_varint(&mut out, entry.run_length.into());
+        }
+        for entry in entries {
+            write_varint(&mut out, entry.length.into());
+        }
+        for (i, entry) in entries.iter().enumerate() {
+            let contiguous =
+                i > 0 && entry.offset == entries[i - 1].offset + u64::from(entries[i - 1].length);
+            write_varint(&mut out, if contiguous { 0 } else { entry.offset + 1 });
+        }
+        gzip(&out)
+    }
+
+    /// Archive with tiles `z/x/y` for zoom levels 0 to 2 in the root directory and
+    /// a single run of identical tiles for zoom level 3 in a leaf directory.
+    /// Tile data starts with 20 KB of padding and is followed by the leaf directory.
+    fn archive() -> Vec<u8> {
+        let mut tiles: Vec<(u64, Vec<u8>)> = (0..=2u8)
+            .flat_map(|z| {
+                (0..1u32 << z).flat_map(move |x| {
+                    (0..1u32 << z).map(move |y| {
+                        (
+                            tile_id(z, x, y).unwrap(),
+                            format!("{z}/{x}/{y}").into_bytes(),
+                        )
+                    })
+                })
+            })
+            .collect();
+        tiles.sort();
+        let mut tile_data = vec![0; 20_000];
+        let mut root: Vec<Entry> = tiles
+            .iter()
+            .map(|(tile_id, content)| {
+                let entry = Entry {
+                    tile_id: *tile_id,
+                    offset: tile_data.len() as u64,
+                    length: content.len() as u32,
+                    run_length: 1,
+                };
+                tile_data.extend(content);
+                entry
+            })
+            .collect();
+        let ocean = Entry {
+            tile_id: tile_id(3, 0, 0).unwrap(),
+            offset: tile_data.len() as u64,
+            length: 5,
+            run_length: 64,
+        };
+        tile_data.extend(b"ocean");
+        let leaf = encode_directory(&[ocean]);
+        root.push(Entry {
+            tile_id: 21,
+            offset: 0,
+            length: leaf.len() as u32,
+            run_length: 0,
+        });
+        let root = encode_directory(&root);
+        let metadata = gzip(br#"{"name":"test"}"#);
+
+        let mut header = b"PMTiles\x03".to_vec();
+        let metadata_offset = HEADER_LEN + root.len();
+        let tile_data_offset = metadata_offset + metadata.len();
+        let leaf_offset = tile_data_offset + tile_data.len();
+        for (offset, length) in [
+            (HEADER_LEN, root.len()),
+            (metadata_offset, metadata.len()),
+            (leaf_offset, leaf.len()),
+            (tile_data_offset, tile_data.len()),
+        ] {
+            header.extend((offset as u64).to_le_bytes());
+            header.extend((length as u64).to_le_bytes());
+        }
+        header.extend(85u64.to_le_bytes());
+        header.extend(22u64.to_le_bytes());
+        header.extend(22u64.to_le_bytes());
+        // Clustered, gzip directories, uncompressed MVT tiles, zoom 0 to 3
+        header.extend([1, 2, 1, 1, 0, 3]);
+        for degrees in [-180, -85, 180, 85] {
+            header.extend((degrees * 10_000_000i32).to_le_bytes());
+        }
+        header.push(0);
+        header.extend([0; 8]);
+        assert_eq!(header.len(), HEADER_LEN);
+        [header, root, metadata, tile_data, leaf].concat()
+    }
+
+    #[test]
+    fn hilbert_tile_ids() {
+        assert_eq!(tile_id(0, 0, 0), Some(0));
+        assert_eq!(tile_id(1, 0, 0), Some(1));
+        assert_eq!(tile_id(1, 0, 1), Some(2));
+        assert_eq!(tile_id(1, 1, 1), Some(3));
+        assert_eq!(tile_id(1, 1, 0), Some(4));
+        assert_eq!(tile_id(2, 0, 0), Some(5));
+        assert_eq!(tile_id(3, 0, 0), Some(21));
+        assert_eq!(tile_id(1, 2, 0), None);
+    }
+
+    #[test]
+    fn malformed_directory() {
+        let mut data = Vec::new();
+        for value in [2, u64::MAX, 1] {
+            write_varint(&mut data, value);
+        }
+        assert!(parse_directory(&data).is_err());
+
+        let mut data = Vec::new();
+        for value in [2, 0, 1, 1, 1, 10, 10, u64::MAX, 0] {
+            write_varint(&mut data, value);
+        }
+        assert!(parse_directory(&data).is_err());
+    }
+
+    #[test]
+    fn decompression_limit() {
+        let data = gzip(&[0; 1000]);
+        assert_eq!(
+            decompress(&data, PmtilesCompression::Gzip, 1000)
+                .unwrap()
+                .len(),
+            1000
+        );
+        assert!(decompress(&data, PmtilesCompression::Gzip, 999).is_err());
+    }
+
+    #[tokio::test]
+    async fn malformed_offsets() -> Result<()> {
+        let mut data = archive();
+        data[40..48].copy_from_slice(&u64::MAX.to_le_bytes());
+        data[56..64].copy_from_slice(&u64::MAX.to_le_bytes());
+        let client = AsyncBufferedHttpRangeClient::with(MockHttpRangeClient::new(data), "mock");
+        let mut reader = AsyncPmtilesReader::open(client).await?;
+        let err = reader.get_tile(2, 3, 1).await.unwrap_err();
+        assert!(err.to_string().contains("tile offset out of range"));
+        let err = reader.get_tile(3, 5, 2).await.unwrap_err();
+        assert!(err.to_string().contains("offset out of range"));
+
+        let mut data = archive();
+        data[24..32].copy_from_slice(&u64::MAX.to_le_bytes());
+        let client = AsyncBufferedHttpRangeClient::with(MockHttpRangeClient::new(data), "mock");
+        let mut reader = AsyncPmtilesReader::open(client).await?;
+        let err = reader.metadata().await.unwrap_err();
+        assert!(err.to_string().contains("offset out of range"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn read_tiles() -> Result<()> {
+        let backend = MockHttpRangeClient::new(archive());
+        let mut client = AsyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        client.min_req_size(1);
+        let mut reader = AsyncPmtilesReader::open(client).await?;
+        let header = reader.header();
+        assert_eq!(header.internal_compression, PmtilesCompression::Gzip);
+        assert_eq!(header.tile_type, PmtilesTileType::Mvt);
+        assert_eq!((header.min_zoom, header.max_zoom), (0, 3));
+        assert_eq!(header.max_latitude, 85.0);
+        // Header and root directory
+        assert_eq!(backend.requests().len(), 1);
+
+        assert_eq!(reader.metadata().await?, r#"{"name":"test"}"#);
+        let tile = reader.get_tile(2, 3, 1).await?;
+        assert_eq!(tile.as_deref(), Some(&b"2/3/1"[..]));
+        let tile = reader.get_tile(3, 5, 2).await?;
+        assert_eq!(tile.as_deref(), Some(&b"ocean"[..]));
+        let tile = reader.get_tile(3, 7, 7).await?;
+        assert_eq!(tile.as_deref(), Some(&b"ocean"[..]));
+        // Cached leaf directory
+        let leaf_request = format!("GET bytes={}-", reader.header().leaf_directories_offset);
+        let leaf_requests = backend.requests();
+        let leaf_requests = leaf_requests
+            .iter()
+            .filter(|r| r.starts_with(&leaf_request));
+        assert_eq!(leaf_requests.count(), 1);
+        assert_eq!(reader.get_tile(4, 0, 0).await?, None);
+        assert_eq!(reader.get_tile(3, 8, 0).await?, None);
+        Ok(())
+    }
+}
@@ -1,32 +1,249 @@
 use crate::error::Result;
 use async_trait::async_trait;
+use base64::prelude::*;
 use bytes::Bytes;
 use std::str;
 
+/// Response of a GET range request
+#[non_exhaustive]
+#[derive(Debug, Clone, Default)]
+pub struct RangeResponse {
+    /// Response body
+    pub data: Bytes,
+    /// Value of `Content-Range` response header
+    pub content_range: Option<String>,
+    /// Final URL after following redirects
+    pub url: Option<String>,
+}
+
+impl RangeResponse {
+    pub fn new(data: Bytes) -> Self {
+        RangeResponse {
+            data,
+            content_range: None,
+            url: None,
+        }
+    }
+
+    /// Set `Content-Range` response header value.
+    pub fn with_content_range(mut self, content_range: Option<String>) -> Self {
+        self.content_range = content_range;
+        self
+    }
+
+    /// Set final URL after following redirects.
+    pub fn with_url(mut self, url: Option<String>) -> Self {
+        self.url = url;
+        self
+    }
+
+    /// Offset of the first byte, from `Content-Range: bytes 100-199/1234`
+    pub fn range_begin(&self) -> Option<u64> {
+        let (range, _) = self.parse_content_range()?;
+        let (begin, _) = range.split_once('-')?;
+        begin.parse().ok()
+    }
+
+    /// Total length of the resource, from `Content-Range: bytes 100-199/1234`
+    pub fn total_length(&self) -> Option<u64> {
+        let (_, total) = self.parse_content_range()?;
+        total.parse().ok()
+    }
+
+    fn parse_content_range(&self) -> Option<(&str, &str)> {
+        let content_range = self.content_range.as_deref()?.trim();
+        content_range.strip_prefix("bytes ")?.split_once('/')
+    }
+}
+
+/// Set a request header, replacing an existing header with the same name
+pub(crate) fn set_header(headers: &mut Vec<(String, String)>, name: &str, value: &str) {
+    headers.retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    headers.push((name.to_string(), value.to_string()));
+}
+
+/// `Authorization` header value for HTTP Basic authentication
+pub(crate) fn basic_auth(username: &str, password: Option<&str>) -> String {
+    let credentials = format!("{username}:{}", password.unwrap_or_default());
+    format!("Basic {}", BASE64_STANDARD.encode(credentials))
+}
+
+/// Value of a response header of http based clients
+#[cfg(any(feature = "reqwest-async", feature = "reqwest-sync", feature = "tower"))]
+pub(crate) fn header_value(headers: &http::HeaderMap, header: &str) -> Result<Option<String>> {
+    if let Some(val) = headers.get(header) {
+        let v = val
+            .to_str()
+            .map_err(|e| crate::error::HttpError::HttpError(e.to_string()))?;
+        Ok(Some(v.to_string()))
+    } else {
+        Ok(None)
+    }
+}
+
 #[cfg(not(target_arch = "wasm32"))]
 #[async_trait]
 /// Async HTTP client for Range requests
-pub trait AsyncHttpRangeClient {
-    /// Send a GET range request
-    async fn get_range(&self, url: &str, range: &str) -> Result<Bytes>;
-    /// Send a HEAD request and return response header value
-    async fn head_response_header(&self, url: &str, header: &str) -> Result<Option<String>>;
+///
+/// Implementations must be `Sync`, since the returned futures are `Send`.
+pub trait AsyncHttpRangeClient: Sync {
+    /// Send a GET range request with additional request headers
+    async fn get_range(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<Bytes>;
+    /// Send a GET range request and return body, `Content-Range` header and final URL
+    ///
+    /// The default implementation doesn't report `Content-Range` and final URL.
+    async fn get_range_response(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<RangeResponse> {
+        self.get_range(url, range, headers)
+            .await
+            .map(RangeResponse::new)
+    }
+    /// Send a HEAD request with additional request headers and return response header value
+    async fn head_response_header(
+        &self,
+        url: &str,
+        header: &str,
+        headers: &[(String, String)],
+    ) -> Result<Option<String>>;
+    /// Send a HEAD request and return the values of multiple response headers
+    ///
+    /// The default implementation sends a HEAD request for every header.
+    async fn head_response_headers(
+        &self,
+        url: &str,
+        names: &[&str],
+        headers: &[(String, String)],
+    ) -> Result<Vec<Option<String>>> {
+        let mut values = Vec::with_capacity(names.len());
+        for name in names {
+            values.push(self.head_response_header(url, name, headers).await?);
+        }
+        Ok(values)
+    }
 }
 
 #[cfg(target_arch = "wasm32")]
 #[async_trait(?Send)]
 /// Async HTTP client for Range requests
 pub trait AsyncHttpRangeClient {
-    /// Send a GET range request
-    async fn get_range(&self, url: &str, range: &str) -> Result<Bytes>;
-    /// Send a HEAD request and return response header value
-    async fn head_response_header(&self, url: &str, header: &str) -> Result<Option<String>>;
+    /// Send a GET range request with additional request headers
+    async fn get_range(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<Bytes>;
+    /// Send a GET range request and return body, `Content-Range` header and final URL
+    ///
+    /// The default implementation doesn't report `Content-Range` and final URL.
+    async fn get_range_response(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<RangeResponse> {
+        self.get_range(url, range, headers)
+            .await
+            .map(RangeResponse::new)
+    }
+    /// Send a HEAD request with additional request headers and return response header value
+    async fn head_response_header(
+        &self,
+        url: &str,
+        header: &str,
+        headers: &[(String, String)],
+    ) -> Result<Option<String>>;
+    /// Send a HEAD request and return the values of multiple response headers
+    ///
+    /// The default implementation sends a HEAD request for every header.
+    async fn head_response_headers(
+        &self,
+        url: &str,
+        names: &[&str],
+        headers: &[(String, String)],
+    ) -> Result<Vec<Option<String>>> {
+        let mut values = Vec::with_capacity(names.len());
+        for name in names {
+            values.push(self.head_response_header(url, name, headers).await?);
+        }
+        Ok(values)
+    }
 }
 
 /// Sync HTTP client for Range requests
 pub trait SyncHttpRangeClient {
-    /// Send a GET range request
-    fn get_range(&self, url: &str, range: &str) -> Result<Bytes>;
-    /// Send a HEAD request and return response header value
-    fn head_response_header(&self, url: &str, header: &str) -> Result<Option<String>>;
+    /// Send a GET range request with additional request headers
+    fn get_range(&self, url: &str, range: &str, headers: &[(String, String)]) -> Result<Bytes>;
+    /// Send a GET range request and return body, `Content-Range` header and final URL
+    ///
+    /// The default implementation doesn't report `Content-Range` and final URL.
+    fn get_range_response(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<RangeResponse> {
+        self.get_range(url, range, headers).map(RangeResponse::new)
+    }
+    /// Send a HEAD request with additional request headers and return response header value
+    fn head_response_header(
+        &self,
+        url: &str,
+        header: &str,
+        headers: &[(String, String)],
+    ) -> Result<Option<String>>;
+    /// Send a HEAD request and return the values of multiple response headers
+    ///
+    /// The default implementation sends a HEAD request for every header.
+    fn head_response_headers(
+        &self,
+        url: &str,
+        names: &[&str],
+        headers: &[(String, String)],
+    ) -> Result<Vec<Option<String>>> {
+        names
+            .iter()
+            .map(|name| self.head_response_header(url, name, headers))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RangeResponse;
+    use bytes::Bytes;
+
+    #[test]
+    fn basic_auth() {
+        assert_eq!(
+            super::basic_auth("Aladdin", Some("open sesame")),
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="
+        );
+    }
+
+    #[test]
+    fn content_range() {
+        let response = RangeResponse::new(Bytes::new())
+            .with_content_range(Some("bytes 100-199/1234".to_string()));
+        assert_eq!(response.range_begin(), Some(100));
+        assert_eq!(response.total_length(), Some(1234));
+
+        let response =
+            RangeResponse::new(Bytes::new()).with_content_range(Some("bytes */1234".to_string()));
+        assert_eq!(response.range_begin(), None);
+        assert_eq!(response.total_length(), Some(1234));
+
+        let response =
+            RangeResponse::new(Bytes::new()).with_content_range(Some("bytes 0-99/*".to_string()));
+        assert_eq!(response.total_length(), None);
+    }
 }
@@ -0,0 +1,324 @@
+//! Request rate limiting and bandwidth throttling shared across clients.
+use crate::error::Result;
+use crate::range_client::{AsyncHttpRangeClient, RangeResponse, SyncHttpRangeClient};
+use async_lock::Semaphore;
+use async_trait::async_trait;
+use bytes::Bytes;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Limits for requests per second, concurrent requests and bytes per second
+///
+/// Share a limiter between readers by wrapping their HTTP clients in a
+/// [RateLimitedClient] with the same `Arc<RateLimiter>`.
+/// The byte rate is enforced by delaying subsequent requests once a response
+/// has been received.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    request_interval: Option<Duration>,
+    bytes_per_sec: Option<u64>,
+    concurrency: Option<Semaphore>,
+    schedule: Mutex<Schedule>,
+}
+
+#[derive(Debug, Default)]
+struct Schedule {
+    /// Earliest start of the next request according to the request rate
+    next_request: Option<Instant>,
+    /// Earliest start of the next request according to the byte rate
+    next_bytes: Option<Instant>,
+}
+
+impl RateLimiter {
+    /// Limiter without any limits.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit the number of requests per second. Zero, negative or NaN rates and rates
+    /// with an interval exceeding [Duration::MAX] remove the limit.
+    pub fn with_requests_per_sec(mut self, requests: f64) -> Self {
+        self.request_interval = if requests > 0.0 {
+            Duration::try_from_secs_f64(1.0 / requests).ok()
+        } else {
+            None
+        };
+        self
+    }
+
+    /// Limit the number of requests in flight. Zero removes the limit.
+    pub fn with_max_concurrent(mut self, requests: usize) -> Self {
+        self.concurrency = (requests > 0).then(|| Semaphore::new(requests));
+        self
+    }
+
+    /// Limit the number of received bytes per second. Zero removes the limit.
+    pub fn with_bytes_per_sec(mut self, bytes: u64) -> Self {
+        self.bytes_per_sec = (bytes > 0).then_some(bytes);
+        self
+    }
+
+    /// Reserve a request slot and return the time to wait before sending.
+    fn reserve(&self) -> Duration {
+        let now = Instant::now();
+        let mut schedule = self.schedule.lock().unwrap();
+        let mut start = now;
+        if let Some(interval) = self.request_interval {
+            start = schedule.next_request.map_or(now, |next| next.max(now));
+            // Intervals beyond the range of `Instant` are not applied
+            schedule.next_request = Some(start.checked_add(interval).unwrap_or(start));
+        }
+        if let Some(next) = schedule.next_bytes {
+            start = start.max(next);
+        }
+        start - now
+    }
+
+    /// Account for `bytes` received.
+    fn consume(&self, bytes: usize) {
+        if let Some(bytes_per_sec) = self.bytes_per_sec {
+            let now = Instant::now();
+            let mut schedule = self.schedule.lock().unwrap();
+            let next = schedule.next_bytes.map_or(now, |next| next.max(now));
+            let duration = Duration::try_from_secs_f64(bytes as f64 / bytes_per_sec as f64)
+                .unwrap_or(Duration::MAX);
+            schedule.next_bytes = Some(next.checked_add(duration).unwrap_or(next));
+        }
+    }
+}
+
+/// HTTP client wrapper applying the limits of a shared [RateLimiter]
+pub struct RateLimitedClient<T> {
+    inner: T,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<T> RateLimitedClient<T> {
+    pub fn new(inner: T, limiter: Arc<RateLimiter>) -> Self {
+        RateLimitedClient { inner, limiter }
+    }
+
+    /// Wrapped HTTP client
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    /// Shared rate limiter
+    pub fn limiter(&self) -> &Arc<RateLimiter> {
+        &self.limiter
+    }
+}
+
+#[async_trait]
+impl<T: AsyncHttpRangeClient> AsyncHttpRangeClient for RateLimitedClient<T> {
+    async fn get_range(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<Bytes> {
+        self.get_range_response(url, range, headers)
+            .await
+            .map(|response| response.data)
+    }
+    async fn get_range_response(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<RangeResponse> {
+        let _permit = match &self.limiter.concurrency {
+            Some(semaphore) => Some(semaphore.acquire().await),
+            None => None,
+        };
+        futures_timer::Delay::new(self.limiter.reserve()).await;
+        let response = self.inner.get_range_response(url, range, headers).await?;
+        self.limiter.consume(response.data.len());
+        Ok(response)
+    }
+    async fn head_response_header(
+        &self,
+        url: &str,
+        header: &str,
+        headers: &[(String, String)],
+    ) -> Result<Option<String>> {
+        let _permit = match &self.limiter.concurrency {
+            Some(semaphore) => Some(semaphore.acquire().await),
+            None => None,
+        };
+        futures_timer::Delay::new(self.limiter.reserve()).await;
+        self.inner.head_response_header(url, header, headers).await
+    }
+    async fn head_response_headers(
+        &self,
+        url: &str,
+        names: &[&str],
+        headers: &[(String, String)],
+    ) -> Result<Vec<Option<String>>> {
+        let _permit = match &self.limiter.concurrency {
+            Some(semaphore) => Some(semaphore.acquire().await),
+            None => None,
+        };
+        futures_timer::Delay::new(self.limiter.reserve()).await;
+        self.inner.head_response_headers(url, names, headers).await
+    }
+}
+
+impl<T: SyncHttpRangeClient> SyncHttpRangeClient for RateLimitedClient<T> {
+    fn get_range(&self, url: &str, range: &str, headers: &[(String, String)]) -> Result<Bytes> {
+        self.get_range_response(url, range, headers)
+            .map(|response| response.data)
+    }
+    fn get_range_response(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<RangeResponse> {
+        let _permit = self
+            .limiter
+            .concurrency
+            .as_ref()
+            .map(Semaphore::acquire_blocking);
+        std::thread::sleep(self.limiter.reserve());
+        let response = self.inner.get_range_response(url, range, headers)?;
+        self.limiter.consume(response.data.len());
+        Ok(response)
+    }
+    fn head_response_header(
+        &self,
+        url: &str,
+        header: &str,
+        headers: &[(String, String)],
+    ) -> Result<Option<String>> {
+        let _permit = self
+            .limiter
+            .concurrency
+            .as_ref()
+            .map(Semaphore::acquire_blocking);
+        std::thread::sleep(self.limiter.reserve());
+        self.inner.head_response_header(url, header, headers)
+    }
+    fn head_response_headers(
+        &self,
+        url: &str,
+        names: &[&str],
+        headers: &[(String, String)],
+    ) -> Result<Vec<Option<String>>> {
+        let _permit = self
+            .limiter
+            .concurrency
+            .as_ref()
+            .map(Semaphore::acquire_blocking);
+        std::thread::sleep(self.limiter.reserve());
+        self.inner.head_response_headers(url, names, headers)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_client::MockHttpRangeClient;
+    use crate::{AsyncBufferedHttpRangeClient, SyncBufferedHttpRangeClient};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn request_and_byte_rate() -> Result<()> {
+        let limiter = Arc::new(RateLimiter::new().with_requests_per_sec(20.0));
+        let http_client = RateLimitedClient::new(MockHttpRangeClient::with_len(1000), limiter);
+        let mut client = SyncBufferedHttpRangeClient::with(http_client, "mock");
+        let start = Instant::now();
+        for i in 0..5 {
+            client.min_req_size(1).get_range(i * 100, 1)?;
+        }
+        // First request is sent immediately
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        let limiter = Arc::new(RateLimiter::new().with_bytes_per_sec(1000));
+        let http_client = RateLimitedClient::new(MockHttpRangeClient::with_len(1000), limiter);
+        let mut client = SyncBufferedHttpRangeClient::with(http_client, "mock");
+        let start = Instant::now();
+        client.min_req_size(1).get_range(0, 100)?;
+        assert!(start.elapsed() < Duration::from_millis(100));
+        client.get_range(500, 100)?;
+        assert!(start.elapsed() >= Duration::from_millis(100));
+        Ok(())
+    }
+
+    #[test]
+    fn zero_limits() -> Result<()> {
+        for requests in [0.0, -1.0, f64::NAN, f64::MIN_POSITIVE] {
+            assert!(RateLimiter::new()
+                .with_requests_per_sec(requests)
+                .request_interval
+                .is_none());
+        }
+        let limiter = RateLimiter::new()
+            .with_max_concurrent(0)
+            .with_bytes_per_sec(0);
+        let http_client =
+            RateLimitedClient::new(MockHttpRangeClient::with_len(1000), Arc::new(limiter));
+        let mut client = SyncBufferedHttpRangeClient::with(http_client, "mock");
+        client.min_req_size(1).get_range(0, 100)?;
+        client.get_range(500, 100)?;
+
+        // Intervals and delays beyond the range of `Instant`
+        let limiter = RateLimiter::new()
+            .with_requests_per_sec(1e-19)
+            .with_bytes_per_sec(1);
+        limiter.reserve();
+        limiter.reserve();
+        limiter.consume(usize::MAX);
+        limiter.consume(1);
+        Ok(())
+    }
+
+    /// Tracks the maximal number of requests in flight
+    #[derive(Default)]
+    struct Concurrency {
+        active: AtomicUsize,
+        max: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl AsyncHttpRangeClient for Arc<Concurrency> {
+        async fn get_range(
+            &self,
+            _url: &str,
+            _range: &str,
+            _headers: &[(String, String)],
+        ) -> Result<Bytes> {
+            let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max.fetch_max(active, Ordering::SeqCst);
+            futures_timer::Delay::new(Duration::from_millis(20)).await;
+            self.active.fetch_sub(1, Ordering::SeqCst);
+            Ok(Bytes::from_static(&[0; 4]))
+        }
+        async fn head_response_header(
+            &self,
+            _url: &str,
+            _header: &str,
+            _headers: &[(String, String)],
+        ) -> Result<Option<String>> {
+            Ok(None)
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn max_concurrent() -> Result<()> {
+        let limiter = Arc::new(RateLimiter::new().with_max_concurrent(2));
+        let backend = Arc::new(Concurrency::default());
+        let tasks = (0..6).map(|_| {
+            let http_client = RateLimitedClient::new(backend.clone(), limiter.clone());
+            tokio::spawn(async move {
+                let mut client = AsyncBufferedHttpRangeClient::with(http_client, "mock");
+                client.get_range(0, 4).await.map(|_| ())
+            })
+        });
+        for task in tasks.collect::<Vec<_>>() {
+            task.await.unwrap()?;
+        }
+        assert_eq!(backend.max.load(Ordering::SeqCst), 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,293 @@
+//! Iteration over length-prefixed records.
+use crate::buffered_range_client::unexpected_end;
+use crate::error::{HttpError, Result};
+use crate::range_client::{AsyncHttpRangeClient, SyncHttpRangeClient};
+use crate::{AsyncBufferedHttpRangeClient, SyncBufferedHttpRangeClient};
+use byteorder::{BigEndian, LittleEndian};
+use bytes::Bytes;
+use futures_util::stream::{self, Stream};
+use std::convert::TryFrom;
+
+/// Encoding of the record length preceding each record
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LengthPrefix {
+    /// Unsigned 32 bit integer, little-endian
+    U32Le,
+    /// Unsigned 32 bit integer, big-endian
+    U32Be,
+    /// Unsigned LEB128 varint, as used by Protocol Buffers
+    Varint,
+}
+
+/// Maximal number of bytes of a 64 bit varint
+const MAX_VARINT_LEN: usize = 10;
+/// Default maximal record length
+const DEFAULT_MAX_RECORD_LEN: usize = 64 * 1024 * 1024;
+
+fn varint_overflow() -> HttpError {
+    HttpError::HttpError("varint length prefix exceeds 64 bits".to_string())
+}
+
+/// Record length at `position`, if within `max_len` and addressable
+fn record_len(length: u64, position: usize, max_len: usize) -> Result<usize> {
+    usize::try_from(length)
+        .ok()
+        .filter(|&length| length <= max_len && position.checked_add(length).is_some())
+        .ok_or_else(|| {
+            HttpError::HttpError(format!(
+                "record length {length} exceeds the maximum of {max_len} bytes"
+            ))
+        })
+}
+
+/// Check whether a failed length prefix read indicates the end of the data
+fn end_of_data(error: &HttpError) -> bool {
+    matches!(error, HttpError::HttpStatus(416))
+}
+
+/// Iterator over length-prefixed records of a [SyncBufferedHttpRangeClient]
+///
+/// Records are read sequentially through the reader buffer, so a scan over
+/// small records needs one request per `min_req_size` bytes.
+pub struct Records<'a, T: SyncHttpRangeClient> {
+    client: &'a mut SyncBufferedHttpRangeClient<T>,
+    prefix: LengthPrefix,
+    max_len: usize,
+    done: bool,
+}
+
+impl<T: SyncHttpRangeClient> Records<'_, T> {
+    fn read_varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for i in 0..MAX_VARINT_LEN {
+            let byte = match self.client.get_u8() {
+                Err(e) if i > 0 && end_of_data(&e) => return Err(unexpected_end(i + 1)),
+                result => result?,
+            };
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(varint_overflow())
+    }
+
+    fn read_length(&mut self) -> Result<Option<usize>> {
+        let length = match self.prefix {
+            LengthPrefix::U32Le => self.client.get_u32::<LittleEndian>().map(u64::from),
+            LengthPrefix::U32Be => self.client.get_u32::<BigEndian>().map(u64::from),
+            LengthPrefix::Varint => self.read_varint(),
+        };
+        match length {
+            Ok(length) => record_len(length, self.client.position(), self.max_len).map(Some),
+            Err(e) if end_of_data(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn read_record(&mut self) -> Result<Option<Bytes>> {
+        let at_end = self
+            .client
+            .content_length()
+            .is_some_and(|length| self.client.position() as u64 >= length);
+        if at_end {
+            return Ok(None);
+        }
+        let Some(length) = self.read_length()? else {
+            return Ok(None);
+        };
+        let bytes = self.client.get_bytes(length)?;
+        if bytes.len() < length {
+            return Err(unexpected_end(length));
+        }
+        Ok(Some(Bytes::copy_from_slice(bytes)))
+    }
+}
+
+impl<T: SyncHttpRangeClient> Iterator for Records<'_, T> {
+    type Item = Result<Bytes>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let record = self.read_record().transpose();
+        self.done = !matches!(record, Some(Ok(_)));
+        record
+    }
+}
+
+impl<T: SyncHttpRangeClient> SyncBufferedHttpRangeClient<T> {
+    /// Iterate over length-prefixed records of up to 64 MiB, starting at the current position.
+    ///
+    /// Iteration ends at the end of the data or after the first error.
+    pub fn records(&mut self, prefix: LengthPrefix) -> Records<'_, T> {
+        self.records_with_max_len(prefix, DEFAULT_MAX_RECORD_LEN)
+    }
+
+    /// Iterate over length-prefixed records of up to `max_len` bytes.
+    ///
+    /// Longer records end the iteration with an error.
+    pub fn records_with_max_len(&mut self, prefix: LengthPrefix, max_len: usize) -> Records<'_, T> {
+        Records {
+            client: self,
+            prefix,
+            max_len,
+            done: false,
+        }
+    }
+}
+
+impl<T: AsyncHttpRangeClient> AsyncBufferedHttpRangeClient<T> {
+    async fn read_varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for i in 0..MAX_VARINT_LEN {
+            let byte = match self.get_u8().await {
+                Err(e) if i > 0 && end_of_data(&e) => return Err(unexpected_end(i + 1)),
+                result => result?,
+            };
+            value |= u64::from(byte & 0x7f) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(varint_overflow())
+    }
+
+    async fn read_length(&mut self, prefix: LengthPrefix, max_len: usize) -> Result<Option<usize>> {
+        let length = match prefix {
+            LengthPrefix::U32Le => self.get_u32::<LittleEndian>().await.map(u64::from),
+            LengthPrefix::U32Be => self.get_u32::<BigEndian>().await.map(u64::from),
+            LengthPrefix::Varint => self.read_varint().await,
+        };
+        match length {
+            Ok(length) => record_len(length, self.position(), max_len).map(Some),
+            Err(e) if end_of_data(&e) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn read_record(&mut self, prefix: LengthPrefix, max_len: usize) -> Result<Option<Bytes>> {
+        let at_end = self
+            .content_length()
+            .is_some_and(|length| self.position() as u64 >= length);
+        if at_end {
+            return Ok(None);
+        }
+        let Some(length) = self.read_length(prefix, max_len).await? else {
+            return Ok(None);
+        };
+        let bytes = self.get_bytes(length).await?;
+        if bytes.len() < length {
+            return Err(unexpected_end(length));
+        }
+        Ok(Some(Bytes::copy_from_slice(bytes)))
+    }
+
+    /// Stream of length-prefixed records of up to 64 MiB, starting at the current position.
+    ///
+    /// Records are read sequentially through the reader buffer, so a scan over
+    /// small records needs one request per `min_req_size` bytes.
+    /// The stream ends at the end of the data or after the first error.
+    pub fn records(&mut self, prefix: LengthPrefix) -> impl Stream<Item = Result<Bytes>> + '_ {
+        self.records_with_max_len(prefix, DEFAULT_MAX_RECORD_LEN)
+    }
+
+    /// Stream of length-prefixed records of up to `max_len` bytes.
+    ///
+    /// Longer records end the stream with an error.
+    pub fn records_with_max_len(
+        &mut self,
+        prefix: LengthPrefix,
+        max_len: usize,
+    ) -> impl Stream<Item = Result<Bytes>> + '_ {
+        stream::unfold(Some(self), move |client| async move {
+            let client = client?;
+            match client.read_record(prefix, max_len).await.transpose()? {
+                Ok(record) => Some((Ok(record), Some(client))),
+                Err(e) => Some((Err(e), None)),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_client::MockHttpRangeClient;
+    use futures_util::TryStreamExt;
+
+    fn records(prefix: LengthPrefix) -> Vec<u8> {
+        let mut data = Vec::new();
+        for len in [3u32, 0, 200] {
+            match prefix {
+                LengthPrefix::U32Le => data.extend(len.to_le_bytes()),
+                LengthPrefix::U32Be => data.extend(len.to_be_bytes()),
+                LengthPrefix::Varint if len < 128 => data.push(len as u8),
+                LengthPrefix::Varint => data.extend([(len as u8 & 0x7f) | 0x80, 1]),
+            }
+            data.extend((0..len).map(|i| i as u8));
+        }
+        data
+    }
+
+    #[test]
+    fn sync_records() -> Result<()> {
+        for prefix in [
+            LengthPrefix::U32Le,
+            LengthPrefix::U32Be,
+            LengthPrefix::Varint,
+        ] {
+            let backend = MockHttpRangeClient::new(records(prefix));
+            let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+            client.min_req_size(128);
+            let lengths = client
+                .records(prefix)
+                .map(|record| record.map(|r| r.len()))
+                .collect::<Result<Vec<_>>>()?;
+            assert_eq!(lengths, [3, 0, 200]);
+            assert_eq!(backend.requests().len(), 2);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn record_length_limit() -> Result<()> {
+        let backend = MockHttpRangeClient::new(records(LengthPrefix::U32Be));
+        let mut client = SyncBufferedHttpRangeClient::with(backend, "mock");
+        let mut records = client.records_with_max_len(LengthPrefix::U32Be, 100);
+        assert_eq!(records.next().unwrap()?, [0, 1, 2][..]);
+        assert!(records.next().unwrap()?.is_empty());
+        assert!(records.next().unwrap().is_err());
+        assert!(records.next().is_none());
+
+        let backend = MockHttpRangeClient::new(u32::MAX.to_le_bytes().to_vec());
+        let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        let err = client
+            .records(LengthPrefix::U32Le)
+            .next()
+            .unwrap()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "http error `record length 4294967295 exceeds the maximum of 67108864 bytes`"
+        );
+        assert_eq!(backend.requests().len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_records() -> Result<()> {
+        let mut data = records(LengthPrefix::Varint);
+        // Truncated last record
+        data.truncate(data.len() - 1);
+        let backend = MockHttpRangeClient::new(data);
+        let mut client = AsyncBufferedHttpRangeClient::with(backend, "mock");
+        let mut records = Box::pin(client.records(LengthPrefix::Varint));
+        assert_eq!(records.try_next().await?.unwrap(), [0, 1, 2][..]);
+        assert!(records.try_next().await?.unwrap().is_empty());
+        assert!(records.try_next().await.is_err());
+        assert!(records.try_next().await?.is_none());
+        Ok(())
+    }
+}
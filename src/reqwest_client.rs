@@ -1,61 +1,144 @@
 use crate::error::{HttpError, Result};
+use crate::range_client::header_value;
 use bytes::Bytes;
 
 #[cfg(feature = "reqwest-async")]
 pub(crate) mod nonblocking {
     use super::*;
-    use crate::range_client::AsyncHttpRangeClient;
+    use crate::range_client::{AsyncHttpRangeClient, RangeResponse};
     use async_trait::async_trait;
 
     #[cfg(not(target_arch = "wasm32"))]
     #[async_trait]
     impl AsyncHttpRangeClient for reqwest::Client {
-        async fn get_range(&self, url: &str, range: &str) -> Result<Bytes> {
-            let response = self.get(url).header("Range", range).send().await?;
+        async fn get_range(
+            &self,
+            url: &str,
+            range: &str,
+            headers: &[(String, String)],
+        ) -> Result<Bytes> {
+            self.get_range_response(url, range, headers)
+                .await
+                .map(|response| response.data)
+        }
+        async fn get_range_response(
+            &self,
+            url: &str,
+            range: &str,
+            headers: &[(String, String)],
+        ) -> Result<RangeResponse> {
+            let mut request = self.get(url).header("Range", range);
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            let response = request.send().await?;
             if !response.status().is_success() {
                 return Err(HttpError::HttpStatus(response.status().as_u16()));
             }
-            response
+            let content_range = header_value(response.headers(), "content-range")?;
+            let final_url = response.url().to_string();
+            let data = response
                 .bytes()
                 .await
-                .map_err(|e| HttpError::HttpError(e.to_string()))
-        }
-        async fn head_response_header(&self, url: &str, header: &str) -> Result<Option<String>> {
-            let response = self.head(url).send().await?;
-            if let Some(val) = response.headers().get(header) {
-                let v = val
-                    .to_str()
-                    .map_err(|e| HttpError::HttpError(e.to_string()))?;
-                Ok(Some(v.to_string()))
-            } else {
-                Ok(None)
+                .map_err(|e| HttpError::TransportError(e.to_string()))?;
+            Ok(RangeResponse::new(data)
+                .with_content_range(content_range)
+                .with_url(Some(final_url)))
+        }
+        async fn head_response_header(
+            &self,
+            url: &str,
+            header: &str,
+            headers: &[(String, String)],
+        ) -> Result<Option<String>> {
+            let mut values = self.head_response_headers(url, &[header], headers).await?;
+            Ok(values.pop().flatten())
+        }
+        async fn head_response_headers(
+            &self,
+            url: &str,
+            names: &[&str],
+            headers: &[(String, String)],
+        ) -> Result<Vec<Option<String>>> {
+            let mut request = self.head(url);
+            for (name, value) in headers {
+                request = request.header(name, value);
             }
+            let response = request.send().await?;
+            if !response.status().is_success() {
+                return Err(HttpError::HttpStatus(response.status().as_u16()));
+            }
+            names
+                .iter()
+                .map(|name| header_value(response.headers(), name))
+                .collect()
         }
     }
 
     #[cfg(target_arch = "wasm32")]
     #[async_trait(?Send)]
     impl AsyncHttpRangeClient for reqwest::Client {
-        async fn get_range(&self, url: &str, range: &str) -> Result<Bytes> {
-            let response = self.get(url).header("Range", range).send().await?;
+        async fn get_range(
+            &self,
+            url: &str,
+            range: &str,
+            headers: &[(String, String)],
+        ) -> Result<Bytes> {
+            self.get_range_response(url, range, headers)
+                .await
+                .map(|response| response.data)
+        }
+        async fn get_range_response(
+            &self,
+            url: &str,
+            range: &str,
+            headers: &[(String, String)],
+        ) -> Result<RangeResponse> {
+            let mut request = self.get(url).header("Range", range);
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            let response = request.send().await?;
             if !response.status().is_success() {
                 return Err(HttpError::HttpStatus(response.status().as_u16()));
             }
-            response
+            let content_range = header_value(response.headers(), "content-range")?;
+            let final_url = response.url().to_string();
+            let data = response
                 .bytes()
                 .await
-                .map_err(|e| HttpError::HttpError(e.to_string()))
-        }
-        async fn head_response_header(&self, url: &str, header: &str) -> Result<Option<String>> {
-            let response = self.head(url).send().await?;
-            if let Some(val) = response.headers().get(header) {
-                let v = val
-                    .to_str()
-                    .map_err(|e| HttpError::HttpError(e.to_string()))?;
-                Ok(Some(v.to_string()))
-            } else {
-                Ok(None)
+                .map_err(|e| HttpError::TransportError(e.to_string()))?;
+            Ok(RangeResponse::new(data)
+                .with_content_range(content_range)
+                .with_url(Some(final_url)))
+        }
+        async fn head_response_header(
+            &self,
+            url: &str,
+            header: &str,
+            headers: &[(String, String)],
+        ) -> Result<Option<String>> {
+            let mut values = self.head_response_headers(url, &[header], headers).await?;
+            Ok(values.pop().flatten())
+        }
+        async fn head_response_headers(
+            &self,
+            url: &str,
+            names: &[&str],
+            headers: &[(String, String)],
+        ) -> Result<Vec<Option<String>>> {
+            let mut request = self.head(url);
+            for (name, value) in headers {
+                request = request.header(name, value);
             }
+            let response = request.send().await?;
+            if !response.status().is_success() {
+                return Err(HttpError::HttpStatus(response.status().as_u16()));
+            }
+            names
+                .iter()
+                .map(|name| header_value(response.headers(), name))
+                .collect()
         }
     }
 
@@ -63,8 +146,33 @@ pub(crate) mod nonblocking {
     pub type BufferedHttpRangeClient = crate::AsyncBufferedHttpRangeClient<reqwest::Client>;
 
     impl BufferedHttpRangeClient {
+        /// Create a client with the default timeouts of [HttpRangeClientBuilder](crate::HttpRangeClientBuilder).
+        ///
+        /// # Panics
+        ///
+        /// Panics if the TLS backend cannot be initialized.
         pub fn new(url: &str) -> Self {
-            Self::with(reqwest::Client::new(), url)
+            let http_client = crate::HttpRangeClientBuilder::new()
+                .reqwest_client()
+                .expect("Failed to initialize HTTP client");
+            Self::with(http_client, url)
+        }
+    }
+
+    /// Async HTTP client for concurrent HTTP Range requests with a shared block cache.
+    pub type SharedHttpRangeClient = crate::AsyncSharedHttpRangeClient<reqwest::Client>;
+
+    impl SharedHttpRangeClient {
+        /// Create a client with the default timeouts of [HttpRangeClientBuilder](crate::HttpRangeClientBuilder).
+        ///
+        /// # Panics
+        ///
+        /// Panics if the TLS backend cannot be initialized.
+        pub fn new(url: &str) -> Self {
+            let http_client = crate::HttpRangeClientBuilder::new()
+                .reqwest_client()
+                .expect("Failed to initialize HTTP client");
+            Self::with(http_client, url)
         }
     }
 }
@@ -72,28 +180,63 @@ pub(crate) mod nonblocking {
 #[cfg(feature = "reqwest-sync")]
 pub(crate) mod sync {
     use super::*;
-    use crate::range_client::SyncHttpRangeClient;
+    use crate::range_client::{RangeResponse, SyncHttpRangeClient};
 
     impl SyncHttpRangeClient for reqwest::blocking::Client {
-        fn get_range(&self, url: &str, range: &str) -> Result<Bytes> {
-            let response = self.get(url).header("Range", range).send()?;
+        fn get_range(&self, url: &str, range: &str, headers: &[(String, String)]) -> Result<Bytes> {
+            self.get_range_response(url, range, headers)
+                .map(|response| response.data)
+        }
+        fn get_range_response(
+            &self,
+            url: &str,
+            range: &str,
+            headers: &[(String, String)],
+        ) -> Result<RangeResponse> {
+            let mut request = self.get(url).header("Range", range);
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            let response = request.send()?;
             if !response.status().is_success() {
                 return Err(HttpError::HttpStatus(response.status().as_u16()));
             }
-            response
+            let content_range = header_value(response.headers(), "content-range")?;
+            let final_url = response.url().to_string();
+            let data = response
                 .bytes()
-                .map_err(|e| HttpError::HttpError(e.to_string()))
-        }
-        fn head_response_header(&self, url: &str, header: &str) -> Result<Option<String>> {
-            let response = self.head(url).send()?;
-            if let Some(val) = response.headers().get(header) {
-                let v = val
-                    .to_str()
-                    .map_err(|e| HttpError::HttpError(e.to_string()))?;
-                Ok(Some(v.to_string()))
-            } else {
-                Ok(None)
+                .map_err(|e| HttpError::TransportError(e.to_string()))?;
+            Ok(RangeResponse::new(data)
+                .with_content_range(content_range)
+                .with_url(Some(final_url)))
+        }
+        fn head_response_header(
+            &self,
+            url: &str,
+            header: &str,
+            headers: &[(String, String)],
+        ) -> Result<Option<String>> {
+            let mut values = self.head_response_headers(url, &[header], headers)?;
+            Ok(values.pop().flatten())
+        }
+        fn head_response_headers(
+            &self,
+            url: &str,
+            names: &[&str],
+            headers: &[(String, String)],
+        ) -> Result<Vec<Option<String>>> {
+            let mut request = self.head(url);
+            for (name, value) in headers {
+                request = request.header(name, value);
             }
+            let response = request.send()?;
+            if !response.status().is_success() {
+                return Err(HttpError::HttpStatus(response.status().as_u16()));
+            }
+            names
+                .iter()
+                .map(|name| header_value(response.headers(), name))
+                .collect()
         }
     }
 
@@ -101,8 +244,33 @@ pub(crate) mod sync {
     pub type HttpReader = crate::SyncBufferedHttpRangeClient<reqwest::blocking::Client>;
 
     impl HttpReader {
+        /// Create a client with the default timeouts of [HttpRangeClientBuilder](crate::HttpRangeClientBuilder).
+        ///
+        /// # Panics
+        ///
+        /// Panics if the TLS backend cannot be initialized.
         pub fn new(url: &str) -> Self {
-            Self::with(reqwest::blocking::Client::new(), url)
+            let http_client = crate::HttpRangeClientBuilder::new()
+                .reqwest_blocking_client()
+                .expect("Failed to initialize HTTP client");
+            Self::with(http_client, url)
+        }
+    }
+
+    /// Sync HTTP client for concurrent HTTP Range requests with a shared block cache.
+    pub type SharedHttpReader = crate::SyncSharedHttpRangeClient<reqwest::blocking::Client>;
+
+    impl SharedHttpReader {
+        /// Create a client with the default timeouts of [HttpRangeClientBuilder](crate::HttpRangeClientBuilder).
+        ///
+        /// # Panics
+        ///
+        /// Panics if the TLS backend cannot be initialized.
+        pub fn new(url: &str) -> Self {
+            let http_client = crate::HttpRangeClientBuilder::new()
+                .reqwest_blocking_client()
+                .expect("Failed to initialize HTTP client");
+            Self::with(http_client, url)
         }
     }
 }
@@ -111,8 +279,96 @@ impl From<reqwest::Error> for HttpError {
     fn from(error: reqwest::Error) -> Self {
         if let Some(status) = error.status() {
             HttpError::HttpStatus(status.as_u16())
-        } else {
+        } else if error.is_builder() {
             HttpError::HttpError(error.to_string())
+        } else {
+            HttpError::TransportError(error.to_string())
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::error::HttpError;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+
+    const FORBIDDEN: &str =
+        "HTTP/1.1 403 Forbidden\r\nContent-Length: 9\r\nConnection: close\r\n\r\nforbidden";
+
+    /// Serve `count` requests on localhost with the response for the request line,
+    /// returning the URL of `/file`.
+    fn serve(count: usize, respond: fn(&str) -> &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/file", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for stream in listener.incoming().take(count) {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                stream.write_all(respond(&request_line).as_bytes()).unwrap();
+            }
+        });
+        url
+    }
+
+    #[cfg(feature = "reqwest-sync")]
+    #[test]
+    fn sync_head_error_status() {
+        use crate::range_client::SyncHttpRangeClient;
+        let url = serve(2, |_| FORBIDDEN);
+        let client = reqwest::blocking::Client::new();
+        let result = client.head_response_headers(&url, &["content-length"], &[]);
+        assert!(matches!(result, Err(HttpError::HttpStatus(403))));
+        // The length of the error page is not taken as file length
+        let mut reader = crate::HttpReader::with(client, &url);
+        assert!(matches!(
+            reader.get_content_length(),
+            Err(HttpError::HttpStatus(403))
+        ));
+        assert_eq!(reader.content_length(), None);
+    }
+
+    #[cfg(feature = "reqwest-sync")]
+    #[test]
+    fn sync_head_pinned_url_expired() -> crate::Result<()> {
+        const REDIRECT: &str = "HTTP/1.1 302 Found\r\nLocation: /signed\r\n\
+            Content-Length: 0\r\nConnection: close\r\n\r\n";
+        const PARTIAL: &str = "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 0-3/1000\r\n\
+            Content-Length: 4\r\nConnection: close\r\n\r\nfgb\x03";
+        const LENGTH: &str = "HTTP/1.1 200 OK\r\nContent-Length: 1000\r\nConnection: close\r\n\r\n";
+        let url = serve(4, |request_line| {
+            if request_line.starts_with("GET /file ") {
+                REDIRECT
+            } else if request_line.starts_with("GET /signed ") {
+                PARTIAL
+            } else if request_line.starts_with("HEAD /file ") {
+                LENGTH
+            } else {
+                FORBIDDEN
+            }
+        });
+        let mut reader = crate::HttpReader::with(reqwest::blocking::Client::new(), &url);
+        reader.pin_redirects(true);
+        assert_eq!(reader.min_req_size(4).get_range(0, 4)?, b"fgb\x03");
+        // HEAD of the pinned URL fails and is sent to the original URL
+        assert_eq!(reader.get_content_length()?, Some(1000));
+        Ok(())
+    }
+
+    #[cfg(feature = "reqwest-async")]
+    #[tokio::test]
+    async fn async_head_error_status() {
+        use crate::range_client::AsyncHttpRangeClient;
+        let url = serve(1, |_| FORBIDDEN);
+        let result = reqwest::Client::new()
+            .head_response_headers(&url, &["etag"], &[])
+            .await;
+        assert!(matches!(result, Err(HttpError::HttpStatus(403))));
+    }
+}
@@ -0,0 +1,218 @@
+use crate::error::{HttpError, Result};
+use crate::range_client::{header_value, AsyncHttpRangeClient, RangeResponse};
+use async_trait::async_trait;
+use bytes::Bytes;
+use reqwest_middleware::ClientWithMiddleware;
+
+#[cfg(not(target_arch = "wasm32"))]
+#[async_trait]
+impl AsyncHttpRangeClient for ClientWithMiddleware {
+    async fn get_range(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<Bytes> {
+        self.get_range_response(url, range, headers)
+            .await
+            .map(|response| response.data)
+    }
+    async fn get_range_response(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<RangeResponse> {
+        let mut request = self.get(url).header("Range", range);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(HttpError::HttpStatus(response.status().as_u16()));
+        }
+        let content_range = header_value(response.headers(), "content-range")?;
+        let final_url = response.url().to_string();
+        let data = response
+            .bytes()
+            .await
+            .map_err(|e| HttpError::TransportError(e.to_string()))?;
+        Ok(RangeResponse::new(data)
+            .with_content_range(content_range)
+            .with_url(Some(final_url)))
+    }
+    async fn head_response_header(
+        &self,
+        url: &str,
+        header: &str,
+        headers: &[(String, String)],
+    ) -> Result<Option<String>> {
+        let mut values = self.head_response_headers(url, &[header], headers).await?;
+        Ok(values.pop().flatten())
+    }
+    async fn head_response_headers(
+        &self,
+        url: &str,
+        names: &[&str],
+        headers: &[(String, String)],
+    ) -> Result<Vec<Option<String>>> {
+        let mut request = self.head(url);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(HttpError::HttpStatus(response.status().as_u16()));
+        }
+        names
+            .iter()
+            .map(|name| header_value(response.headers(), name))
+            .collect()
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+#[async_trait(?Send)]
+impl AsyncHttpRangeClient for ClientWithMiddleware {
+    async fn get_range(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<Bytes> {
+        self.get_range_response(url, range, headers)
+            .await
+            .map(|response| response.data)
+    }
+    async fn get_range_response(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<RangeResponse> {
+        let mut request = self.get(url).header("Range", range);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(HttpError::HttpStatus(response.status().as_u16()));
+        }
+        let content_range = header_value(response.headers(), "content-range")?;
+        let final_url = response.url().to_string();
+        let data = response
+            .bytes()
+            .await
+            .map_err(|e| HttpError::TransportError(e.to_string()))?;
+        Ok(RangeResponse::new(data)
+            .with_content_range(content_range)
+            .with_url(Some(final_url)))
+    }
+    async fn head_response_header(
+        &self,
+        url: &str,
+        header: &str,
+        headers: &[(String, String)],
+    ) -> Result<Option<String>> {
+        let mut values = self.head_response_headers(url, &[header], headers).await?;
+        Ok(values.pop().flatten())
+    }
+    async fn head_response_headers(
+        &self,
+        url: &str,
+        names: &[&str],
+        headers: &[(String, String)],
+    ) -> Result<Vec<Option<String>>> {
+        let mut request = self.head(url);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let response = request.send().await?;
+        if !response.status().is_success() {
+            return Err(HttpError::HttpStatus(response.status().as_u16()));
+        }
+        names
+            .iter()
+            .map(|name| header_value(response.headers(), name))
+            .collect()
+    }
+}
+
+impl From<reqwest_middleware::Error> for HttpError {
+    fn from(error: reqwest_middleware::Error) -> Self {
+        match error {
+            reqwest_middleware::Error::Reqwest(e) => e.into(),
+            reqwest_middleware::Error::Middleware(e) => HttpError::HttpError(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::AsyncBufferedHttpRangeClient;
+    use reqwest_middleware::{ClientBuilder, Middleware, Next};
+
+    /// Serves Range requests in-process without sending them
+    struct Serve;
+
+    #[async_trait]
+    impl Middleware for Serve {
+        async fn handle(
+            &self,
+            req: reqwest::Request,
+            _extensions: &mut http::Extensions,
+            _next: Next<'_>,
+        ) -> reqwest_middleware::Result<reqwest::Response> {
+            if req.method() == reqwest::Method::HEAD {
+                let status = if req.url().path() == "/missing" {
+                    404
+                } else {
+                    200
+                };
+                let response = http::Response::builder()
+                    .status(status)
+                    .header("Content-Length", "256")
+                    .body(Vec::new())
+                    .unwrap();
+                return Ok(response.into());
+            }
+            let range = req.headers()["Range"].to_str().unwrap();
+            let (begin, end) = range
+                .strip_prefix("bytes=")
+                .and_then(|r| r.split_once('-'))
+                .unwrap();
+            let begin: u8 = begin.parse().unwrap();
+            let end = end.parse::<usize>().unwrap().min(255) as u8;
+            let status = if req.url().path() == "/missing" {
+                404
+            } else {
+                206
+            };
+            let response = http::Response::builder()
+                .status(status)
+                .header("Content-Range", format!("bytes {begin}-{end}/256"))
+                .body((begin..=end).collect::<Vec<u8>>())
+                .unwrap();
+            Ok(response.into())
+        }
+    }
+
+    #[tokio::test]
+    async fn client_with_middleware() -> Result<()> {
+        let http_client = ClientBuilder::new(reqwest::Client::new())
+            .with(Serve)
+            .build();
+        let mut client = AsyncBufferedHttpRangeClient::with(http_client.clone(), "http://local/");
+        let bytes = client.min_req_size(16).get_range(10, 4).await?;
+        assert_eq!(bytes, [10, 11, 12, 13]);
+        assert_eq!(client.content_length(), Some(256));
+
+        let mut client = AsyncBufferedHttpRangeClient::with(http_client, "http://local/missing");
+        let result = client.get_range(0, 4).await;
+        assert_eq!(result.unwrap_err().to_string(), "http status 404");
+        let result = client.head_response_header("content-length").await;
+        assert_eq!(result.unwrap_err().to_string(), "http status 404");
+        Ok(())
+    }
+}
@@ -0,0 +1,97 @@
+//! Retry policy for transient request failures.
+use crate::error::HttpError;
+use std::time::Duration;
+
+/// Retry policy for failed range requests
+///
+/// Requests failing with a transport error or with status 408, 429, 500, 502, 503
+/// or 504 are retried with exponential backoff. The default policy does not retry.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries: 0,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Policy without retries.
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Retry up to `max_retries` times, waiting 100ms before the first retry
+    /// and doubling the delay for every further retry.
+    pub fn exponential(max_retries: u32) -> Self {
+        RetryPolicy {
+            max_retries,
+            ..Default::default()
+        }
+    }
+
+    /// Set delay before the first retry and the upper limit of retry delays.
+    pub fn with_backoff(mut self, initial: Duration, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.max_backoff = max;
+        self
+    }
+
+    /// Maximal number of retries
+    pub fn max_retries(&self) -> u32 {
+        self.max_retries
+    }
+
+    /// Delay before retry number `attempt` (starting at 0), if the error is retryable.
+    pub(crate) fn retry_delay(&self, error: &HttpError, attempt: u32) -> Option<Duration> {
+        let retryable = matches!(
+            error,
+            HttpError::TransportError(_) | HttpError::HttpStatus(408 | 429 | 500 | 502 | 503 | 504)
+        );
+        if !retryable || attempt >= self.max_retries {
+            return None;
+        }
+        let factor = 2u32.saturating_pow(attempt);
+        Some(
+            self.initial_backoff
+                .saturating_mul(factor)
+                .min(self.max_backoff),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let policy = RetryPolicy::exponential(3)
+            .with_backoff(Duration::from_millis(100), Duration::from_millis(300));
+        let error = HttpError::HttpStatus(503);
+        let delays: Vec<_> = (0..4).map(|i| policy.retry_delay(&error, i)).collect();
+        assert_eq!(
+            delays,
+            [
+                Some(Duration::from_millis(100)),
+                Some(Duration::from_millis(200)),
+                Some(Duration::from_millis(300)),
+                None
+            ]
+        );
+        assert_eq!(policy.retry_delay(&HttpError::HttpStatus(404), 0), None);
+        assert_eq!(RetryPolicy::none().retry_delay(&error, 0), None);
+        let transport = HttpError::TransportError("connection reset".to_string());
+        assert!(policy.retry_delay(&transport, 0).is_some());
+        let denied = HttpError::HttpError("denied".to_string());
+        assert_eq!(policy.retry_delay(&denied, 0), None);
+    }
+}
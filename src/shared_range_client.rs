@@ -0,0 +1,528 @@
+use crate::error::{HttpError, Result};
+use crate::range_client::RangeResponse;
+use bytes::{BufMut, Bytes, BytesMut};
+use read_logger::{Level, ReadStatsLogger};
+use std::cmp::min;
+use std::collections::{HashMap, VecDeque};
+
+/// Runs of blocks `(first_block, data)`
+type BlockRuns = Vec<(usize, Bytes)>;
+
+/// Block cache shared between concurrent readers
+struct BlockCache {
+    block_size: usize,
+    max_blocks: usize,
+    blocks: HashMap<usize, Bytes>,
+    /// Insertion order for eviction
+    order: VecDeque<usize>,
+    /// File length, if known from a response
+    length: Option<usize>,
+    http_stats: ReadStatsLogger,
+}
+
+impl BlockCache {
+    fn new() -> Self {
+        BlockCache {
+            block_size: 64 * 1024,
+            max_blocks: 256,
+            blocks: HashMap::new(),
+            order: VecDeque::new(),
+            length: None,
+            http_stats: ReadStatsLogger::new(Level::Debug, "http-range-shared"),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.blocks.clear();
+        self.order.clear();
+    }
+
+    /// Runs of blocks `(first_block, block_count)` missing for the given byte range,
+    /// clamped to the file length if known, and the cached blocks of the range
+    fn lookup(&self, begin: usize, length: usize) -> (Vec<(usize, usize)>, BlockRuns) {
+        let mut missing: Vec<(usize, usize)> = Vec::new();
+        let mut cached = Vec::new();
+        let end = self
+            .length
+            .map_or(begin + length, |len| min(begin + length, len));
+        if begin >= end {
+            return (missing, cached);
+        }
+        let first = begin / self.block_size;
+        let last = (end - 1) / self.block_size;
+        for block in first..=last {
+            if let Some(data) = self.blocks.get(&block) {
+                cached.push((block, data.clone()));
+                continue;
+            }
+            match missing.last_mut() {
+                Some((run_begin, count)) if *run_begin + *count == block => *count += 1,
+                _ => missing.push((block, 1)),
+            }
+        }
+        (missing, cached)
+    }
+
+    /// HTTP range header for a run of blocks
+    fn range(&self, first_block: usize, count: usize) -> String {
+        let begin = first_block * self.block_size;
+        let mut end = begin + count * self.block_size;
+        if let Some(length) = self.length {
+            end = min(end, length);
+        }
+        format!("bytes={begin}-{}", end - 1)
+    }
+
+    /// Check and cache the response for a run of `count` blocks, returning its data.
+    ///
+    /// A response without `Content-Range` from a backend reporting response info
+    /// holds the complete file.
+    fn add_response(
+        &mut self,
+        first_block: usize,
+        count: usize,
+        response: RangeResponse,
+    ) -> Result<Bytes> {
+        let begin = first_block * self.block_size;
+        let complete = response.content_range.is_none() && response.url.is_some();
+        let response_begin = if complete {
+            Some(0)
+        } else {
+            response.range_begin()
+        };
+        if response_begin.is_some_and(|b| b != begin as u64) {
+            return Err(HttpError::HttpError(format!(
+                "response doesn't match requested range {}",
+                self.range(first_block, count)
+            )));
+        }
+        if let Some(total) = response.total_length() {
+            self.length = Some(total as usize);
+        } else if complete {
+            self.length = Some(response.data.len());
+        } else if response.data.len() < count * self.block_size {
+            self.length = Some(begin + response.data.len());
+        }
+        let data = response
+            .data
+            .slice(..min(response.data.len(), count * self.block_size));
+        self.insert(first_block, &data);
+        Ok(data)
+    }
+
+    fn insert(&mut self, first_block: usize, bytes: &Bytes) {
+        for (i, offset) in (0..bytes.len()).step_by(self.block_size).enumerate() {
+            let block = first_block + i;
+            let upper = min(offset + self.block_size, bytes.len());
+            if self
+                .blocks
+                .insert(block, bytes.slice(offset..upper))
+                .is_none()
+            {
+                self.order.push_back(block);
+            }
+        }
+        while self.blocks.len() > self.max_blocks {
+            if let Some(block) = self.order.pop_front() {
+                self.blocks.remove(&block);
+            }
+        }
+    }
+
+    fn block(&self, block: usize, fetched: &[(usize, Bytes)]) -> Option<Bytes> {
+        for (first_block, bytes) in fetched {
+            if block >= *first_block {
+                let offset = (block - first_block) * self.block_size;
+                if offset < bytes.len() {
+                    let upper = min(offset + self.block_size, bytes.len());
+                    return Some(bytes.slice(offset..upper));
+                }
+            }
+        }
+        None
+    }
+
+    /// Collect bytes from fetched runs and cached blocks, not from the cache,
+    /// which may have evicted blocks of the range.
+    /// The result is shorter than `length` when the end of the file is reached.
+    fn assemble(&self, begin: usize, length: usize, fetched: &[(usize, Bytes)]) -> Bytes {
+        let mut bytes = BytesMut::with_capacity(length);
+        let end = begin + length;
+        let mut pos = begin;
+        while pos < end {
+            let block = pos / self.block_size;
+            let block_begin = block * self.block_size;
+            let Some(data) = self.block(block, fetched) else {
+                break;
+            };
+            let lower = pos - block_begin;
+            if lower >= data.len() {
+                break;
+            }
+            let upper = min(data.len(), end - block_begin);
+            bytes.put_slice(&data[lower..upper]);
+            pos = block_begin + upper;
+            if data.len() < self.block_size {
+                break;
+            }
+        }
+        bytes.freeze()
+    }
+}
+
+pub(crate) mod nonblocking {
+    use super::*;
+    use crate::range_client::{basic_auth, set_header, AsyncHttpRangeClient};
+    use futures_util::future::try_join_all;
+    use std::sync::Mutex;
+
+    /// HTTP client adapter for concurrent HTTP Range requests with a shared block cache
+    ///
+    /// All reading methods take `&self`, so a client can be shared between tasks via `Arc`.
+    pub struct AsyncSharedHttpRangeClient<T: AsyncHttpRangeClient> {
+        http_client: T,
+        url: String,
+        cache: Mutex<BlockCache>,
+        /// Additional request headers
+        headers: Vec<(String, String)>,
+    }
+
+    impl<T: AsyncHttpRangeClient> AsyncSharedHttpRangeClient<T> {
+        pub fn with(http_client: T, url: &str) -> AsyncSharedHttpRangeClient<T> {
+            AsyncSharedHttpRangeClient {
+                http_client,
+                url: url.to_string(),
+                cache: Mutex::new(BlockCache::new()),
+                headers: Vec::new(),
+            }
+        }
+
+        /// Set size of cached blocks. Clears the cache.
+        pub fn set_block_size(&mut self, size: usize) {
+            let cache = self.cache.get_mut().unwrap();
+            cache.block_size = size.max(1);
+            cache.clear();
+        }
+
+        /// Set size of cached blocks. Clears the cache.
+        pub fn block_size(&mut self, size: usize) -> &mut Self {
+            self.set_block_size(size);
+            self
+        }
+
+        /// Set maximal number of cached blocks.
+        pub fn set_max_cached_blocks(&mut self, count: usize) {
+            self.cache.get_mut().unwrap().max_blocks = count;
+        }
+
+        /// Set maximal number of cached blocks.
+        pub fn max_cached_blocks(&mut self, count: usize) -> &mut Self {
+            self.set_max_cached_blocks(count);
+            self
+        }
+
+        /// Set a request header sent with every request.
+        pub fn set_header(&mut self, name: &str, value: &str) {
+            set_header(&mut self.headers, name, value);
+        }
+
+        /// Set a request header sent with every request.
+        pub fn with_header(&mut self, name: &str, value: &str) -> &mut Self {
+            self.set_header(name, value);
+            self
+        }
+
+        /// Use HTTP Bearer authentication.
+        pub fn bearer_auth(&mut self, token: &str) -> &mut Self {
+            self.with_header("Authorization", &format!("Bearer {token}"))
+        }
+
+        /// Use HTTP Basic authentication.
+        pub fn basic_auth(&mut self, username: &str, password: Option<&str>) -> &mut Self {
+            self.with_header("Authorization", &basic_auth(username, password))
+        }
+
+        /// Get `length` bytes with offset `begin`.
+        pub async fn get_range(&self, begin: usize, length: usize) -> Result<Bytes> {
+            if length == 0 {
+                return Ok(Bytes::new());
+            }
+            let (http_ranges, mut fetched) = {
+                let mut cache = self.cache.lock().unwrap();
+                let (missing, cached) = cache.lookup(begin, length);
+                let http_ranges: Vec<(usize, usize, String)> = missing
+                    .into_iter()
+                    .map(|(first_block, count)| {
+                        let block_size = cache.block_size;
+                        cache
+                            .http_stats
+                            .log(first_block * block_size, count * block_size, length);
+                        (first_block, count, cache.range(first_block, count))
+                    })
+                    .collect();
+                (http_ranges, cached)
+            };
+            let responses =
+                try_join_all(http_ranges.into_iter().map(|(first_block, count, range)| {
+                    let (http_client, url, headers) = (&self.http_client, &self.url, &self.headers);
+                    async move {
+                        let response = http_client.get_range_response(url, &range, headers).await?;
+                        Ok::<_, crate::HttpError>((first_block, count, response))
+                    }
+                }))
+                .await?;
+            let mut cache = self.cache.lock().unwrap();
+            for (first_block, count, response) in responses {
+                fetched.push((
+                    first_block,
+                    cache.add_response(first_block, count, response)?,
+                ));
+            }
+            Ok(cache.assemble(begin, length, &fetched))
+        }
+
+        /// Send a HEAD request and return response header value
+        pub async fn head_response_header(&self, header: &str) -> Result<Option<String>> {
+            self.http_client
+                .head_response_header(&self.url, header, &self.headers)
+                .await
+        }
+    }
+}
+
+pub(crate) mod sync {
+    use super::*;
+    use crate::range_client::{basic_auth, set_header, SyncHttpRangeClient};
+    use std::sync::Mutex;
+
+    /// HTTP client adapter for concurrent HTTP Range requests with a shared block cache
+    ///
+    /// All reading methods take `&self`, so a reader can be shared between threads via `Arc`.
+    pub struct SyncSharedHttpRangeClient<T: SyncHttpRangeClient> {
+        http_client: T,
+        url: String,
+        cache: Mutex<BlockCache>,
+        /// Additional request headers
+        headers: Vec<(String, String)>,
+    }
+
+    impl<T: SyncHttpRangeClient> SyncSharedHttpRangeClient<T> {
+        pub fn with(http_client: T, url: &str) -> SyncSharedHttpRangeClient<T> {
+            SyncSharedHttpRangeClient {
+                http_client,
+                url: url.to_string(),
+                cache: Mutex::new(BlockCache::new()),
+                headers: Vec::new(),
+            }
+        }
+
+        /// Set size of cached blocks. Clears the cache.
+        pub fn set_block_size(&mut self, size: usize) {
+            let cache = self.cache.get_mut().unwrap();
+            cache.block_size = size.max(1);
+            cache.clear();
+        }
+
+        /// Set size of cached blocks. Clears the cache.
+        pub fn block_size(&mut self, size: usize) -> &mut Self {
+            self.set_block_size(size);
+            self
+        }
+
+        /// Set maximal number of cached blocks.
+        pub fn set_max_cached_blocks(&mut self, count: usize) {
+            self.cache.get_mut().unwrap().max_blocks = count;
+        }
+
+        /// Set maximal number of cached blocks.
+        pub fn max_cached_blocks(&mut self, count: usize) -> &mut Self {
+            self.set_max_cached_blocks(count);
+            self
+        }
+
+        /// Set a request header sent with every request.
+        pub fn set_header(&mut self, name: &str, value: &str) {
+            set_header(&mut self.headers, name, value);
+        }
+
+        /// Set a request header sent with every request.
+        pub fn with_header(&mut self, name: &str, value: &str) -> &mut Self {
+            self.set_header(name, value);
+            self
+        }
+
+        /// Use HTTP Bearer authentication.
+        pub fn bearer_auth(&mut self, token: &str) -> &mut Self {
+            self.with_header("Authorization", &format!("Bearer {token}"))
+        }
+
+        /// Use HTTP Basic authentication.
+        pub fn basic_auth(&mut self, username: &str, password: Option<&str>) -> &mut Self {
+            self.with_header("Authorization", &basic_auth(username, password))
+        }
+
+        /// Get `length` bytes with offset `begin`.
+        pub fn get_range(&self, begin: usize, length: usize) -> Result<Bytes> {
+            if length == 0 {
+                return Ok(Bytes::new());
+            }
+            let (missing, mut fetched) = self.cache.lock().unwrap().lookup(begin, length);
+            for (first_block, count) in missing {
+                let range = {
+                    let mut cache = self.cache.lock().unwrap();
+                    let block_size = cache.block_size;
+                    cache
+                        .http_stats
+                        .log(first_block * block_size, count * block_size, length);
+                    cache.range(first_block, count)
+                };
+                let response =
+                    self.http_client
+                        .get_range_response(&self.url, &range, &self.headers)?;
+                let data = self
+                    .cache
+                    .lock()
+                    .unwrap()
+                    .add_response(first_block, count, response)?;
+                fetched.push((first_block, data));
+            }
+            Ok(self.cache.lock().unwrap().assemble(begin, length, &fetched))
+        }
+
+        /// Read bytes at position `offset` into `buf`, returning the number of bytes read.
+        ///
+        /// Returns 0 when `offset` is at or after the end of the file.
+        pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.get_range(offset as usize, buf.len()) {
+                Ok(bytes) => {
+                    buf[..bytes.len()].copy_from_slice(&bytes);
+                    Ok(bytes.len())
+                }
+                Err(HttpError::HttpStatus(416)) => Ok(0),
+                Err(e) => Err(std::io::Error::other(e.to_string())),
+            }
+        }
+
+        /// Send a HEAD request and return response header value
+        pub fn head_response_header(&self, header: &str) -> Result<Option<String>> {
+            self.http_client
+                .head_response_header(&self.url, header, &self.headers)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::mock_client::MockHttpRangeClient;
+    use crate::{AsyncSharedHttpRangeClient, Result, SyncSharedHttpRangeClient};
+    use std::sync::Arc;
+
+    fn expected(begin: usize, length: usize) -> Vec<u8> {
+        (begin..begin + length).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SyncSharedHttpRangeClient<MockHttpRangeClient>>();
+        assert_send_sync::<AsyncSharedHttpRangeClient<MockHttpRangeClient>>();
+    }
+
+    #[test]
+    fn read_cached_blocks() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000);
+        let mut reader = SyncSharedHttpRangeClient::with(backend.clone(), "mock");
+        reader.block_size(100);
+        assert_eq!(reader.get_range(150, 100)?, expected(150, 100));
+        assert_eq!(reader.get_range(120, 10)?, expected(120, 10));
+        assert_eq!(reader.get_range(50, 300)?, expected(50, 300));
+        assert_eq!(
+            backend.requests(),
+            ["GET bytes=100-299", "GET bytes=0-99", "GET bytes=300-399"]
+        );
+
+        let mut buf = [0; 20];
+        assert_eq!(reader.read_at(990, &mut buf).unwrap(), 10);
+        assert_eq!(buf[..10], expected(990, 10));
+        assert_eq!(reader.read_at(1000, &mut buf).unwrap(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn read_at_end_of_file() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000);
+        let mut reader = SyncSharedHttpRangeClient::with(backend.clone(), "mock");
+        reader.block_size(100);
+        let mut buf = [0; 200];
+        assert_eq!(reader.read_at(950, &mut buf[..50]).unwrap(), 50);
+        assert_eq!(reader.read_at(950, &mut buf).unwrap(), 50);
+        assert_eq!(buf[..50], expected(950, 50));
+        assert_eq!(reader.read_at(1000, &mut buf).unwrap(), 0);
+        assert_eq!(backend.requests(), ["GET bytes=900-999"]);
+        Ok(())
+    }
+
+    #[test]
+    fn full_cache() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(1000);
+        let mut reader = SyncSharedHttpRangeClient::with(backend.clone(), "mock");
+        reader.block_size(100).max_cached_blocks(2);
+        assert_eq!(reader.get_range(0, 200)?, expected(0, 200));
+        // Inserting block 2 evicts block 0 of the same range
+        assert_eq!(reader.get_range(0, 300)?, expected(0, 300));
+        assert_eq!(backend.requests(), ["GET bytes=0-199", "GET bytes=200-299"]);
+        Ok(())
+    }
+
+    #[test]
+    fn misplaced_response() {
+        let backend = MockHttpRangeClient::with_len(1000);
+        backend.ignore_ranges();
+        let mut reader = SyncSharedHttpRangeClient::with(backend, "mock");
+        reader.block_size(100);
+        assert!(reader.get_range(250, 10).is_err());
+        assert_eq!(reader.get_range(50, 10).unwrap(), expected(50, 10));
+        assert!(reader.get_range(250, 10).is_err());
+    }
+
+    #[test]
+    fn concurrent_threads() {
+        let backend = MockHttpRangeClient::with_len(100_000);
+        let mut reader = SyncSharedHttpRangeClient::with(backend, "mock");
+        reader.block_size(1024).max_cached_blocks(8);
+        let reader = Arc::new(reader);
+        std::thread::scope(|scope| {
+            for t in 0..4 {
+                let reader = reader.clone();
+                scope.spawn(move || {
+                    for i in 0..50 {
+                        let begin = (t * 7919 + i * 1931) % 99_000;
+                        let mut buf = [0; 700];
+                        let n = reader.read_at(begin as u64, &mut buf).unwrap();
+                        assert_eq!(buf[..n], expected(begin, 700));
+                    }
+                });
+            }
+        });
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn concurrent_tasks() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(10_000);
+        let mut client = AsyncSharedHttpRangeClient::with(backend.clone(), "mock");
+        client.block_size(512);
+        let client = Arc::new(client);
+        let tasks: Vec<_> = (0..8)
+            .map(|t| {
+                let client = client.clone();
+                tokio::spawn(async move { client.get_range(t * 1000, 1500).await })
+            })
+            .collect();
+        for (t, task) in tasks.into_iter().enumerate() {
+            assert_eq!(task.await.unwrap()?, expected(t * 1000, 1500));
+        }
+        // Read past end of file
+        assert_eq!(client.get_range(9990, 100).await?, expected(9990, 10));
+        Ok(())
+    }
+}
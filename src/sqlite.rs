@@ -0,0 +1,753 @@
+//! Read-only SQLite VFS reading database files with range requests.
+use crate::buffered_range_client::unexpected_end;
+use crate::error::{HttpError, Result};
+use crate::range_client::SyncHttpRangeClient;
+use crate::SyncBufferedHttpRangeClient;
+use bytes::Bytes;
+use rusqlite::ffi;
+use std::cmp::{max, min};
+use std::collections::{HashMap, VecDeque};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Mutex;
+use std::{ptr, slice};
+
+/// Page size assumed for the first request
+const DEFAULT_PAGE_SIZE: usize = 4096;
+const DEFAULT_CACHE_PAGES: usize = 1024;
+const DEFAULT_MAX_READAHEAD_PAGES: usize = 64;
+const MAX_PATHNAME: c_int = 4096;
+
+/// Page size from the database header
+fn page_size(header: &[u8]) -> Option<usize> {
+    if header.len() < 18 || &header[..16] != b"SQLite format 3\0" {
+        return None;
+    }
+    match u16::from_be_bytes([header[16], header[17]]) {
+        1 => Some(65536),
+        size if size >= 512 && size.is_power_of_two() => Some(size as usize),
+        _ => None,
+    }
+}
+
+/// Database file read in whole pages, with a page cache and readahead for sequential reads
+struct PagedFile<T: SyncHttpRangeClient> {
+    client: SyncBufferedHttpRangeClient<T>,
+    length: u64,
+    page_size: usize,
+    pages: HashMap<u64, Bytes>,
+    /// Cached pages in insertion order
+    cached: VecDeque<u64>,
+    cache_pages: usize,
+    /// Number of pages read with the next request
+    readahead: usize,
+    max_readahead: usize,
+    /// Page following the last read
+    next_page: u64,
+}
+
+impl<T: SyncHttpRangeClient> PagedFile<T> {
+    /// Read the first 4 KiB, containing the database header.
+    fn open(
+        mut client: SyncBufferedHttpRangeClient<T>,
+        cache_pages: usize,
+        max_readahead: usize,
+    ) -> Result<Self> {
+        let first = client
+            .get_ranges_coalesced(&[(0, DEFAULT_PAGE_SIZE)], 0)?
+            .remove(0);
+        let length = match client.content_length() {
+            Some(length) => length,
+            None => client
+                .get_content_length()?
+                .ok_or_else(|| HttpError::HttpError("content length unknown".to_string()))?,
+        };
+        let page_size = page_size(&first).unwrap_or(DEFAULT_PAGE_SIZE);
+        let mut file = PagedFile {
+            client,
+            length,
+            page_size,
+            pages: HashMap::new(),
+            cached: VecDeque::new(),
+            cache_pages,
+            readahead: 1,
+            max_readahead: max(max_readahead, 1),
+            next_page: 0,
+        };
+        file.insert_pages(0, &first);
+        Ok(file)
+    }
+
+    /// Cache the complete pages of `data`, starting with `page`.
+    fn insert_pages(&mut self, page: u64, data: &Bytes) {
+        let begin = page * self.page_size as u64;
+        for (i, lower) in (0..data.len()).step_by(self.page_size).enumerate() {
+            let upper = min(lower + self.page_size, data.len());
+            if upper - lower < self.page_size && begin + upper as u64 != self.length {
+                break;
+            }
+            self.insert(page + i as u64, data.slice(lower..upper));
+        }
+    }
+
+    fn insert(&mut self, page: u64, data: Bytes) {
+        if self.cache_pages == 0 || self.pages.contains_key(&page) {
+            return;
+        }
+        while self.pages.len() >= self.cache_pages {
+            match self.cached.pop_front() {
+                Some(oldest) => self.pages.remove(&oldest),
+                None => break,
+            };
+        }
+        self.pages.insert(page, data);
+        self.cached.push_back(page);
+    }
+
+    /// Fetch `page` and following uncached pages, at least up to `last`.
+    fn fetch(&mut self, page: u64, last: u64) -> Result<Bytes> {
+        let page_size = self.page_size as u64;
+        let total_pages = self.length.div_ceil(page_size);
+        let wanted = max(last - page + 1, self.readahead as u64).min(total_pages - page);
+        let count = (1..wanted)
+            .take_while(|i| page + i > last || !self.pages.contains_key(&(page + i)))
+            .count() as u64
+            + 1;
+        let begin = page * page_size;
+        let length = min(count * page_size, self.length - begin) as usize;
+        let data = self
+            .client
+            .get_ranges_coalesced(&[(begin as usize, length)], 0)?
+            .remove(0);
+        if data.len() < length {
+            return Err(unexpected_end(length));
+        }
+        self.insert_pages(page, &data);
+        Ok(data)
+    }
+
+    /// Read into `buf`, returning the number of bytes read before the end of the file.
+    fn read(&mut self, buf: &mut [u8], offset: u64) -> Result<usize> {
+        let end = min(offset + buf.len() as u64, self.length);
+        if offset >= end {
+            return Ok(0);
+        }
+        let page_size = self.page_size as u64;
+        let (first, last) = (offset / page_size, (end - 1) / page_size);
+        self.readahead = if first == self.next_page {
+            min(self.readahead * 2, self.max_readahead)
+        } else {
+            1
+        };
+        self.next_page = last + 1;
+        let mut pos = offset;
+        let mut fetched = Bytes::new();
+        let mut fetched_page = 0;
+        while pos < end {
+            let page = pos / page_size;
+            let data = match self.pages.get(&page) {
+                Some(data) => data.clone(),
+                None if page >= fetched_page
+                    && (page - fetched_page) * page_size < fetched.len() as u64 =>
+                {
+                    let lower = ((page - fetched_page) * page_size) as usize;
+                    let upper = min(lower + self.page_size, fetched.len());
+                    fetched.slice(lower..upper)
+                }
+                None => {
+                    fetched = self.fetch(page, last)?;
+                    fetched_page = page;
+                    fetched.slice(..min(self.page_size, fetched.len()))
+                }
+            };
+            let page_begin = page * page_size;
+            let lower = (pos - page_begin) as usize;
+            let upper = min((end - page_begin) as usize, data.len());
+            let dest = (pos - offset) as usize;
+            buf[dest..dest + upper - lower].copy_from_slice(&data[lower..upper]);
+            pos = page_begin + upper as u64;
+        }
+        Ok((end - offset) as usize)
+    }
+}
+
+/// Read-only SQLite VFS opening database URLs with a [SyncBufferedHttpRangeClient]
+///
+/// Reads are aligned to database pages, which are kept in a page cache per
+/// opened file. Sequential reads double the number of pages read ahead.
+/// Temporary files are handled by the default VFS.
+///
+/// ```no_run
+/// # #[cfg(feature = "reqwest-sync")]
+/// # fn open() -> Result<(), Box<dyn std::error::Error>> {
+/// use http_range_client::{HttpReader, SqliteVfs};
+/// use rusqlite::{Connection, OpenFlags};
+///
+/// SqliteVfs::new("http", |url: &str| Ok(HttpReader::new(url))).register()?;
+/// let conn = Connection::open_with_flags_and_vfs(
+///     "https://example.com/db.sqlite",
+///     OpenFlags::SQLITE_OPEN_READ_ONLY,
+///     "http",
+/// )?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct SqliteVfs<F> {
+    name: String,
+    open: F,
+    cache_pages: usize,
+    max_readahead_pages: usize,
+}
+
+impl<T, F> SqliteVfs<F>
+where
+    T: SyncHttpRangeClient + Send + 'static,
+    F: Fn(&str) -> Result<SyncBufferedHttpRangeClient<T>> + Send + Sync + 'static,
+{
+    /// VFS creating clients for opened URLs with `open`.
+    pub fn new(name: &str, open: F) -> Self {
+        SqliteVfs {
+            name: name.to_string(),
+            open,
+            cache_pages: DEFAULT_CACHE_PAGES,
+            max_readahead_pages: DEFAULT_MAX_READAHEAD_PAGES,
+        }
+    }
+
+    /// Maximal number of cached pages per file (Default: 1024)
+    pub fn with_cache_pages(mut self, pages: usize) -> Self {
+        self.cache_pages = pages;
+        self
+    }
+
+    /// Maximal number of pages read ahead for sequential reads (Default: 64)
+    pub fn with_max_readahead_pages(mut self, pages: usize) -> Self {
+        self.max_readahead_pages = pages;
+        self
+    }
+
+    /// Register the VFS for the lifetime of the process.
+    pub fn register(self) -> Result<()> {
+        let name = CString::new(self.name.as_str())
+            .map_err(|_| HttpError::HttpError(format!("invalid VFS name {}", self.name)))?;
+        // SAFETY: registered VFS and their data are leaked and never freed
+        unsafe {
+            if !ffi::sqlite3_vfs_find(name.as_ptr()).is_null() {
+                return Err(HttpError::HttpError(format!(
+                    "VFS {} already registered",
+                    self.name
+                )));
+            }
+            let default = ffi::sqlite3_vfs_find(ptr::null());
+            if default.is_null() {
+                return Err(HttpError::HttpError("no default VFS found".to_string()));
+            }
+            let data = Box::new(VfsData {
+                open: self.open,
+                default,
+                io_methods: io_methods::<T>(),
+                cache_pages: self.cache_pages,
+                max_readahead_pages: self.max_readahead_pages,
+                last_error: Mutex::new(None),
+            });
+            let vfs = Box::new(ffi::sqlite3_vfs {
+                iVersion: 2,
+                szOsFile: max(
+                    std::mem::size_of::<HttpFile<T>>() as c_int,
+                    (*default).szOsFile,
+                ),
+                mxPathname: MAX_PATHNAME,
+                pNext: ptr::null_mut(),
+                zName: name.into_raw(),
+                pAppData: Box::into_raw(data) as *mut c_void,
+                xOpen: Some(x_open::<T, F>),
+                xDelete: Some(x_delete::<F>),
+                xAccess: Some(x_access),
+                xFullPathname: Some(x_full_pathname),
+                xDlOpen: None,
+                xDlError: None,
+                xDlSym: None,
+                xDlClose: None,
+                xRandomness: Some(x_randomness::<F>),
+                xSleep: Some(x_sleep::<F>),
+                xCurrentTime: Some(x_current_time::<F>),
+                xGetLastError: Some(x_get_last_error::<F>),
+                xCurrentTimeInt64: Some(x_current_time_int64::<F>),
+                xSetSystemCall: None,
+                xGetSystemCall: None,
+                xNextSystemCall: None,
+            });
+            match ffi::sqlite3_vfs_register(Box::into_raw(vfs), 0) {
+                ffi::SQLITE_OK => Ok(()),
+                rc => Err(HttpError::HttpError(format!(
+                    "registering VFS {} failed with code {rc}",
+                    self.name
+                ))),
+            }
+        }
+    }
+}
+
+/// Data of a registered VFS
+struct VfsData<F> {
+    open: F,
+    /// VFS handling temporary files
+    default: *mut ffi::sqlite3_vfs,
+    io_methods: ffi::sqlite3_io_methods,
+    cache_pages: usize,
+    max_readahead_pages: usize,
+    /// Message of the last failed open or read, reported by `xGetLastError`
+    last_error: Mutex<Option<String>>,
+}
+
+unsafe fn vfs_data<'a, F>(vfs: *mut ffi::sqlite3_vfs) -> &'a VfsData<F> {
+    &*((*vfs).pAppData as *const VfsData<F>)
+}
+
+fn set_last_error(last_error: &Mutex<Option<String>>, message: String) {
+    *last_error.lock().unwrap_or_else(|e| e.into_inner()) = Some(message);
+}
+
+/// Run a callback, returning `error_code` if it panics. Panics must not unwind into SQLite.
+fn catch_panic(
+    last_error: &Mutex<Option<String>>,
+    error_code: c_int,
+    callback: impl FnOnce() -> c_int,
+) -> c_int {
+    panic::catch_unwind(AssertUnwindSafe(callback)).unwrap_or_else(|payload| {
+        let message = payload
+            .downcast_ref::<&str>()
+            .map(|message| message.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_default();
+        set_last_error(last_error, format!("panic: {message}"));
+        error_code
+    })
+}
+
+#[repr(C)]
+struct HttpFile<T: SyncHttpRangeClient> {
+    base: ffi::sqlite3_file,
+    file: *mut PagedFile<T>,
+    /// Last error of the VFS, which is never freed
+    last_error: *const Mutex<Option<String>>,
+}
+
+unsafe fn paged_file<'a, T: SyncHttpRangeClient>(
+    file: *mut ffi::sqlite3_file,
+) -> &'a mut PagedFile<T> {
+    &mut *(*(file as *mut HttpFile<T>)).file
+}
+
+fn io_methods<T: SyncHttpRangeClient>() -> ffi::sqlite3_io_methods {
+    ffi::sqlite3_io_methods {
+        iVersion: 1,
+        xClose: Some(x_close::<T>),
+        xRead: Some(x_read::<T>),
+        xWrite: Some(x_write),
+        xTruncate: Some(x_truncate),
+        xSync: Some(x_sync),
+        xFileSize: Some(x_file_size::<T>),
+        xLock: Some(x_lock),
+        xUnlock: Some(x_lock),
+        xCheckReservedLock: Some(x_check_reserved_lock),
+        xFileControl: Some(x_file_control),
+        xSectorSize: Some(x_sector_size),
+        xDeviceCharacteristics: Some(x_device_characteristics),
+        xShmMap: None,
+        xShmLock: None,
+        xShmBarrier: None,
+        xShmUnmap: None,
+        xFetch: None,
+        xUnfetch: None,
+    }
+}
+
+unsafe extern "C" fn x_open<T, F>(
+    vfs: *mut ffi::sqlite3_vfs,
+    name: ffi::sqlite3_filename,
+    file: *mut ffi::sqlite3_file,
+    flags: c_int,
+    out_flags: *mut c_int,
+) -> c_int
+where
+    T: SyncHttpRangeClient,
+    F: Fn(&str) -> Result<SyncBufferedHttpRangeClient<T>>,
+{
+    let data = vfs_data::<F>(vfs);
+    if flags & ffi::SQLITE_OPEN_MAIN_DB == 0 {
+        return match (*data.default).xOpen {
+            Some(open) => open(data.default, name, file, flags, out_flags),
+            None => ffi::SQLITE_CANTOPEN,
+        };
+    }
+    (*file).pMethods = ptr::null();
+    let Some(url) = (!name.is_null())
+        .then(|| CStr::from_ptr(name).to_str().ok())
+        .flatten()
+    else {
+        return ffi::SQLITE_CANTOPEN;
+    };
+    catch_panic(&data.last_error, ffi::SQLITE_CANTOPEN, || {
+        let paged = match (data.open)(url)
+            .and_then(|client| PagedFile::open(client, data.cache_pages, data.max_readahead_pages))
+        {
+            Ok(paged) => paged,
+            Err(e) => {
+                set_last_error(&data.last_error, format!("opening {url} failed: {e}"));
+                return ffi::SQLITE_CANTOPEN;
+            }
+        };
+        ptr::write(
+            file as *mut HttpFile<T>,
+            HttpFile {
+                base: ffi::sqlite3_file {
+                    pMethods: &data.io_methods,
+                },
+                file: Box::into_raw(Box::new(paged)),
+                last_error: &data.last_error,
+            },
+        );
+        if !out_flags.is_null() {
+            *out_flags = ffi::SQLITE_OPEN_MAIN_DB | ffi::SQLITE_OPEN_READONLY;
+        }
+        ffi::SQLITE_OK
+    })
+}
+
+unsafe extern "C" fn x_delete<F>(
+    vfs: *mut ffi::sqlite3_vfs,
+    name: *const c_char,
+    sync_dir: c_int,
+) -> c_int {
+    let default = vfs_data::<F>(vfs).default;
+    match (*default).xDelete {
+        Some(delete) => delete(default, name, sync_dir),
+        None => ffi::SQLITE_IOERR_DELETE,
+    }
+}
+
+/// Journal and WAL files of remote databases never exist.
+unsafe extern "C" fn x_access(
+    _vfs: *mut ffi::sqlite3_vfs,
+    _name: *const c_char,
+    _flags: c_int,
+    res_out: *mut c_int,
+) -> c_int {
+    *res_out = 0;
+    ffi::SQLITE_OK
+}
+
+unsafe extern "C" fn x_full_pathname(
+    _vfs: *mut ffi::sqlite3_vfs,
+    name: *const c_char,
+    n_out: c_int,
+    out: *mut c_char,
+) -> c_int {
+    let name = CStr::from_ptr(name).to_bytes_with_nul();
+    if name.len() > n_out as usize {
+        return ffi::SQLITE_CANTOPEN;
+    }
+    ptr::copy_nonoverlapping(name.as_ptr() as *const c_char, out, name.len());
+    ffi::SQLITE_OK
+}
+
+unsafe extern "C" fn x_randomness<F>(
+    vfs: *mut ffi::sqlite3_vfs,
+    n_byte: c_int,
+    out: *mut c_char,
+) -> c_int {
+    let default = vfs_data::<F>(vfs).default;
+    match (*default).xRandomness {
+        Some(randomness) => randomness(default, n_byte, out),
+        None => 0,
+    }
+}
+
+unsafe extern "C" fn x_sleep<F>(vfs: *mut ffi::sqlite3_vfs, microseconds: c_int) -> c_int {
+    let default = vfs_data::<F>(vfs).default;
+    match (*default).xSleep {
+        Some(sleep) => sleep(default, microseconds),
+        None => 0,
+    }
+}
+
+unsafe extern "C" fn x_current_time<F>(vfs: *mut ffi::sqlite3_vfs, time: *mut f64) -> c_int {
+    let default = vfs_data::<F>(vfs).default;
+    match (*default).xCurrentTime {
+        Some(current_time) => current_time(default, time),
+        None => ffi::SQLITE_ERROR,
+    }
+}
+
+/// Message of the last failed open or read, or of the default VFS
+unsafe extern "C" fn x_get_last_error<F>(
+    vfs: *mut ffi::sqlite3_vfs,
+    n_byte: c_int,
+    out: *mut c_char,
+) -> c_int {
+    let data = vfs_data::<F>(vfs);
+    let last_error = data.last_error.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(message) = last_error.as_deref() {
+        if n_byte > 0 && !out.is_null() {
+            let len = min(message.len(), n_byte as usize - 1);
+            ptr::copy_nonoverlapping(message.as_ptr() as *const c_char, out, len);
+            *out.add(len) = 0;
+        }
+        return 0;
+    }
+    let default = data.default;
+    match (*default).xGetLastError {
+        Some(get_last_error) => get_last_error(default, n_byte, out),
+        None => 0,
+    }
+}
+
+unsafe extern "C" fn x_current_time_int64<F>(
+    vfs: *mut ffi::sqlite3_vfs,
+    time: *mut ffi::sqlite3_int64,
+) -> c_int {
+    let default = vfs_data::<F>(vfs).default;
+    match (*default).xCurrentTimeInt64 {
+        Some(current_time) if (*default).iVersion >= 2 => current_time(default, time),
+        _ => ffi::SQLITE_ERROR,
+    }
+}
+
+unsafe extern "C" fn x_close<T: SyncHttpRangeClient>(file: *mut ffi::sqlite3_file) -> c_int {
+    let file = &*(file as *mut HttpFile<T>);
+    catch_panic(&*file.last_error, ffi::SQLITE_IOERR_CLOSE, || {
+        drop(Box::from_raw(file.file));
+        ffi::SQLITE_OK
+    })
+}
+
+unsafe extern "C" fn x_read<T: SyncHttpRangeClient>(
+    file: *mut ffi::sqlite3_file,
+    buf: *mut c_void,
+    amount: c_int,
+    offset: ffi::sqlite3_int64,
+) -> c_int {
+    let buf = slice::from_raw_parts_mut(buf as *mut u8, amount as usize);
+    let last_error = &*(*(file as *mut HttpFile<T>)).last_error;
+    catch_panic(
+        last_error,
+        ffi::SQLITE_IOERR_READ,
+        || match paged_file::<T>(file).read(buf, offset as u64) {
+            Ok(len) if len == buf.len() => ffi::SQLITE_OK,
+            Ok(len) => {
+                buf[len..].fill(0);
+                ffi::SQLITE_IOERR_SHORT_READ
+            }
+            Err(e) => {
+                set_last_error(last_error, format!("read at offset {offset} failed: {e}"));
+                ffi::SQLITE_IOERR_READ
+            }
+        },
+    )
+}
+
+unsafe extern "C" fn x_write(
+    _file: *mut ffi::sqlite3_file,
+    _buf: *const c_void,
+    _amount: c_int,
+    _offset: ffi::sqlite3_int64,
+) -> c_int {
+    ffi::SQLITE_READONLY
+}
+
+unsafe extern "C" fn x_truncate(_file: *mut ffi::sqlite3_file, _size: ffi::sqlite3_int64) -> c_int {
+    ffi::SQLITE_READONLY
+}
+
+unsafe extern "C" fn x_sync(_file: *mut ffi::sqlite3_file, _flags: c_int) -> c_int {
+    ffi::SQLITE_OK
+}
+
+unsafe extern "C" fn x_file_size<T: SyncHttpRangeClient>(
+    file: *mut ffi::sqlite3_file,
+    size: *mut ffi::sqlite3_int64,
+) -> c_int {
+    *size = paged_file::<T>(file).length as ffi::sqlite3_int64;
+    ffi::SQLITE_OK
+}
+
+/// Locks are not needed for immutable files.
+unsafe extern "C" fn x_lock(_file: *mut ffi::sqlite3_file, _lock: c_int) -> c_int {
+    ffi::SQLITE_OK
+}
+
+unsafe extern "C" fn x_check_reserved_lock(
+    _file: *mut ffi::sqlite3_file,
+    res_out: *mut c_int,
+) -> c_int {
+    *res_out = 0;
+    ffi::SQLITE_OK
+}
+
+unsafe extern "C" fn x_file_control(
+    _file: *mut ffi::sqlite3_file,
+    _op: c_int,
+    _arg: *mut c_void,
+) -> c_int {
+    ffi::SQLITE_NOTFOUND
+}
+
+unsafe extern "C" fn x_sector_size(_file: *mut ffi::sqlite3_file) -> c_int {
+    0
+}
+
+unsafe extern "C" fn x_device_characteristics(_file: *mut ffi::sqlite3_file) -> c_int {
+    ffi::SQLITE_IOCAP_IMMUTABLE
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_client::MockHttpRangeClient;
+    use rusqlite::{Connection, OpenFlags};
+
+    /// Database with pages of 1 KiB and a table of 2000 rows
+    fn database() -> Vec<u8> {
+        let path =
+            std::env::temp_dir().join(format!("http-range-client-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "PRAGMA page_size = 1024;
+             CREATE TABLE items (id INTEGER PRIMARY KEY, name TEXT);
+             WITH RECURSIVE n(i) AS (SELECT 1 UNION ALL SELECT i + 1 FROM n WHERE i < 2000)
+             INSERT INTO items SELECT i, 'item ' || i FROM n;",
+        )
+        .unwrap();
+        drop(conn);
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        data
+    }
+
+    fn assert_page_aligned(requests: &[String], page_size: usize, length: usize) {
+        for request in requests {
+            let range = request.strip_prefix("GET bytes=").unwrap();
+            let (begin, end) = range.split_once('-').unwrap();
+            let (begin, end): (usize, usize) = (begin.parse().unwrap(), end.parse().unwrap());
+            assert_eq!(begin % page_size, 0, "{}", request);
+            assert!(
+                (end + 1) % page_size == 0 || end + 1 == length,
+                "{}",
+                request
+            );
+        }
+    }
+
+    #[test]
+    fn query() -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let data = database();
+        let length = data.len();
+        let backend = MockHttpRangeClient::new(data);
+        let mock = backend.clone();
+        SqliteVfs::new("mock-http", move |url: &str| {
+            Ok(SyncBufferedHttpRangeClient::with(mock.clone(), url))
+        })
+        .register()?;
+        let conn = Connection::open_with_flags_and_vfs(
+            "https://example.com/db.sqlite",
+            OpenFlags::SQLITE_OPEN_READ_ONLY,
+            "mock-http",
+        )?;
+        let name: String =
+            conn.query_row("SELECT name FROM items WHERE id = ?1", [1234], |row| {
+                row.get(0)
+            })?;
+        assert_eq!(name, "item 1234");
+        let requests = backend.requests().len();
+        let name: String =
+            conn.query_row("SELECT name FROM items WHERE id = ?1", [1234], |row| {
+                row.get(0)
+            })?;
+        assert_eq!(name, "item 1234");
+        assert_eq!(backend.requests().len(), requests);
+        let count: usize = conn.query_row("SELECT count(*) FROM items", [], |row| row.get(0))?;
+        assert_eq!(count, 2000);
+        assert!(backend.requests().len() < length / 1024 / 4);
+        assert_page_aligned(&backend.requests(), 1024, length);
+        assert!(conn.execute("DELETE FROM items", []).is_err());
+        assert!(SqliteVfs::new("mock-http", |url: &str| {
+            Ok(SyncBufferedHttpRangeClient::with(
+                MockHttpRangeClient::new(Vec::new()),
+                url,
+            ))
+        })
+        .register()
+        .is_err());
+        Ok(())
+    }
+
+    /// Message of `xGetLastError` of a registered VFS
+    fn last_error(vfs: &str) -> String {
+        let name = CString::new(vfs).unwrap();
+        let mut buf = [0 as c_char; 256];
+        unsafe {
+            let vfs = ffi::sqlite3_vfs_find(name.as_ptr());
+            (*vfs).xGetLastError.unwrap()(vfs, buf.len() as c_int, buf.as_mut_ptr());
+            CStr::from_ptr(buf.as_ptr()).to_str().unwrap().to_string()
+        }
+    }
+
+    #[test]
+    fn open_errors() -> std::result::Result<(), Box<dyn std::error::Error>> {
+        SqliteVfs::new("failing-http", |url: &str| {
+            if url.ends_with("panic.sqlite") {
+                panic!("open panicked");
+            }
+            Ok(SyncBufferedHttpRangeClient::with(
+                MockHttpRangeClient::new(Vec::new()),
+                url,
+            ))
+        })
+        .register()?;
+        let open = |url| {
+            Connection::open_with_flags_and_vfs(
+                url,
+                OpenFlags::SQLITE_OPEN_READ_ONLY,
+                "failing-http",
+            )
+            .and_then(|conn| conn.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(())))
+        };
+        assert!(open("https://example.com/empty.sqlite").is_err());
+        assert_eq!(
+            last_error("failing-http"),
+            "opening https://example.com/empty.sqlite failed: http status 416"
+        );
+        assert!(open("https://example.com/panic.sqlite").is_err());
+        assert_eq!(last_error("failing-http"), "panic: open panicked");
+        Ok(())
+    }
+
+    #[test]
+    fn readahead() -> Result<()> {
+        let backend = MockHttpRangeClient::with_len(40_000);
+        let client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        let mut file = PagedFile::open(client, 8, 4)?;
+        let mut buf = [0; 100];
+        for page in 0..6 {
+            assert_eq!(file.read(&mut buf, page * 4096 + 10)?, 100);
+        }
+        assert_eq!(buf[0], ((5 * 4096 + 10) % 251) as u8);
+        assert_eq!(file.read(&mut buf, 39_950)?, 50);
+        assert_eq!(
+            backend.requests(),
+            [
+                "GET bytes=0-4095",
+                "GET bytes=4096-20479",
+                "GET bytes=20480-36863",
+                "GET bytes=36864-39999"
+            ]
+        );
+        // page 1 was evicted
+        file.read(&mut buf, 4096)?;
+        assert_eq!(backend.requests().len(), 5);
+        Ok(())
+    }
+}
@@ -0,0 +1,451 @@
+//! Index of remote tar archives and access to their members.
+use crate::buffered_range_client::unexpected_end;
+use crate::error::{HttpError, Result};
+use crate::range_client::SyncHttpRangeClient;
+use crate::SyncBufferedHttpRangeClient;
+use std::io::{Read, Seek, SeekFrom};
+
+/// Size of tar headers and padding unit of member data
+const BLOCK_LEN: usize = 512;
+/// Maximal size of GNU long name and PAX extended header data
+const MAX_EXTENDED_HEADER_LEN: u64 = 1024 * 1024;
+
+fn invalid_header(offset: u64) -> HttpError {
+    HttpError::HttpError(format!("invalid tar header at offset {offset}"))
+}
+
+/// Zero terminated string of a header field
+fn c_string(field: &[u8]) -> String {
+    let end = field.iter().position(|b| *b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// Octal number or GNU base-256 number of a header field
+fn parse_number(field: &[u8], offset: u64) -> Result<u64> {
+    if field[0] & 0x80 != 0 {
+        // Negative numbers have the sign bit 0x40 set
+        if field[0] & 0x40 != 0 {
+            return Err(invalid_header(offset));
+        }
+        return field[1..]
+            .iter()
+            .try_fold(u64::from(field[0] & 0x3f), |n, byte| {
+                n.checked_mul(256).map(|n| n | u64::from(*byte))
+            })
+            .ok_or_else(|| invalid_header(offset));
+    }
+    let text = std::str::from_utf8(field).map_err(|_| invalid_header(offset))?;
+    let text = text.trim_matches(|c| c == ' ' || c == '\0');
+    if text.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(text, 8).map_err(|_| invalid_header(offset))
+}
+
+/// Verify the header checksum, computed with spaces in the checksum field.
+fn check_checksum(header: &[u8], offset: u64) -> Result<()> {
+    let sum: u64 = header
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| {
+            if (148..156).contains(&i) {
+                u64::from(b' ')
+            } else {
+                u64::from(*byte)
+            }
+        })
+        .sum();
+    if parse_number(&header[148..156], offset)? != sum {
+        return Err(invalid_header(offset));
+    }
+    Ok(())
+}
+
+/// Path of a header, including the ustar prefix
+fn header_path(header: &[u8]) -> String {
+    let name = c_string(&header[..100]);
+    if &header[257..265] == b"ustar\x0000" {
+        let prefix = c_string(&header[345..500]);
+        if !prefix.is_empty() {
+            return format!("{prefix}/{name}");
+        }
+    }
+    name
+}
+
+/// Values of a PAX extended header applying to the next entry
+#[derive(Default)]
+struct PaxHeader {
+    path: Option<String>,
+    size: Option<u64>,
+}
+
+impl PaxHeader {
+    /// Parse records of the form `<length> <key>=<value>\n`.
+    fn parse(mut data: &[u8], offset: u64) -> Result<Self> {
+        let mut pax = PaxHeader::default();
+        while !data.is_empty() && data[0] != 0 {
+            let space = data
+                .iter()
+                .position(|b| *b == b' ')
+                .ok_or_else(|| invalid_header(offset))?;
+            let len: usize = std::str::from_utf8(&data[..space])
+                .ok()
+                .and_then(|len| len.parse().ok())
+                .filter(|len| *len > space && *len <= data.len())
+                .ok_or_else(|| invalid_header(offset))?;
+            let record = String::from_utf8_lossy(&data[space + 1..len]);
+            if let Some((key, value)) = record.trim_end_matches('\n').split_once('=') {
+                match key {
+                    "path" => pax.path = Some(value.to_string()),
+                    "size" => pax.size = Some(value.parse().map_err(|_| invalid_header(offset))?),
+                    _ => {}
+                }
+            }
+            data = &data[len..];
+        }
+        Ok(pax)
+    }
+}
+
+/// Member of a tar archive
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TarEntry {
+    pub path: String,
+    pub size: u64,
+    /// Offset of the member data in the archive
+    pub data_offset: u64,
+    /// Type flag of the header (`b'0'`: file, `b'5'`: directory, ...)
+    pub entry_type: u8,
+}
+
+impl TarEntry {
+    pub fn is_file(&self) -> bool {
+        matches!(self.entry_type, b'0' | 0 | b'7')
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.entry_type == b'5'
+    }
+}
+
+/// Entries of a tar archive with their data offsets
+#[derive(Clone, Debug, Default)]
+pub struct TarIndex {
+    entries: Vec<TarEntry>,
+}
+
+impl TarIndex {
+    /// Build an index by reading all headers, jumping over member data.
+    ///
+    /// Supports ustar, GNU long names and PAX paths and sizes.
+    pub fn build<T: SyncHttpRangeClient>(
+        client: &mut SyncBufferedHttpRangeClient<T>,
+    ) -> Result<Self> {
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        let mut long_name = None;
+        let mut pax = PaxHeader::default();
+        loop {
+            let header = match client.get_range(offset as usize, BLOCK_LEN) {
+                // Archive without end of archive blocks
+                Ok([]) | Err(HttpError::HttpStatus(416)) => break,
+                Ok(header) if header.len() < BLOCK_LEN => return Err(unexpected_end(BLOCK_LEN)),
+                Ok(header) => header.to_vec(),
+                Err(e) => return Err(e),
+            };
+            if header.iter().all(|b| *b == 0) {
+                break;
+            }
+            check_checksum(&header, offset)?;
+            let data_offset = offset + BLOCK_LEN as u64;
+            let mut size = parse_number(&header[124..136], offset)?;
+            if matches!(header[156], b'L' | b'x') && size > MAX_EXTENDED_HEADER_LEN {
+                return Err(invalid_header(offset));
+            }
+            match header[156] {
+                b'L' => {
+                    let data = client.get_range(data_offset as usize, size as usize)?;
+                    long_name = Some(c_string(data));
+                }
+                b'x' => {
+                    let data = client.get_range(data_offset as usize, size as usize)?;
+                    pax = PaxHeader::parse(data, offset)?;
+                }
+                // Global PAX headers and GNU long link names are not used
+                b'g' | b'K' => {}
+                entry_type => {
+                    size = pax.size.take().unwrap_or(size);
+                    let path = pax.path.take().or(long_name.take());
+                    entries.push(TarEntry {
+                        path: path.unwrap_or_else(|| header_path(&header)),
+                        size,
+                        data_offset,
+                        entry_type,
+                    });
+                }
+            }
+            offset = size
+                .checked_next_multiple_of(BLOCK_LEN as u64)
+                .and_then(|padded| data_offset.checked_add(padded))
+                .ok_or_else(|| invalid_header(offset))?;
+        }
+        Ok(TarIndex { entries })
+    }
+
+    pub fn entries(&self) -> &[TarEntry] {
+        &self.entries
+    }
+
+    /// Entry with the given path
+    pub fn entry(&self, path: &str) -> Option<&TarEntry> {
+        self.entries.iter().find(|entry| entry.path == path)
+    }
+}
+
+/// Tar archive with an index built on first access
+pub struct SyncTarArchive<T: SyncHttpRangeClient> {
+    client: SyncBufferedHttpRangeClient<T>,
+    index: Option<TarIndex>,
+}
+
+impl<T: SyncHttpRangeClient> SyncTarArchive<T> {
+    pub fn new(client: SyncBufferedHttpRangeClient<T>) -> Self {
+        SyncTarArchive {
+            client,
+            index: None,
+        }
+    }
+
+    /// Use a previously built index.
+    pub fn with_index(client: SyncBufferedHttpRangeClient<T>, index: TarIndex) -> Self {
+        SyncTarArchive {
+            client,
+            index: Some(index),
+        }
+    }
+
+    /// Index of the archive, built with the first call
+    pub fn index(&mut self) -> Result<&TarIndex> {
+        let index = match self.index.take() {
+            Some(index) => index,
+            None => TarIndex::build(&mut self.client)?,
+        };
+        Ok(self.index.insert(index))
+    }
+
+    /// Reader of the data of an entry.
+    pub fn reader(&mut self, entry: &TarEntry) -> TarEntryReader<'_, T> {
+        TarEntryReader {
+            client: &mut self.client,
+            data_offset: entry.data_offset,
+            size: entry.size,
+            pos: 0,
+        }
+    }
+
+    /// Reader of the data of the entry with the given path.
+    pub fn reader_by_path(&mut self, path: &str) -> Result<TarEntryReader<'_, T>> {
+        let entry = self
+            .index()?
+            .entry(path)
+            .cloned()
+            .ok_or_else(|| HttpError::HttpError(format!("no tar entry `{path}`")))?;
+        Ok(self.reader(&entry))
+    }
+}
+
+/// Reader of the data of a tar entry, limited to its size
+pub struct TarEntryReader<'a, T: SyncHttpRangeClient> {
+    client: &'a mut SyncBufferedHttpRangeClient<T>,
+    data_offset: u64,
+    size: u64,
+    /// Position within the entry
+    pos: u64,
+}
+
+impl<T: SyncHttpRangeClient> Read for TarEntryReader<'_, T> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let length = (buf.len() as u64).min(self.size.saturating_sub(self.pos)) as usize;
+        if length == 0 {
+            return Ok(0);
+        }
+        let data = self
+            .client
+            .get_range((self.data_offset + self.pos) as usize, length)
+            .map_err(|e| std::io::Error::other(e.to_string()))?;
+        if data.is_empty() {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        buf[..data.len()].copy_from_slice(data);
+        self.pos += data.len() as u64;
+        Ok(data.len())
+    }
+}
+
+impl<T: SyncHttpRangeClient> Seek for TarEntryReader<'_, T> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(pos) => Some(pos),
+            SeekFrom::End(delta) => self.size.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.pos.checked_add_signed(delta),
+        };
+        self.pos = pos.ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "invalid seek to a negative position",
+            )
+        })?;
+        Ok(self.pos)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_client::MockHttpRangeClient;
+    use ::tar::{Builder, EntryType, Header};
+
+    fn test_data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 239) as u8).collect()
+    }
+
+    fn append(builder: &mut Builder<Vec<u8>>, path: &str, data: &[u8]) {
+        let mut header = Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o644);
+        builder.append_data(&mut header, path, data).unwrap();
+    }
+
+    fn archive() -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        append(&mut builder, "small.txt", b"hello tar");
+        let mut header = Header::new_ustar();
+        header.set_entry_type(EntryType::Directory);
+        header.set_mode(0o755);
+        header.set_size(0);
+        builder.append_data(&mut header, "data/", &[][..]).unwrap();
+        append(&mut builder, "data/large.bin", &test_data(300_000));
+        let long_path = format!("data/{}/file.txt", "nested".repeat(20));
+        append(&mut builder, &long_path, b"long name");
+        builder
+            .append_pax_extensions([("path", &b"data/pax-name.txt"[..])])
+            .unwrap();
+        append(&mut builder, "short.txt", b"pax name");
+        builder.into_inner().unwrap()
+    }
+
+    /// Total number of bytes requested
+    fn requested_bytes(backend: &MockHttpRangeClient) -> usize {
+        backend
+            .requests()
+            .iter()
+            .filter_map(|request| request.strip_prefix("GET bytes="))
+            .filter_map(|range| range.split_once('-'))
+            .map(|(begin, end)| end.parse::<usize>().unwrap() + 1 - begin.parse::<usize>().unwrap())
+            .sum()
+    }
+
+    #[test]
+    fn build_index() -> Result<()> {
+        let backend = MockHttpRangeClient::new(archive());
+        let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        client.min_req_size(BLOCK_LEN);
+        let index = TarIndex::build(&mut client)?;
+        let paths: Vec<_> = index.entries().iter().map(|e| e.path.as_str()).collect();
+        let long_path = format!("data/{}/file.txt", "nested".repeat(20));
+        assert_eq!(
+            paths,
+            [
+                "small.txt",
+                "data/",
+                "data/large.bin",
+                &long_path,
+                "data/pax-name.txt"
+            ]
+        );
+        assert!(index.entries()[1].is_dir());
+        let large = index.entry("data/large.bin").unwrap();
+        assert!(large.is_file());
+        assert_eq!(large.size, 300_000);
+        // Member data is skipped
+        assert!(requested_bytes(&backend) <= 10 * BLOCK_LEN);
+        Ok(())
+    }
+
+    /// Archive with a single header and end of archive blocks
+    fn single_header(header: &mut Header) -> Vec<u8> {
+        header.set_cksum();
+        let mut archive = header.as_bytes().to_vec();
+        archive.extend([0; 2 * BLOCK_LEN]);
+        archive
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse_number(b"0000644\0", 0).unwrap(), 0o644);
+        let mut field = [0; 12];
+        field[0] = 0x80;
+        field[10] = 1;
+        assert_eq!(parse_number(&field, 0).unwrap(), 256);
+        // Exceeding 64 bits
+        field[3] = 1;
+        assert!(parse_number(&field, 0).is_err());
+        // Negative
+        assert!(parse_number(&[0xff; 12], 0).is_err());
+    }
+
+    #[test]
+    fn malformed_headers() -> Result<()> {
+        // GNU header with data in the fields used as ustar prefix
+        let mut header = Header::new_gnu();
+        header.set_path("file.txt").unwrap();
+        header.as_mut_bytes()[345..349].copy_from_slice(b"junk");
+        let backend = MockHttpRangeClient::new(single_header(&mut header));
+        let mut client = SyncBufferedHttpRangeClient::with(backend, "mock");
+        assert_eq!(TarIndex::build(&mut client)?.entries()[0].path, "file.txt");
+
+        let mut header = Header::new_gnu();
+        header.set_path("huge.bin").unwrap();
+        header.set_size(u64::MAX);
+        let backend = MockHttpRangeClient::new(single_header(&mut header));
+        let mut client = SyncBufferedHttpRangeClient::with(backend, "mock");
+        assert!(TarIndex::build(&mut client).is_err());
+
+        let mut header = Header::new_gnu();
+        header.set_entry_type(EntryType::GNULongName);
+        header.set_size(1 << 30);
+        let backend = MockHttpRangeClient::new(single_header(&mut header));
+        let mut client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        assert!(TarIndex::build(&mut client).is_err());
+        assert_eq!(backend.requests().len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn read_entry() -> std::io::Result<()> {
+        let backend = MockHttpRangeClient::new(archive());
+        let client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        let mut archive = SyncTarArchive::new(client);
+        let mut text = String::new();
+        archive
+            .reader_by_path("data/pax-name.txt")
+            .unwrap()
+            .read_to_string(&mut text)?;
+        assert_eq!(text, "pax name");
+
+        let requests = backend.requests().len();
+        let mut reader = archive.reader_by_path("data/large.bin").unwrap();
+        let mut buf = vec![0; 1000];
+        reader.seek(SeekFrom::Start(150_000))?;
+        reader.read_exact(&mut buf)?;
+        assert_eq!(buf, test_data(300_000)[150_000..151_000]);
+        let mut rest = Vec::new();
+        reader.seek(SeekFrom::End(-100))?;
+        reader.read_to_end(&mut rest)?;
+        assert_eq!(rest, test_data(300_000)[299_900..]);
+        // Cached index
+        assert_eq!(backend.requests().len(), requests + 2);
+        Ok(())
+    }
+}
@@ -0,0 +1,239 @@
+use crate::error::{HttpError, Result};
+use crate::range_client::{header_value, AsyncHttpRangeClient, RangeResponse};
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures_util::future::poll_fn;
+use http::header::{CONTENT_RANGE, RANGE};
+use http_body_util::BodyExt;
+use std::marker::PhantomData;
+use tower_service::Service;
+
+/// Boxed error type of tower services
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// HTTP Range client adapter for a [tower](https://docs.rs/tower) HTTP service
+///
+/// The service is cloned for every request. `B` is the request body type, which
+/// is constructed with `B::default()`.
+///
+/// Like all [AsyncHttpRangeClient]s, the service must be `Sync`. Boxed services
+/// can use `tower::util::BoxCloneSyncService` instead of `BoxCloneService`.
+pub struct TowerHttpRangeClient<S, B = http_body_util::Empty<Bytes>> {
+    service: S,
+    _body: PhantomData<fn() -> B>,
+}
+
+impl<S: Clone, B> Clone for TowerHttpRangeClient<S, B> {
+    fn clone(&self) -> Self {
+        TowerHttpRangeClient {
+            service: self.service.clone(),
+            _body: PhantomData,
+        }
+    }
+}
+
+impl<S, B> TowerHttpRangeClient<S, B> {
+    pub fn new(service: S) -> Self {
+        TowerHttpRangeClient {
+            service,
+            _body: PhantomData,
+        }
+    }
+}
+
+impl<S, B, RB> TowerHttpRangeClient<S, B>
+where
+    S: Service<http::Request<B>, Response = http::Response<RB>> + Clone,
+    S::Error: Into<BoxError>,
+    B: Default,
+    RB: http_body::Body,
+    RB::Error: Into<BoxError>,
+{
+    async fn send(
+        &self,
+        method: http::Method,
+        url: &str,
+        range: Option<&str>,
+        headers: &[(String, String)],
+    ) -> Result<http::Response<RB>> {
+        let mut builder = http::Request::builder().method(method).uri(url);
+        if let Some(range) = range {
+            builder = builder.header(RANGE, range);
+        }
+        for (name, value) in headers {
+            builder = builder.header(name, value);
+        }
+        let request = builder
+            .body(B::default())
+            .map_err(|e| HttpError::HttpError(e.to_string()))?;
+        let mut service = self.service.clone();
+        poll_fn(|cx| service.poll_ready(cx))
+            .await
+            .map_err(box_error)?;
+        service.call(request).await.map_err(box_error)
+    }
+}
+
+fn box_error(error: impl Into<BoxError>) -> HttpError {
+    HttpError::TransportError(error.into().to_string())
+}
+
+#[async_trait]
+impl<S, B, RB> AsyncHttpRangeClient for TowerHttpRangeClient<S, B>
+where
+    S: Service<http::Request<B>, Response = http::Response<RB>> + Clone + Send + Sync,
+    S::Future: Send,
+    S::Error: Into<BoxError>,
+    B: Default + Send,
+    RB: http_body::Body + Send,
+    RB::Data: Send,
+    RB::Error: Into<BoxError>,
+{
+    async fn get_range(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<Bytes> {
+        self.get_range_response(url, range, headers)
+            .await
+            .map(|response| response.data)
+    }
+    async fn get_range_response(
+        &self,
+        url: &str,
+        range: &str,
+        headers: &[(String, String)],
+    ) -> Result<RangeResponse> {
+        let response = self
+            .send(http::Method::GET, url, Some(range), headers)
+            .await?;
+        if !response.status().is_success() {
+            return Err(HttpError::HttpStatus(response.status().as_u16()));
+        }
+        let content_range = header_value(response.headers(), CONTENT_RANGE.as_str())?;
+        let data = response
+            .into_body()
+            .collect()
+            .await
+            .map_err(box_error)?
+            .to_bytes();
+        Ok(RangeResponse::new(data).with_content_range(content_range))
+    }
+    async fn head_response_header(
+        &self,
+        url: &str,
+        header: &str,
+        headers: &[(String, String)],
+    ) -> Result<Option<String>> {
+        let mut values = self.head_response_headers(url, &[header], headers).await?;
+        Ok(values.pop().flatten())
+    }
+    async fn head_response_headers(
+        &self,
+        url: &str,
+        names: &[&str],
+        headers: &[(String, String)],
+    ) -> Result<Vec<Option<String>>> {
+        let response = self.send(http::Method::HEAD, url, None, headers).await?;
+        if !response.status().is_success() {
+            return Err(HttpError::HttpStatus(response.status().as_u16()));
+        }
+        names
+            .iter()
+            .map(|name| header_value(response.headers(), name))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::AsyncBufferedHttpRangeClient;
+    use http::header::CONTENT_LENGTH;
+    use http_body_util::{Empty, Full};
+    use tower::util::BoxCloneSyncService;
+    use tower::{service_fn, ServiceBuilder, ServiceExt};
+
+    /// In-process service serving Range requests
+    async fn serve(
+        request: http::Request<Empty<Bytes>>,
+    ) -> std::result::Result<http::Response<Full<Bytes>>, HttpError> {
+        let data: Vec<u8> = (0..=255).collect();
+        let status = if request.headers().contains_key("x-api-key") {
+            200
+        } else {
+            401
+        };
+        if request.method() == http::Method::HEAD {
+            let response = http::Response::builder()
+                .status(status)
+                .header(CONTENT_LENGTH, data.len())
+                .body(Full::default())
+                .unwrap();
+            return Ok(response);
+        }
+        let range = request.headers().get(RANGE).unwrap().to_str().unwrap();
+        let (begin, end) = range
+            .strip_prefix("bytes=")
+            .and_then(|r| r.split_once('-'))
+            .unwrap();
+        let begin: usize = begin.parse().unwrap();
+        let end = (end.parse::<usize>().unwrap() + 1).min(data.len());
+        let response = http::Response::builder()
+            .status(status)
+            .header(CONTENT_RANGE, format!("bytes {begin}-{}/256", end - 1))
+            .body(Full::new(Bytes::copy_from_slice(&data[begin..end])))
+            .unwrap();
+        Ok(response)
+    }
+
+    #[tokio::test]
+    async fn tower_service() -> Result<()> {
+        let service = ServiceBuilder::new()
+            .map_request(|mut request: http::Request<Empty<Bytes>>| {
+                request
+                    .headers_mut()
+                    .insert("x-api-key", "secret".parse().unwrap());
+                request
+            })
+            .service(service_fn(serve));
+        let mut client = AsyncBufferedHttpRangeClient::with(
+            TowerHttpRangeClient::new(service),
+            "http://in-process/",
+        );
+        let bytes = client.min_req_size(16).get_range(10, 4).await?;
+        assert_eq!(bytes, [10, 11, 12, 13]);
+        assert_eq!(client.content_length(), Some(256));
+
+        let mut client = AsyncBufferedHttpRangeClient::with(
+            TowerHttpRangeClient::new(service_fn(serve)),
+            "http://in-process/",
+        );
+        let result = client.get_range(0, 4).await;
+        assert_eq!(result.unwrap_err().to_string(), "http status 401");
+        let result = client.head_response_header("content-length").await;
+        assert_eq!(result.unwrap_err().to_string(), "http status 401");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn boxed_service() -> Result<()> {
+        let service = BoxCloneSyncService::new(service_fn(serve).map_request(
+            |mut request: http::Request<Empty<Bytes>>| {
+                request
+                    .headers_mut()
+                    .insert("x-api-key", "secret".parse().unwrap());
+                request
+            },
+        ));
+        let mut client = AsyncBufferedHttpRangeClient::with(
+            TowerHttpRangeClient::new(service),
+            "http://in-process/",
+        );
+        let bytes = client.get_range(250, 6).await?;
+        assert_eq!(bytes, [250, 251, 252, 253, 254, 255]);
+        assert_eq!(client.get_content_length().await?, Some(256));
+        Ok(())
+    }
+}
@@ -1,27 +1,66 @@
 use crate::error::{HttpError, Result};
 use bytes::Bytes;
 use std::io::Read;
-use std::iter::FromIterator;
-use std::time::Duration;
 
 #[cfg(feature = "ureq-sync")]
 pub(crate) mod sync {
     use super::*;
-    use crate::range_client::SyncHttpRangeClient;
+    use crate::range_client::{RangeResponse, SyncHttpRangeClient};
 
     impl SyncHttpRangeClient for ureq::Agent {
-        fn get_range(&self, url: &str, range: &str) -> Result<Bytes> {
-            let response = self.get(url).set("Range", range).call()?;
+        fn get_range(&self, url: &str, range: &str, headers: &[(String, String)]) -> Result<Bytes> {
+            self.get_range_response(url, range, headers)
+                .map(|response| response.data)
+        }
+        fn get_range_response(
+            &self,
+            url: &str,
+            range: &str,
+            headers: &[(String, String)],
+        ) -> Result<RangeResponse> {
+            let mut request = self.get(url).set("Range", range);
+            for (name, value) in headers {
+                request = request.set(name, value);
+            }
+            let response = request.call()?;
             if response.status() < 200 || response.status() > 299 {
                 return Err(HttpError::HttpStatus(response.status()));
             }
-            // TODO: return error instead of dropping remaining bytes
-            let bytes = response.into_reader().bytes().map_while(|val| val.ok());
-            Ok(Bytes::from_iter(bytes))
+            let content_range = response.header("content-range").map(|val| val.to_string());
+            let final_url = response.get_url().to_string();
+            let mut data = Vec::new();
+            response
+                .into_reader()
+                .read_to_end(&mut data)
+                .map_err(|e| HttpError::TransportError(e.to_string()))?;
+            Ok(RangeResponse::new(Bytes::from(data))
+                .with_content_range(content_range)
+                .with_url(Some(final_url)))
         }
-        fn head_response_header(&self, url: &str, header: &str) -> Result<Option<String>> {
-            let response = self.head(url).call()?;
-            Ok(response.header(header).map(|val| val.to_string()))
+        fn head_response_header(
+            &self,
+            url: &str,
+            header: &str,
+            headers: &[(String, String)],
+        ) -> Result<Option<String>> {
+            let mut values = self.head_response_headers(url, &[header], headers)?;
+            Ok(values.pop().flatten())
+        }
+        fn head_response_headers(
+            &self,
+            url: &str,
+            names: &[&str],
+            headers: &[(String, String)],
+        ) -> Result<Vec<Option<String>>> {
+            let mut request = self.head(url);
+            for (name, value) in headers {
+                request = request.set(name, value);
+            }
+            let response = request.call()?;
+            Ok(names
+                .iter()
+                .map(|name| response.header(name).map(|val| val.to_string()))
+                .collect())
         }
     }
 
@@ -29,12 +68,19 @@ pub(crate) mod sync {
     pub type UreqHttpReader = crate::SyncBufferedHttpRangeClient<ureq::Agent>;
 
     impl UreqHttpReader {
+        /// Create a client with the default timeouts of [HttpRangeClientBuilder](crate::HttpRangeClientBuilder).
+        pub fn new(url: &str) -> Self {
+            Self::with(crate::HttpRangeClientBuilder::new().ureq_agent(), url)
+        }
+    }
+
+    /// Sync HTTP client for concurrent HTTP Range requests with a shared block cache.
+    pub type SharedUreqHttpReader = crate::SyncSharedHttpRangeClient<ureq::Agent>;
+
+    impl SharedUreqHttpReader {
+        /// Create a client with the default timeouts of [HttpRangeClientBuilder](crate::HttpRangeClientBuilder).
         pub fn new(url: &str) -> Self {
-            let agent = ureq::AgentBuilder::new()
-                .timeout_read(Duration::from_secs(5))
-                .timeout_write(Duration::from_secs(5))
-                .build();
-            Self::with(agent, url)
+            Self::with(crate::HttpRangeClientBuilder::new().ureq_agent(), url)
         }
     }
 }
@@ -44,7 +90,7 @@ impl From<ureq::Error> for HttpError {
         use ureq::Error::*;
         match error {
             Status(status, _resp) => HttpError::HttpStatus(status),
-            Transport(e) => HttpError::HttpError(e.to_string()),
+            Transport(e) => HttpError::TransportError(e.to_string()),
         }
     }
 }
@@ -0,0 +1,565 @@
+//! Random access to members of remote ZIP archives.
+use crate::buffered_range_client::unexpected_end;
+use crate::error::{HttpError, Result};
+use crate::range_client::{AsyncHttpRangeClient, SyncHttpRangeClient};
+use crate::{AsyncBufferedHttpRangeClient, SyncBufferedHttpRangeClient};
+use bytes::Bytes;
+use flate2::bufread::DeflateDecoder;
+use std::convert::TryInto;
+use std::io::{BufRead, Cursor, Read, Seek, SeekFrom, Take};
+
+const EOCD_SIGNATURE: u32 = 0x0605_4b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+const ZIP64_EOCD_SIGNATURE: u32 = 0x0606_4b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const EOCD_LEN: usize = 22;
+const ZIP64_LOCATOR_LEN: usize = 20;
+const ZIP64_EOCD_LEN: usize = 56;
+const CENTRAL_HEADER_LEN: usize = 46;
+const LOCAL_HEADER_LEN: usize = 30;
+/// Suffix containing the end of central directory record with a comment of maximal
+/// length and the preceding ZIP64 locator
+const TAIL_LEN: usize = ZIP64_LOCATOR_LEN + EOCD_LEN + u16::MAX as usize;
+/// Header ID of the ZIP64 extended information extra field
+const ZIP64_EXTRA_ID: u16 = 1;
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+
+fn u16_le(data: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes(data[pos..pos + 2].try_into().expect("2 byte slice"))
+}
+
+fn u32_le(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().expect("4 byte slice"))
+}
+
+fn u64_le(data: &[u8], pos: usize) -> u64 {
+    u64::from_le_bytes(data[pos..pos + 8].try_into().expect("8 byte slice"))
+}
+
+fn invalid(reason: &str) -> HttpError {
+    HttpError::HttpError(format!("invalid ZIP archive: {reason}"))
+}
+
+/// Member of a ZIP archive from its central directory entry
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ZipEntry {
+    pub name: String,
+    /// Compression method (0: stored, 8: deflate)
+    pub compression_method: u16,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    pub crc32: u32,
+    pub local_header_offset: u64,
+    flags: u16,
+}
+
+impl ZipEntry {
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        self.flags & 1 != 0
+    }
+
+    /// Replace saturated fields with the values of a ZIP64 extra field
+    fn apply_zip64_extra(&mut self, mut extra: &[u8]) -> Result<()> {
+        while extra.len() >= 4 {
+            let (id, len) = (u16_le(extra, 0), u16_le(extra, 2) as usize);
+            let data = extra
+                .get(4..4 + len)
+                .ok_or_else(|| unexpected_end(4 + len))?;
+            if id == ZIP64_EXTRA_ID {
+                let mut values = data.chunks_exact(8).map(|value| u64_le(value, 0));
+                for field in [
+                    &mut self.uncompressed_size,
+                    &mut self.compressed_size,
+                    &mut self.local_header_offset,
+                ] {
+                    if *field == u64::from(u32::MAX) {
+                        *field = values
+                            .next()
+                            .ok_or_else(|| invalid("incomplete ZIP64 extra field"))?;
+                    }
+                }
+            }
+            extra = &extra[4 + len..];
+        }
+        Ok(())
+    }
+}
+
+/// Location of the central directory
+struct CentralDirectory {
+    offset: u64,
+    size: u64,
+    entries: u64,
+}
+
+enum EndOfCentralDirectory {
+    Directory(CentralDirectory),
+    /// Offset of the ZIP64 end of central directory record
+    Zip64(u64),
+}
+
+/// Find the end of central directory record in the last bytes of the archive.
+fn parse_eocd(tail: &[u8]) -> Result<EndOfCentralDirectory> {
+    if tail.len() < EOCD_LEN {
+        return Err(unexpected_end(EOCD_LEN));
+    }
+    let pos = (0..=tail.len() - EOCD_LEN)
+        .rev()
+        .find(|&pos| {
+            u32_le(tail, pos) == EOCD_SIGNATURE
+                && pos + EOCD_LEN + u16_le(tail, pos + 20) as usize <= tail.len()
+        })
+        .ok_or_else(|| invalid("no end of central directory record found"))?;
+    let eocd = &tail[pos..];
+    if u16_le(eocd, 4) != 0 || u16_le(eocd, 6) != 0 {
+        return Err(invalid("multi-disk archives are not supported"));
+    }
+    let (entries, size, offset) = (u16_le(eocd, 10), u32_le(eocd, 12), u32_le(eocd, 16));
+    if entries == u16::MAX || size == u32::MAX || offset == u32::MAX {
+        let locator = pos
+            .checked_sub(ZIP64_LOCATOR_LEN)
+            .map(|begin| &tail[begin..pos])
+            .filter(|locator| u32_le(locator, 0) == ZIP64_LOCATOR_SIGNATURE)
+            .ok_or_else(|| invalid("no ZIP64 end of central directory locator found"))?;
+        return Ok(EndOfCentralDirectory::Zip64(u64_le(locator, 8)));
+    }
+    Ok(EndOfCentralDirectory::Directory(CentralDirectory {
+        offset: offset.into(),
+        size: size.into(),
+        entries: entries.into(),
+    }))
+}
+
+fn parse_zip64_eocd(record: &[u8]) -> Result<CentralDirectory> {
+    if record.len() < ZIP64_EOCD_LEN {
+        return Err(unexpected_end(ZIP64_EOCD_LEN));
+    }
+    if u32_le(record, 0) != ZIP64_EOCD_SIGNATURE {
+        return Err(invalid("no ZIP64 end of central directory record found"));
+    }
+    Ok(CentralDirectory {
+        entries: u64_le(record, 32),
+        size: u64_le(record, 40),
+        offset: u64_le(record, 48),
+    })
+}
+
+fn parse_central_directory(data: &[u8], count: u64) -> Result<Vec<ZipEntry>> {
+    let mut entries = Vec::with_capacity(count.min(u16::MAX.into()) as usize);
+    let mut pos = 0;
+    for _ in 0..count {
+        let header = data
+            .get(pos..pos + CENTRAL_HEADER_LEN)
+            .ok_or_else(|| unexpected_end(CENTRAL_HEADER_LEN))?;
+        if u32_le(header, 0) != CENTRAL_HEADER_SIGNATURE {
+            return Err(invalid("invalid central directory entry"));
+        }
+        let name_end = pos + CENTRAL_HEADER_LEN + u16_le(header, 28) as usize;
+        let extra_end = name_end + u16_le(header, 30) as usize;
+        if data.len() < extra_end {
+            return Err(unexpected_end(extra_end - pos));
+        }
+        let mut entry = ZipEntry {
+            name: String::from_utf8_lossy(&data[pos + CENTRAL_HEADER_LEN..name_end]).into_owned(),
+            compression_method: u16_le(header, 10),
+            compressed_size: u32_le(header, 20).into(),
+            uncompressed_size: u32_le(header, 24).into(),
+            crc32: u32_le(header, 16),
+            local_header_offset: u32_le(header, 42).into(),
+            flags: u16_le(header, 8),
+        };
+        entry.apply_zip64_extra(&data[name_end..extra_end])?;
+        entries.push(entry);
+        // Skip the file comment
+        pos = extra_end + u16_le(header, 32) as usize;
+    }
+    Ok(entries)
+}
+
+/// Offset of the entry data following its local header
+fn data_offset(local_header: &[u8], entry: &ZipEntry) -> Result<u64> {
+    if local_header.len() < LOCAL_HEADER_LEN {
+        return Err(unexpected_end(LOCAL_HEADER_LEN));
+    }
+    if u32_le(local_header, 0) != LOCAL_HEADER_SIGNATURE {
+        return Err(invalid(&format!("no local header for `{}`", entry.name)));
+    }
+    let header_len =
+        LOCAL_HEADER_LEN + u16_le(local_header, 26) as usize + u16_le(local_header, 28) as usize;
+    Ok(entry.local_header_offset + header_len as u64)
+}
+
+fn find_entry(entries: &[ZipEntry], name: &str) -> Result<usize> {
+    entries
+        .iter()
+        .position(|entry| entry.name == name)
+        .ok_or_else(|| HttpError::HttpError(format!("no ZIP entry named `{name}`")))
+}
+
+fn entry_at(entries: &[ZipEntry], index: usize) -> Result<&ZipEntry> {
+    entries
+        .get(index)
+        .ok_or_else(|| HttpError::HttpError(format!("no ZIP entry with index {index}")))
+}
+
+enum Decoder<R: BufRead> {
+    Stored(R),
+    Deflated(DeflateDecoder<R>),
+}
+
+/// Reader of the uncompressed data of a ZIP entry
+///
+/// The CRC32 and size of the data are checked when reaching the end.
+pub struct ZipEntryReader<R: BufRead> {
+    decoder: Decoder<R>,
+    name: String,
+    crc32: u32,
+    uncompressed_size: u64,
+    crc: flate2::Crc,
+    read: u64,
+}
+
+impl<R: BufRead> ZipEntryReader<R> {
+    fn new(entry: &ZipEntry, data: R) -> Result<Self> {
+        if entry.is_encrypted() {
+            return Err(HttpError::HttpError(format!(
+                "encrypted ZIP entry `{}` is not supported",
+                entry.name
+            )));
+        }
+        let decoder = match entry.compression_method {
+            METHOD_STORED => Decoder::Stored(data),
+            METHOD_DEFLATED => Decoder::Deflated(DeflateDecoder::new(data)),
+            method => {
+                return Err(HttpError::HttpError(format!(
+                    "compression method {method} of ZIP entry `{}` is not supported",
+                    entry.name
+                )))
+            }
+        };
+        Ok(ZipEntryReader {
+            decoder,
+            name: entry.name.clone(),
+            crc32: entry.crc32,
+            uncompressed_size: entry.uncompressed_size,
+            crc: flate2::Crc::new(),
+            read: 0,
+        })
+    }
+}
+
+impl<R: BufRead> Read for ZipEntryReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = match &mut self.decoder {
+            Decoder::Stored(data) => data.read(buf)?,
+            Decoder::Deflated(decoder) => decoder.read(buf)?,
+        };
+        self.crc.update(&buf[..n]);
+        self.read += n as u64;
+        let complete = n == 0 && !buf.is_empty();
+        if complete && (self.read != self.uncompressed_size || self.crc.sum() != self.crc32) {
+            let error = HttpError::IntegrityError(format!(
+                "ZIP entry `{}` has {} bytes with CRC32 {:08x}, expected {} bytes with CRC32 {:08x}",
+                self.name,
+                self.read,
+                self.crc.sum(),
+                self.uncompressed_size,
+                self.crc32
+            ));
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                error.to_string(),
+            ));
+        }
+        Ok(n)
+    }
+}
+
+/// ZIP archive read with a few range requests
+///
+/// Opening reads the end of central directory record with a suffix request
+/// and the central directory. Entries are streamed from their local header.
+pub struct SyncZipArchive<T: SyncHttpRangeClient> {
+    client: SyncBufferedHttpRangeClient<T>,
+    entries: Vec<ZipEntry>,
+}
+
+impl<T: SyncHttpRangeClient> SyncZipArchive<T> {
+    /// Open an archive by reading its central directory.
+    pub fn open(mut client: SyncBufferedHttpRangeClient<T>) -> Result<Self> {
+        let directory = match parse_eocd(client.get_suffix(TAIL_LEN)?)? {
+            EndOfCentralDirectory::Directory(directory) => directory,
+            EndOfCentralDirectory::Zip64(offset) => {
+                parse_zip64_eocd(client.get_range(offset as usize, ZIP64_EOCD_LEN)?)?
+            }
+        };
+        let entries = if directory.size > 0 {
+            let data = client.get_range(directory.offset as usize, directory.size as usize)?;
+            parse_central_directory(data, directory.entries)?
+        } else {
+            Vec::new()
+        };
+        Ok(SyncZipArchive { client, entries })
+    }
+
+    /// Entries of the central directory
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    /// Reader of the entry with the given index.
+    pub fn by_index(
+        &mut self,
+        index: usize,
+    ) -> Result<ZipEntryReader<Take<&mut SyncBufferedHttpRangeClient<T>>>> {
+        let entry = entry_at(&self.entries, index)?;
+        let header = self
+            .client
+            .get_range(entry.local_header_offset as usize, LOCAL_HEADER_LEN)?;
+        let offset = data_offset(header, entry)?;
+        self.client
+            .seek(SeekFrom::Start(offset))
+            .map_err(|e| HttpError::HttpError(e.to_string()))?;
+        let data = (&mut self.client).take(entry.compressed_size);
+        ZipEntryReader::new(entry, data)
+    }
+
+    /// Reader of the entry with the given name.
+    pub fn by_name(
+        &mut self,
+        name: &str,
+    ) -> Result<ZipEntryReader<Take<&mut SyncBufferedHttpRangeClient<T>>>> {
+        let index = find_entry(&self.entries, name)?;
+        self.by_index(index)
+    }
+}
+
+/// ZIP archive read with a few range requests
+///
+/// Opening reads the end of central directory record with a suffix request
+/// and the central directory. The compressed data of an entry is fetched
+/// with a single request and kept in memory while reading the entry.
+pub struct AsyncZipArchive<T: AsyncHttpRangeClient> {
+    client: AsyncBufferedHttpRangeClient<T>,
+    entries: Vec<ZipEntry>,
+}
+
+impl<T: AsyncHttpRangeClient> AsyncZipArchive<T> {
+    /// Open an archive by reading its central directory.
+    pub async fn open(mut client: AsyncBufferedHttpRangeClient<T>) -> Result<Self> {
+        let directory = match parse_eocd(client.get_suffix(TAIL_LEN).await?)? {
+            EndOfCentralDirectory::Directory(directory) => directory,
+            EndOfCentralDirectory::Zip64(offset) => {
+                parse_zip64_eocd(client.get_range(offset as usize, ZIP64_EOCD_LEN).await?)?
+            }
+        };
+        let entries = if directory.size > 0 {
+            let data = client
+                .get_range(directory.offset as usize, directory.size as usize)
+                .await?;
+            parse_central_directory(data, directory.entries)?
+        } else {
+            Vec::new()
+        };
+        Ok(AsyncZipArchive { client, entries })
+    }
+
+    /// Entries of the central directory
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+
+    /// Reader of the entry with the given index.
+    ///
+    /// The compressed data of the entry is fetched and buffered completely.
+    pub async fn by_index(&mut self, index: usize) -> Result<ZipEntryReader<Cursor<Bytes>>> {
+        let entry = entry_at(&self.entries, index)?;
+        let header = self
+            .client
+            .get_range(entry.local_header_offset as usize, LOCAL_HEADER_LEN)
+            .await?;
+        let offset = data_offset(header, entry)?;
+        let data = self
+            .client
+            .get_range(offset as usize, entry.compressed_size as usize)
+            .await?;
+        ZipEntryReader::new(entry, Cursor::new(Bytes::copy_from_slice(data)))
+    }
+
+    /// Reader of the entry with the given name, see [by_index](Self::by_index).
+    pub async fn by_name(&mut self, name: &str) -> Result<ZipEntryReader<Cursor<Bytes>>> {
+        let index = find_entry(&self.entries, name)?;
+        self.by_index(index).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_client::MockHttpRangeClient;
+    use ::zip::write::SimpleFileOptions;
+    use ::zip::{CompressionMethod, ZipWriter};
+    use std::io::Write;
+
+    fn test_data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i / 10 % 251) as u8).collect()
+    }
+
+    /// Archive with a stored, a deflated and a directory entry
+    fn archive() -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+        writer.start_file("stored.bin", stored).unwrap();
+        writer.write_all(&test_data(5000)).unwrap();
+        writer.add_directory("dir/", stored).unwrap();
+        let deflated = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+        writer.start_file("dir/deflated.bin", deflated).unwrap();
+        writer.write_all(&test_data(100_000)).unwrap();
+        writer.set_comment("test archive");
+        writer.finish().unwrap().into_inner()
+    }
+
+    /// Archive with a single stored entry, using ZIP64 records for all sizes and offsets
+    fn zip64_archive(name: &str, data: &[u8]) -> Vec<u8> {
+        let mut crc = flate2::Crc::new();
+        crc.update(data);
+        let mut file = Vec::new();
+        file.extend(LOCAL_HEADER_SIGNATURE.to_le_bytes());
+        file.extend([45, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        file.extend(crc.sum().to_le_bytes());
+        file.extend([0xff; 8]);
+        file.extend((name.len() as u16).to_le_bytes());
+        file.extend(20u16.to_le_bytes());
+        file.extend(name.as_bytes());
+        file.extend([1, 0, 16, 0]);
+        file.extend((data.len() as u64).to_le_bytes());
+        file.extend((data.len() as u64).to_le_bytes());
+        file.extend(data);
+
+        let cd_offset = file.len() as u64;
+        file.extend(CENTRAL_HEADER_SIGNATURE.to_le_bytes());
+        file.extend([45, 3, 45, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        file.extend(crc.sum().to_le_bytes());
+        file.extend([0xff; 8]);
+        file.extend((name.len() as u16).to_le_bytes());
+        file.extend(28u16.to_le_bytes());
+        file.extend([0; 10]);
+        file.extend([0xff; 4]);
+        file.extend(name.as_bytes());
+        file.extend([1, 0, 24, 0]);
+        file.extend((data.len() as u64).to_le_bytes());
+        file.extend((data.len() as u64).to_le_bytes());
+        file.extend(0u64.to_le_bytes());
+        let cd_size = file.len() as u64 - cd_offset;
+
+        let eocd64_offset = file.len() as u64;
+        file.extend(ZIP64_EOCD_SIGNATURE.to_le_bytes());
+        file.extend(44u64.to_le_bytes());
+        file.extend([45, 3, 45, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        file.extend(1u64.to_le_bytes());
+        file.extend(1u64.to_le_bytes());
+        file.extend(cd_size.to_le_bytes());
+        file.extend(cd_offset.to_le_bytes());
+        file.extend(ZIP64_LOCATOR_SIGNATURE.to_le_bytes());
+        file.extend(0u32.to_le_bytes());
+        file.extend(eocd64_offset.to_le_bytes());
+        file.extend(1u32.to_le_bytes());
+        file.extend(EOCD_SIGNATURE.to_le_bytes());
+        file.extend([0; 4]);
+        file.extend([0xff; 4]);
+        file.extend([0xff; 8]);
+        file.extend([0; 2]);
+        file
+    }
+
+    #[test]
+    fn sync_zip() -> Result<()> {
+        let backend = MockHttpRangeClient::new(archive());
+        let client = SyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        let mut archive = SyncZipArchive::open(client)?;
+        let names: Vec<_> = archive.entries().iter().map(|e| e.name.as_str()).collect();
+        assert_eq!(names, ["stored.bin", "dir/", "dir/deflated.bin"]);
+        assert!(archive.entries()[1].is_dir());
+        assert_eq!(archive.entries()[2].compression_method, METHOD_DEFLATED);
+
+        let mut data = Vec::new();
+        archive
+            .by_name("dir/deflated.bin")?
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, test_data(100_000));
+        data.clear();
+        archive.by_index(0)?.read_to_end(&mut data).unwrap();
+        assert_eq!(data, test_data(5000));
+        assert!(archive.by_name("missing.bin").is_err());
+        assert!(archive.by_index(3).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn corrupted_entry() {
+        let mut file = archive();
+        // Modify the first byte of the stored entry
+        let pos = LOCAL_HEADER_LEN + "stored.bin".len();
+        let header = &file[..LOCAL_HEADER_LEN];
+        let pos = pos + u16_le(header, 28) as usize;
+        file[pos] ^= 0xff;
+        let client = SyncBufferedHttpRangeClient::with(MockHttpRangeClient::new(file), "mock");
+        let mut archive = SyncZipArchive::open(client).unwrap();
+        let mut data = Vec::new();
+        let error = archive
+            .by_name("stored.bin")
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn async_zip() -> Result<()> {
+        let backend = MockHttpRangeClient::new(archive());
+        let client = AsyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        let mut archive = AsyncZipArchive::open(client).await?;
+        assert_eq!(archive.entries().len(), 3);
+        // Suffix covering the whole archive
+        assert_eq!(backend.requests().len(), 1);
+        let mut data = Vec::new();
+        archive
+            .by_name("dir/deflated.bin")
+            .await?
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, test_data(100_000));
+        assert!(archive.by_index(3).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn async_zip64() -> Result<()> {
+        let data = test_data(70_000);
+        let backend = MockHttpRangeClient::new(zip64_archive("large.bin", &data));
+        let mut client = AsyncBufferedHttpRangeClient::with(backend.clone(), "mock");
+        client.min_req_size(1);
+        let mut archive = AsyncZipArchive::open(client).await?;
+        let entry = &archive.entries()[0];
+        assert_eq!(entry.name, "large.bin");
+        assert_eq!(entry.compressed_size, data.len() as u64);
+        assert_eq!(entry.local_header_offset, 0);
+        let mut content = Vec::new();
+        archive
+            .by_index(0)
+            .await?
+            .read_to_end(&mut content)
+            .unwrap();
+        assert_eq!(content, data);
+        // Suffix including the central directory, local header and data
+        assert_eq!(backend.requests().len(), 3);
+        Ok(())
+    }
+}
@@ -0,0 +1,197 @@
+//! Seek table of the Zstandard seekable format.
+use crate::buffered_range_client::unexpected_end;
+use crate::compressed::{decode_error, Frame};
+use crate::error::{HttpError, Result};
+use std::convert::TryInto;
+use std::io::Read;
+
+/// Magic number of the skippable frame containing the seek table
+const SKIPPABLE_MAGIC: u32 = 0x184D_2A5E;
+/// Magic number at the end of the seek table
+const SEEKABLE_MAGIC: u32 = 0x8F92_EAB1;
+/// Length of the skippable frame header
+const FRAME_HEADER_LEN: usize = 8;
+/// Length of the seek table footer
+const FOOTER_LEN: usize = 9;
+
+fn u32_le(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().expect("4 byte slice"))
+}
+
+fn invalid_seek_table() -> HttpError {
+    HttpError::HttpError("no Zstandard seek table found".to_string())
+}
+
+/// Number of frames and entry size from the seek table footer
+fn parse_footer(footer: &[u8]) -> Result<(usize, usize)> {
+    if footer.len() < FOOTER_LEN {
+        return Err(unexpected_end(FOOTER_LEN));
+    }
+    if u32_le(footer, 5) != SEEKABLE_MAGIC {
+        return Err(invalid_seek_table());
+    }
+    let frames = u32_le(footer, 0) as usize;
+    // Entries contain a checksum if the highest descriptor bit is set
+    let entry_len = if footer[4] & 0x80 != 0 { 12 } else { 8 };
+    Ok((frames, entry_len))
+}
+
+/// Length of a seek table including its skippable frame header and footer
+fn table_len(frames: usize, entry_len: usize) -> Result<usize> {
+    frames
+        .checked_mul(entry_len)
+        .and_then(|len| len.checked_add(FRAME_HEADER_LEN + FOOTER_LEN))
+        .ok_or_else(invalid_seek_table)
+}
+
+/// Frames of a seek table including its skippable frame header and footer
+fn parse_seek_table(table: &[u8], frames: usize, entry_len: usize) -> Result<Vec<Frame>> {
+    let table_len = table_len(frames, entry_len)?;
+    if table.len() < table_len {
+        return Err(unexpected_end(table_len));
+    }
+    if u32_le(table, 0) != SKIPPABLE_MAGIC
+        || u32_le(table, 4) as usize != table_len - FRAME_HEADER_LEN
+    {
+        return Err(invalid_seek_table());
+    }
+    let (mut compressed_offset, mut uncompressed_offset) = (0, 0);
+    Ok(table[FRAME_HEADER_LEN..table_len - FOOTER_LEN]
+        .chunks_exact(entry_len)
+        .map(|entry| {
+            let frame = Frame {
+                compressed_offset,
+                compressed_len: u64::from(u32_le(entry, 0)),
+                uncompressed_offset,
+                uncompressed_len: u64::from(u32_le(entry, 4)),
+            };
+            compressed_offset += frame.compressed_len;
+            uncompressed_offset += frame.uncompressed_len;
+            frame
+        })
+        .collect())
+}
+
+/// Decompress a single Zstandard frame, stopping after `max_len + 1` bytes.
+pub(crate) fn decode_frame(data: &[u8], max_len: u64) -> Result<Vec<u8>> {
+    let mut decoded = Vec::new();
+    ruzstd::decoding::StreamingDecoder::new(data)
+        .map_err(decode_error)?
+        .take(max_len.saturating_add(1))
+        .read_to_end(&mut decoded)
+        .map_err(decode_error)?;
+    Ok(decoded)
+}
+
+pub(crate) mod sync {
+    use super::*;
+    use crate::range_client::SyncHttpRangeClient;
+    use crate::SyncBufferedHttpRangeClient;
+
+    /// Read the seek table at the end of the file.
+    pub(crate) fn seek_table<T: SyncHttpRangeClient>(
+        client: &mut SyncBufferedHttpRangeClient<T>,
+    ) -> Result<Vec<Frame>> {
+        let (frames, entry_len) = parse_footer(client.get_suffix(FOOTER_LEN)?)?;
+        let table_len = table_len(frames, entry_len)?;
+        parse_seek_table(client.get_suffix(table_len)?, frames, entry_len)
+    }
+}
+
+pub(crate) mod nonblocking {
+    use super::*;
+    use crate::range_client::AsyncHttpRangeClient;
+    use crate::AsyncBufferedHttpRangeClient;
+
+    /// Read the seek table at the end of the file.
+    pub(crate) async fn seek_table<T: AsyncHttpRangeClient>(
+        client: &mut AsyncBufferedHttpRangeClient<T>,
+    ) -> Result<Vec<Frame>> {
+        let (frames, entry_len) = parse_footer(client.get_suffix(FOOTER_LEN).await?)?;
+        let table_len = table_len(frames, entry_len)?;
+        parse_seek_table(client.get_suffix(table_len).await?, frames, entry_len)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mock_client::MockHttpRangeClient;
+    use crate::{
+        AsyncBufferedHttpRangeClient, AsyncCompressedReader, SyncBufferedHttpRangeClient,
+        SyncCompressedReader,
+    };
+    use ruzstd::encoding::{compress_to_vec, CompressionLevel};
+    use std::io::{Seek, SeekFrom};
+
+    /// Seekable Zstandard file with frames of 4096 bytes and checksums in the seek table
+    fn seekable(data: &[u8]) -> Vec<u8> {
+        let mut file = Vec::new();
+        let mut entries = Vec::new();
+        for chunk in data.chunks(4096) {
+            let frame = compress_to_vec(chunk, CompressionLevel::Fastest);
+            entries.push((frame.len() as u32, chunk.len() as u32));
+            file.extend(frame);
+        }
+        let table_len = entries.len() * 12 + FOOTER_LEN;
+        file.extend(SKIPPABLE_MAGIC.to_le_bytes());
+        file.extend((table_len as u32).to_le_bytes());
+        for (compressed, uncompressed) in &entries {
+            file.extend(compressed.to_le_bytes());
+            file.extend(uncompressed.to_le_bytes());
+            file.extend([0; 4]);
+        }
+        file.extend((entries.len() as u32).to_le_bytes());
+        file.push(0x80);
+        file.extend(SEEKABLE_MAGIC.to_le_bytes());
+        file
+    }
+
+    fn test_data() -> Vec<u8> {
+        (0..10_000u32).flat_map(|i| (i / 3).to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn sync_zstd_seekable() -> std::io::Result<()> {
+        let data = test_data();
+        let backend = MockHttpRangeClient::new(seekable(&data));
+        let client = SyncBufferedHttpRangeClient::with(backend, "mock");
+        let mut reader = SyncCompressedReader::zstd_seekable(client).unwrap();
+        assert_eq!(reader.frames().len(), 10);
+        assert_eq!(reader.uncompressed_len(), 40_000);
+        reader.seek(SeekFrom::Start(12_000))?;
+        let mut buf = vec![0; 5000];
+        reader.read_exact(&mut buf)?;
+        assert_eq!(buf, data[12_000..17_000]);
+        Ok(())
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(table_len(usize::MAX, 12).is_err());
+        let frame = compress_to_vec(&[0; 100_000][..], CompressionLevel::Fastest);
+        assert_eq!(decode_frame(&frame, 100).unwrap().len(), 101);
+    }
+
+    #[tokio::test]
+    async fn async_zstd_seekable() -> Result<()> {
+        let data = test_data();
+        let backend = MockHttpRangeClient::new(seekable(&data));
+        let client = AsyncBufferedHttpRangeClient::with(backend, "mock");
+        let mut reader = AsyncCompressedReader::zstd_seekable(client).await?;
+        assert_eq!(reader.get_range(39_000, 2000).await?, data[39_000..]);
+
+        // Seek table with a wrong uncompressed frame size
+        let mut file = seekable(&data);
+        let entry = file.len() - FOOTER_LEN - 10 * 12;
+        file[entry + 4..entry + 8].copy_from_slice(&4000u32.to_le_bytes());
+        let client = AsyncBufferedHttpRangeClient::with(MockHttpRangeClient::new(file), "mock");
+        let mut reader = AsyncCompressedReader::zstd_seekable(client).await?;
+        assert!(reader.get_range(0, 10).await.is_err());
+
+        let backend = MockHttpRangeClient::new(data);
+        let client = AsyncBufferedHttpRangeClient::with(backend, "mock");
+        assert!(AsyncCompressedReader::zstd_seekable(client).await.is_err());
+        Ok(())
+    }
+}